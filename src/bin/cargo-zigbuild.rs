@@ -3,7 +3,11 @@ use std::ffi::OsString;
 use std::path::PathBuf;
 use std::process::Command;
 
-use cargo_zigbuild::{Build, Check, Clippy, Doc, Install, Run, Rustc, Test, Zig};
+use cargo_zigbuild::windows::windres;
+use cargo_zigbuild::{
+    Build, Check, Clippy, Doc, Doctor, Gendef, Install, PrintEnv, Run, Rustc, Tbd, Test,
+    Xcframework, Zig,
+};
 use clap::Parser;
 
 #[allow(clippy::large_enum_variant)]
@@ -23,14 +27,24 @@ pub enum Opt {
     Check(Check),
     #[command(name = "doc")]
     Doc(Doc),
+    #[command(name = "doctor")]
+    Doctor(Doctor),
+    #[command(name = "gendef")]
+    Gendef(Gendef),
     #[command(name = "install")]
     Install(Install),
+    #[command(name = "print-env")]
+    PrintEnv(PrintEnv),
     #[command(name = "rustc")]
     Rustc(Rustc),
     #[command(name = "run", alias = "r")]
     Run(Run),
+    #[command(name = "tbd")]
+    Tbd(Tbd),
     #[command(name = "test", alias = "t")]
     Test(Test),
+    #[command(name = "xcframework")]
+    Xcframework(Xcframework),
     #[command(subcommand)]
     Zig(Zig),
     #[command(external_subcommand)]
@@ -56,8 +70,29 @@ fn main() -> anyhow::Result<()> {
             args: args.collect(),
         };
         zig.execute()?;
+    } else if program_name.to_string_lossy().ends_with("windres") {
+        // A prefixed windres like `x86_64-w64-mingw32-windres` targets its architecture
+        let target = windres::program_target(&program_name.to_string_lossy());
+        let zig = Zig::Windres {
+            args: target.into_iter().chain(args).collect(),
+        };
+        zig.execute()?;
+    } else if program_name.eq_ignore_ascii_case("rc") {
+        let zig = Zig::Rc {
+            args: args.collect(),
+        };
+        zig.execute()?;
     } else if program_name.eq_ignore_ascii_case("install_name_tool") {
         cargo_zigbuild::macos::install_name_tool::execute(args)?;
+    } else if program_name.eq_ignore_ascii_case("otool") {
+        cargo_zigbuild::macos::otool::execute(args)?;
+    } else if program_name.eq_ignore_ascii_case("lipo") {
+        #[cfg(feature = "universal2")]
+        cargo_zigbuild::macos::lipo::execute(args)?;
+        #[cfg(not(feature = "universal2"))]
+        anyhow::bail!("lipo requires cargo-zigbuild to be built with the `universal2` feature");
+    } else if program_name.eq_ignore_ascii_case("codesign") {
+        cargo_zigbuild::macos::codesign::execute(args)?;
     } else {
         let opt = Opt::parse();
         match opt {
@@ -77,11 +112,13 @@ fn main() -> anyhow::Result<()> {
                 doc.enable_zig_ar = true;
                 doc.execute()?
             }
+            Opt::Doctor(doctor) => doctor.execute()?,
+            Opt::Gendef(gendef) => gendef.execute()?,
             Opt::Install(mut install) => {
                 install.enable_zig_ar = true;
                 install.execute()?
             }
-
+            Opt::PrintEnv(print_env) => print_env.execute()?,
             Opt::Rustc(mut rustc) => {
                 rustc.enable_zig_ar = true;
                 rustc.execute()?
@@ -90,10 +127,15 @@ fn main() -> anyhow::Result<()> {
                 run.enable_zig_ar = true;
                 run.execute()?
             }
+            Opt::Tbd(tbd) => tbd.execute()?,
             Opt::Test(mut test) => {
                 test.enable_zig_ar = true;
                 test.execute()?
             }
+            Opt::Xcframework(mut xcframework) => {
+                xcframework.enable_zig_ar = true;
+                xcframework.execute()?
+            }
             Opt::Zig(zig) => zig.execute()?,
             Opt::External(args) => {
                 let mut child = Command::new(env::var_os("CARGO").unwrap_or("cargo".into()))
@@ -1,13 +1,20 @@
+use std::collections::HashSet;
+use std::io::{BufRead, BufReader, IsTerminal};
 use std::ops::{Deref, DerefMut};
 use std::path::PathBuf;
-#[cfg(feature = "universal2")]
-use std::process::Stdio;
-use std::process::{self, Child, Command};
+use std::process::{self, Child, Command, Stdio};
 
 use anyhow::{Context, Result};
+use cargo_metadata::{Artifact, Message, PackageId};
 use clap::Parser;
+use target_lexicon::{OperatingSystem, Triple};
 
-use crate::zig::Zig;
+use crate::config::ZigbuildConfig;
+use crate::fat::FatTarget;
+use crate::linux::glibc_audit::audit_glibc_artifact;
+use crate::target_version::{TargetVersion, split_target_version};
+use crate::windows::pe_security::report_pe_artifact;
+use crate::zig::{Zig, is_glibc_env};
 
 /// Compile a local package and all of its dependencies
 /// using zig as the linker
@@ -20,6 +27,11 @@ pub struct Build {
     #[command(flatten)]
     pub cargo: cargo_options::Build,
 
+    /// Build a fat binary from a comma separated list of Apple targets,
+    /// like `x86_64-apple-ios,aarch64-apple-ios-sim`
+    #[arg(long, value_name = "TRIPLE,TRIPLE...", help_heading = cargo_options::heading::COMPILATION_OPTIONS)]
+    pub fat: Vec<String>,
+
     /// Disable zig linker
     #[arg(skip)]
     pub disable_zig_linker: bool,
@@ -40,166 +52,317 @@ impl Build {
 
     /// Execute `cargo build` command with zig as the linker
     pub fn execute(&self) -> Result<()> {
-        let has_universal2 = self
-            .cargo
-            .target
-            .contains(&"universal2-apple-darwin".to_string());
-        let mut build = self.build_command()?;
-        let mut child = build.spawn().context("Failed to run cargo build")?;
-        if has_universal2 {
-            self.handle_universal2_build(child)?;
-        } else {
+        let config = ZigbuildConfig::load()?;
+        let fat_targets = self.fat_targets(&config)?;
+        let glibc_targets = self.glibc_audit_targets(&config)?;
+        let pe_targets = self.pe_report_targets(&config)?;
+        if fat_targets.is_empty() && glibc_targets.is_empty() && pe_targets.is_empty() {
+            let mut build = self.cargo_command(&config, &fat_targets)?;
+            let mut child = build.spawn().context("Failed to run cargo build")?;
             let status = child.wait().expect("Failed to wait on cargo build process");
             if !status.success() {
                 process::exit(status.code().unwrap_or(1));
             }
+            return Ok(());
+        }
+
+        let mut build = if fat_targets.is_empty() {
+            self.json_build_command(self.cargo.clone(), &config)?
+        } else {
+            self.cargo_command(&config, &fat_targets)?
+        };
+        let member_ids = self.workspace_member_ids()?;
+        let child = build.spawn().context("Failed to run cargo build")?;
+        let json_output = is_json_message_format(&self.cargo.message_format);
+        let (artifacts, build_finished) = collect_artifacts(child, &member_ids, json_output)?;
+        let fat_artifacts = self.handle_fat_build(&fat_targets, &artifacts)?;
+        for artifact in &artifacts {
+            for filename in &artifact.filenames {
+                let floor = glibc_targets.iter().find_map(|(target, floor)| {
+                    filename
+                        .components()
+                        .any(|c| c.as_str() == target)
+                        .then_some(*floor)
+                });
+                if let Some(floor) = floor
+                    && let Some(required) = audit_glibc_artifact(filename.as_std_path(), floor)?
+                {
+                    eprintln!("cargo-zigbuild: `{filename}` requires glibc {required}");
+                }
+                if pe_targets
+                    .iter()
+                    .any(|target| filename.components().any(|c| c.as_str() == target))
+                {
+                    report_pe_artifact(filename.as_std_path())?;
+                }
+            }
+        }
+        if json_output {
+            // Report the merged fat artifacts before cargo's `build-finished` message
+            for artifact in fat_artifacts {
+                println!(
+                    "{}",
+                    serde_json::to_string(&Message::CompilerArtifact(artifact))?
+                );
+            }
+            if let Some(build_finished) = build_finished {
+                println!("{build_finished}");
+            }
         }
         Ok(())
     }
 
-    #[cfg(not(feature = "universal2"))]
-    fn handle_universal2_build(&self, mut _child: Child) -> Result<()> {
-        anyhow::bail!("Unsupported Rust target: universal2-apple-darwin")
+    /// Linux glibc targets with a requested glibc version whose artifacts should be audited,
+    /// unless `glibc-audit` is disabled
+    fn glibc_audit_targets(&self, config: &ZigbuildConfig) -> Result<Vec<(String, TargetVersion)>> {
+        if self.disable_zig_linker || config.glibc_audit == Some(false) {
+            return Ok(Vec::new());
+        }
+        let mut targets = Vec::new();
+        for target in &self.raw_targets()? {
+            let (rust_target, version) = split_target_version(target)?;
+            let Some(version) = version else {
+                continue;
+            };
+            let Ok(triple) = rust_target.parse::<Triple>() else {
+                continue;
+            };
+            if triple.operating_system == OperatingSystem::Linux && is_glibc_env(triple.environment)
+            {
+                targets.push((rust_target.to_string(), version));
+            }
+        }
+        Ok(targets)
     }
 
-    #[cfg(feature = "universal2")]
-    fn handle_universal2_build(&self, mut child: Child) -> Result<()> {
-        use cargo_metadata::Message;
-        use std::io::BufReader;
-        use std::path::Path;
-
-        // Find workspace member package ids
-        let manifest_path = self
-            .manifest_path
-            .as_deref()
-            .unwrap_or_else(|| Path::new("Cargo.toml"));
+    /// windows-gnu targets whose artifacts get their PE security characteristics reported
+    /// when `pe-report` is enabled, see `pe_security`
+    fn pe_report_targets(&self, config: &ZigbuildConfig) -> Result<Vec<String>> {
+        if self.disable_zig_linker || config.pe_report != Some(true) {
+            return Ok(Vec::new());
+        }
+        let mut targets = Vec::new();
+        for target in &self.raw_targets()? {
+            let (rust_target, _) = split_target_version(target)?;
+            if rust_target.contains("windows-gnu") {
+                targets.push(rust_target.to_string());
+            }
+        }
+        Ok(targets)
+    }
+
+    /// Targets from CLI args, or cargo config's `build.target`
+    fn raw_targets(&self) -> Result<Vec<String>> {
+        if !self.cargo.target.is_empty() {
+            return Ok(self.cargo.target.clone());
+        }
+        Ok(cargo_config2::Config::load()?
+            .build
+            .target
+            .unwrap_or_default()
+            .iter()
+            .map(|t| t.triple().to_string())
+            .collect())
+    }
+
+    /// Package ids of the workspace members, whose artifacts are collected from the build
+    fn workspace_member_ids(&self) -> Result<HashSet<PackageId>> {
         let mut metadata_cmd = cargo_metadata::MetadataCommand::new();
-        metadata_cmd.manifest_path(manifest_path);
+        if let Some(manifest_path) = &self.manifest_path {
+            metadata_cmd.manifest_path(manifest_path);
+        }
         let metadata = metadata_cmd.exec()?;
-        let member_ids: std::collections::HashSet<_> = metadata.workspace_members.iter().collect();
-
-        let mut x86_64_artifacts = Vec::new();
-        let mut aarch64_artifacts = Vec::new();
-
-        let stream = child
-            .stdout
-            .take()
-            .expect("Cargo build should have a stdout");
-        for message in Message::parse_stream(BufReader::new(stream)) {
-            let message = message.context("Failed to parse cargo metadata message")?;
-            match message {
-                Message::CompilerArtifact(artifact) => {
-                    if member_ids.contains(&artifact.package_id) {
-                        for filename in artifact.filenames {
-                            if filename.as_str().contains("x86_64-apple-darwin") {
-                                x86_64_artifacts.push(filename);
-                            } else if filename.as_str().contains("aarch64-apple-darwin") {
-                                aarch64_artifacts.push(filename);
-                            }
-                        }
-                    }
-                }
-                Message::CompilerMessage(msg) => {
-                    println!("{}", msg.message);
-                }
-                _ => {}
+        Ok(metadata.workspace_members.into_iter().collect())
+    }
+
+    /// Build with JSON messages and collect the artifacts of workspace members,
+    /// printing the rendered diagnostics
+    pub(crate) fn build_artifacts(&self) -> Result<Vec<Artifact>> {
+        let config = ZigbuildConfig::load()?;
+        let mut build = self.json_build_command(self.cargo.clone(), &config)?;
+        let member_ids = self.workspace_member_ids()?;
+        let child = build.spawn().context("Failed to run cargo build")?;
+        let (artifacts, _) = collect_artifacts(child, &member_ids, false)?;
+        Ok(artifacts)
+    }
+
+    /// Fat targets from `--target` and `--fat`
+    fn fat_targets(&self, config: &ZigbuildConfig) -> Result<Vec<FatTarget>> {
+        let mut fat_targets = Vec::new();
+        for target in &self.cargo.target {
+            if let Some(fat) = FatTarget::resolve(target, config) {
+                fat.validate()?;
+                fat_targets.push(fat);
             }
         }
-        let status = child.wait().expect("Failed to wait on cargo build process");
-        if !status.success() {
-            process::exit(status.code().unwrap_or(1));
-        }
-        // create fat binaries for artifacts
-        for (x86_64_path, aarch64_path) in x86_64_artifacts
-            .into_iter()
-            .zip(aarch64_artifacts.into_iter())
-        {
-            let mut fat = fat_macho::FatWriter::new();
-            match fat.add(fs_err::read(&x86_64_path)?) {
-                Err(fat_macho::Error::InvalidMachO(_)) => continue,
-                Err(e) => return Err(e)?,
-                Ok(()) => {}
-            }
-            match fat.add(fs_err::read(&aarch64_path)?) {
-                Err(fat_macho::Error::InvalidMachO(_)) => continue,
-                Err(e) => return Err(e)?,
-                Ok(()) => {}
-            }
-            let universal2_path = PathBuf::from(
-                x86_64_path
-                    .to_string()
-                    .replace("x86_64-apple-darwin", "universal2-apple-darwin"),
-            );
-            let universal2_dir = universal2_path.parent().unwrap();
-            fs_err::create_dir_all(universal2_dir)?;
-            fat.write_to_file(universal2_path)?;
+        for list in &self.fat {
+            fat_targets.push(FatTarget::from_list(list)?);
         }
-        Ok(())
+        #[cfg(not(feature = "universal2"))]
+        if let Some(fat) = fat_targets.first() {
+            anyhow::bail!("Unsupported Rust target: {}", fat.name)
+        }
+        Ok(fat_targets)
+    }
+
+    /// Merge the Mach-O artifacts of the constituent targets into fat binaries,
+    /// returns the artifacts describing the merged files
+    #[cfg(feature = "universal2")]
+    fn handle_fat_build(
+        &self,
+        fat_targets: &[FatTarget],
+        artifacts: &[Artifact],
+    ) -> Result<Vec<Artifact>> {
+        let mut fat_artifacts = Vec::new();
+        for fat_target in fat_targets {
+            fat_artifacts.extend(fat_target.write_fat_artifacts(artifacts)?);
+        }
+        Ok(fat_artifacts)
     }
 
-    /// Generate cargo subcommand
     #[cfg(not(feature = "universal2"))]
-    pub fn build_command(&self) -> Result<Command> {
-        let mut build = self.cargo.command();
+    fn handle_fat_build(
+        &self,
+        _fat_targets: &[FatTarget],
+        _artifacts: &[Artifact],
+    ) -> Result<Vec<Artifact>> {
+        Ok(Vec::new())
+    }
+
+    /// Generate cargo subcommand emitting JSON messages on a piped stdout
+    fn json_build_command(
+        &self,
+        mut cargo: cargo_options::Build,
+        config: &ZigbuildConfig,
+    ) -> Result<Command> {
+        if !is_json_message_format(&cargo.message_format) {
+            // Keep cargo's rendering of diagnostics, which are printed from the JSON messages
+            let short = cargo
+                .message_format
+                .iter()
+                .flat_map(|f| f.split(','))
+                .any(|f| f.trim() == "short");
+            let ansi = match cargo.color.as_deref() {
+                Some("always") => true,
+                Some("never") => false,
+                _ => std::io::stderr().is_terminal(),
+            };
+            cargo.message_format = match (short, ansi) {
+                (true, true) => vec!["json-diagnostic-short,json-diagnostic-rendered-ansi".into()],
+                (true, false) => vec!["json-diagnostic-short".into()],
+                (false, true) => vec!["json-diagnostic-rendered-ansi".into()],
+                (false, false) => vec!["json".into()],
+            };
+        }
+        let mut build = cargo.command();
+        build.stdout(Stdio::piped()).stderr(Stdio::inherit());
         if !self.disable_zig_linker {
-            Zig::apply_command_env(
+            Zig::apply_command_env_with_config(
                 self.manifest_path.as_deref(),
                 self.release,
-                &self.cargo.common,
+                &cargo.common,
                 &mut build,
                 self.enable_zig_ar,
+                config,
             )?;
         }
         Ok(build)
     }
 
     /// Generate cargo subcommand
-    #[cfg(feature = "universal2")]
     pub fn build_command(&self) -> Result<Command> {
-        let build = if let Some(index) = self
-            .cargo
-            .target
-            .iter()
-            .position(|t| t == "universal2-apple-darwin")
-        {
-            let mut cargo = self.cargo.clone();
-            cargo.target.remove(index);
-            if !cargo.target.contains(&"x86_64-apple-darwin".to_string()) {
-                cargo.target.push("x86_64-apple-darwin".to_string());
-            }
-            if !cargo.target.contains(&"aarch64-apple-darwin".to_string()) {
-                cargo.target.push("aarch64-apple-darwin".to_string());
-            }
-            if !cargo.message_format.iter().any(|f| f.starts_with("json")) {
-                cargo.message_format.push("json".to_string());
-            }
-            let mut build = cargo.command();
-            build.stdout(Stdio::piped()).stderr(Stdio::inherit());
-            if !self.disable_zig_linker {
-                Zig::apply_command_env(
-                    self.manifest_path.as_deref(),
-                    self.release,
-                    &cargo.common,
-                    &mut build,
-                    self.enable_zig_ar,
-                )?;
-            }
-            build
-        } else {
+        let config = ZigbuildConfig::load()?;
+        let fat_targets = self.fat_targets(&config)?;
+        self.cargo_command(&config, &fat_targets)
+    }
+
+    /// Generate cargo subcommand, fat targets are replaced by their constituent targets
+    /// built with JSON messages
+    fn cargo_command(&self, config: &ZigbuildConfig, fat_targets: &[FatTarget]) -> Result<Command> {
+        if fat_targets.is_empty() {
             let mut build = self.cargo.command();
             if !self.disable_zig_linker {
-                Zig::apply_command_env(
+                Zig::apply_command_env_with_config(
                     self.manifest_path.as_deref(),
                     self.release,
                     &self.cargo.common,
                     &mut build,
                     self.enable_zig_ar,
+                    config,
                 )?;
             }
-            build
-        };
-        Ok(build)
+            return Ok(build);
+        }
+        let mut cargo = self.cargo.clone();
+        cargo
+            .target
+            .retain(|target| !fat_targets.iter().any(|fat| &fat.name == target));
+        for target in fat_targets.iter().flat_map(|fat| &fat.targets) {
+            if !cargo.target.contains(target) {
+                cargo.target.push(target.clone());
+            }
+        }
+        self.json_build_command(cargo, config)
+    }
+}
+
+/// Whether the user asked for cargo's JSON messages
+fn is_json_message_format(message_format: &[String]) -> bool {
+    message_format
+        .iter()
+        .flat_map(|f| f.split(','))
+        .any(|f| f.trim().starts_with("json"))
+}
+
+/// Collect the artifacts of workspace members from the cargo JSON message stream,
+/// exits if cargo fails
+///
+/// With `json_output` the stream is passed through except for the `build-finished`
+/// message, which is returned, otherwise the rendered diagnostics are printed.
+fn collect_artifacts(
+    mut child: Child,
+    member_ids: &HashSet<PackageId>,
+    json_output: bool,
+) -> Result<(Vec<Artifact>, Option<String>)> {
+    let mut artifacts = Vec::new();
+    let mut build_finished = None;
+    let stream = child
+        .stdout
+        .take()
+        .expect("Cargo build should have a stdout");
+    for line in BufReader::new(stream).lines() {
+        let line = line.context("Failed to read cargo build output")?;
+        let message = serde_json::from_str(&line).unwrap_or(Message::TextLine(line.clone()));
+        if matches!(message, Message::BuildFinished(_)) {
+            build_finished = Some(line);
+            continue;
+        }
+        if json_output {
+            println!("{line}");
+        }
+        match message {
+            Message::CompilerArtifact(artifact) if member_ids.contains(&artifact.package_id) => {
+                artifacts.push(artifact);
+            }
+            Message::CompilerMessage(msg) if !json_output => {
+                if let Some(rendered) = &msg.message.rendered {
+                    eprint!("{rendered}");
+                }
+            }
+            Message::TextLine(line) if !json_output => {
+                println!("{line}");
+            }
+            _ => {}
+        }
+    }
+    let status = child.wait().expect("Failed to wait on cargo build process");
+    if !status.success() {
+        if json_output && let Some(build_finished) = build_finished {
+            println!("{build_finished}");
+        }
+        process::exit(status.code().unwrap_or(1));
     }
+    Ok((artifacts, build_finished))
 }
 
 impl Deref for Build {
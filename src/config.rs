@@ -0,0 +1,269 @@
+//! `[zigbuild]` configuration
+//!
+//! cargo-zigbuild reads its own settings from the `[zigbuild]` table of
+//! cargo configuration files (`.cargo/config.toml`) and from a project level
+//! `zigbuild.toml` file, whose top-level keys are the same as the `[zigbuild]` table.
+//!
+//! ```toml
+//! [[zigbuild.linker-arg-rules]]
+//! exact = "-Wl,--some-new-flag"
+//! cfg = "not(windows_gnu)"
+//! zig = "<0.16"
+//! action = "skip"
+//! ```
+
+use std::collections::BTreeMap;
+use std::env;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use fs_err as fs;
+use serde::{Deserialize, Serialize};
+
+use crate::linker_rules::LinkerArgRuleConfig;
+
+/// Environment variable used to pass the resolved configuration down to the
+/// `zig cc` wrappers, which may run in a different working directory.
+pub(crate) const RESOLVED_CONFIG_ENV: &str = "CARGO_ZIGBUILD_RESOLVED_CONFIG";
+
+/// Name of the project level configuration file
+const PROJECT_CONFIG_FILE: &str = "zigbuild.toml";
+
+/// cargo-zigbuild configuration
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ZigbuildConfig {
+    /// Extra linker argument rewrite rules, evaluated before the built-in rules
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub linker_arg_rules: Vec<LinkerArgRuleConfig>,
+    /// Check the glibc symbol versions of the built artifacts against the
+    /// requested glibc version, enabled by default
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub glibc_audit: Option<bool>,
+    /// User-defined fat targets, mapping a target name to its constituent targets
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub fat_targets: BTreeMap<String, Vec<String>>,
+    /// Link Apple targets with `-headerpad_max_install_names`, so that their
+    /// install names and rpaths can be rewritten later
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub headerpad_max_install_names: Option<bool>,
+    /// Directory of `.tbd` stubs for the system libraries and frameworks linked by
+    /// macOS targets, searched in addition to the SDK
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub apple_sdk_stubs: Option<PathBuf>,
+    /// SDKs of Apple platforms other than macOS, keyed by their `xcrun` name like
+    /// `iphoneos` or `iphonesimulator`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub apple_sdk_roots: BTreeMap<String, PathBuf>,
+    /// Extra directories to look for macOS SDKs in when `SDKROOT` isn't set,
+    /// searched before the conventional locations
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub macos_sdk_search_paths: Vec<PathBuf>,
+    /// Version of the macOS SDK to use, like `14` or `11.3`, the newest SDK found by default
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub macos_sdk_version: Option<String>,
+    /// Windows SDK and MSVC CRT splatted by `xwin`, used to link `*-windows-msvc` targets
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub xwin_dir: Option<PathBuf>,
+    /// Directories of `.def` files to generate the import libraries zig's mingw doesn't
+    /// ship from, searched before the bundled ones
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub def_dirs: Vec<PathBuf>,
+    /// Report the PE security characteristics of the built windows-gnu executables and DLLs
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pe_report: Option<bool>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct CargoConfigFile {
+    #[serde(default)]
+    zigbuild: Option<ZigbuildConfig>,
+}
+
+impl ZigbuildConfig {
+    /// Load the configuration passed down by the parent cargo-zigbuild process,
+    /// or discover it from the current directory.
+    pub fn load() -> Result<Self> {
+        if let Ok(resolved) = env::var(RESOLVED_CONFIG_ENV) {
+            return serde_json::from_str(&resolved)
+                .with_context(|| format!("Failed to parse `{RESOLVED_CONFIG_ENV}`"));
+        }
+        Self::discover(&env::current_dir()?)
+    }
+
+    /// Discover the configuration from `zigbuild.toml` and cargo configuration files,
+    /// searching `cwd` and its ancestors, then `$CARGO_HOME`.
+    ///
+    /// Files closer to `cwd` take precedence, `zigbuild.toml` takes precedence
+    /// over cargo configuration files.
+    pub fn discover(cwd: &Path) -> Result<Self> {
+        let cargo_home = env::var_os("CARGO_HOME")
+            .map(PathBuf::from)
+            .or_else(|| dirs::home_dir().map(|home| home.join(".cargo")));
+        Self::discover_with_cargo_home(cwd, cargo_home)
+    }
+
+    /// Like `discover`, with the cargo home directory given explicitly
+    fn discover_with_cargo_home(cwd: &Path, cargo_home: Option<PathBuf>) -> Result<Self> {
+        let mut config = Self::default();
+        if let Some(project_file) = cwd
+            .ancestors()
+            .map(|dir| dir.join(PROJECT_CONFIG_FILE))
+            .find(|path| path.is_file())
+        {
+            let content = fs::read_to_string(&project_file)?;
+            let mut project: ZigbuildConfig = toml::from_str(&content)
+                .with_context(|| format!("Failed to parse `{}`", project_file.display()))?;
+            project.resolve_paths(project_file.parent().unwrap());
+            config.merge(project);
+        }
+        for config_file in cargo_config_files(cwd, cargo_home) {
+            let content = fs::read_to_string(&config_file)?;
+            let cargo_config: CargoConfigFile = toml::from_str(&content)
+                .with_context(|| format!("Failed to parse `{}`", config_file.display()))?;
+            if let Some(mut zigbuild) = cargo_config.zigbuild {
+                // Like cargo, paths are relative to the directory containing `.cargo`
+                let cargo_dir = config_file.parent().unwrap();
+                zigbuild.resolve_paths(cargo_dir.parent().unwrap_or(cargo_dir));
+                config.merge(zigbuild);
+            }
+        }
+        Ok(config)
+    }
+
+    /// Make relative paths absolute, the configuration is also used by
+    /// the `zig cc` wrappers, which may run in a different working directory
+    fn resolve_paths(&mut self, base: &Path) {
+        if let Some(stubs) = &mut self.apple_sdk_stubs {
+            *stubs = base.join(&*stubs);
+        }
+        for path in self.apple_sdk_roots.values_mut() {
+            *path = base.join(&*path);
+        }
+        for path in &mut self.macos_sdk_search_paths {
+            *path = base.join(&*path);
+        }
+        if let Some(xwin_dir) = &mut self.xwin_dir {
+            *xwin_dir = base.join(&*xwin_dir);
+        }
+        for path in &mut self.def_dirs {
+            *path = base.join(&*path);
+        }
+    }
+
+    /// Merge a lower precedence configuration into this one
+    fn merge(&mut self, low: Self) {
+        self.linker_arg_rules.extend(low.linker_arg_rules);
+        self.glibc_audit = self.glibc_audit.or(low.glibc_audit);
+        self.headerpad_max_install_names = self
+            .headerpad_max_install_names
+            .or(low.headerpad_max_install_names);
+        self.apple_sdk_stubs = self.apple_sdk_stubs.take().or(low.apple_sdk_stubs);
+        self.macos_sdk_search_paths
+            .extend(low.macos_sdk_search_paths);
+        self.macos_sdk_version = self.macos_sdk_version.take().or(low.macos_sdk_version);
+        self.xwin_dir = self.xwin_dir.take().or(low.xwin_dir);
+        self.def_dirs.extend(low.def_dirs);
+        self.pe_report = self.pe_report.or(low.pe_report);
+        for (sdk_name, path) in low.apple_sdk_roots {
+            self.apple_sdk_roots.entry(sdk_name).or_insert(path);
+        }
+        for (name, targets) in low.fat_targets {
+            self.fat_targets.entry(name).or_insert(targets);
+        }
+    }
+}
+
+/// Cargo configuration files in precedence order, see
+/// https://doc.rust-lang.org/cargo/reference/config.html#hierarchical-structure
+fn cargo_config_files(cwd: &Path, cargo_home: Option<PathBuf>) -> Vec<PathBuf> {
+    let mut dirs: Vec<PathBuf> = cwd.ancestors().map(|dir| dir.join(".cargo")).collect();
+    if let Some(cargo_home) = cargo_home
+        && !dirs.contains(&cargo_home)
+    {
+        dirs.push(cargo_home);
+    }
+    dirs.into_iter()
+        .filter_map(|dir| {
+            // `config.toml` takes precedence over the legacy `config` file
+            [dir.join("config.toml"), dir.join("config")]
+                .into_iter()
+                .find(|path| path.is_file())
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_discover_merges_project_and_cargo_config() {
+        let tmp = tempfile::tempdir().unwrap();
+        let project = tmp.path().join("project");
+        fs::create_dir_all(project.join(".cargo")).unwrap();
+        fs::write(
+            tmp.path().join(PROJECT_CONFIG_FILE),
+            r#"
+[[linker-arg-rules]]
+exact = "-lfoo"
+action = "skip"
+"#,
+        )
+        .unwrap();
+        fs::write(
+            project.join(".cargo").join("config.toml"),
+            r#"
+[build]
+jobs = 1
+
+[zigbuild]
+glibc-audit = false
+headerpad-max-install-names = true
+apple-sdk-stubs = "stubs"
+macos-sdk-search-paths = ["/opt/sdks", "sdks"]
+macos-sdk-version = "14"
+xwin-dir = "/opt/xwin"
+def-dirs = ["defs"]
+pe-report = true
+
+[zigbuild.apple-sdk-roots]
+iphoneos = "sdks/iPhoneOS.sdk"
+
+[zigbuild.fat-targets]
+universal-apple-tvos = ["x86_64-apple-tvos", "aarch64-apple-tvos"]
+
+[[zigbuild.linker-arg-rules]]
+prefix = "-Wl,--bar"
+replace = ["-Wl,--baz"]
+"#,
+        )
+        .unwrap();
+
+        let cargo_home = tmp.path().join("cargo-home");
+        let config = ZigbuildConfig::discover_with_cargo_home(&project, Some(cargo_home)).unwrap();
+        let rules = &config.linker_arg_rules;
+        assert_eq!(rules.len(), 2);
+        assert_eq!(rules[0].exact[..], ["-lfoo"]);
+        assert_eq!(rules[1].prefix[..], ["-Wl,--bar"]);
+        assert_eq!(config.glibc_audit, Some(false));
+        assert_eq!(config.headerpad_max_install_names, Some(true));
+        assert_eq!(config.apple_sdk_stubs, Some(project.join("stubs")));
+        assert_eq!(
+            config.macos_sdk_search_paths,
+            [PathBuf::from("/opt/sdks"), project.join("sdks")]
+        );
+        assert_eq!(config.macos_sdk_version.as_deref(), Some("14"));
+        assert_eq!(
+            config.apple_sdk_roots["iphoneos"],
+            project.join("sdks/iPhoneOS.sdk")
+        );
+        assert_eq!(config.xwin_dir, Some(PathBuf::from("/opt/xwin")));
+        assert_eq!(config.def_dirs, [project.join("defs")]);
+        assert_eq!(config.pe_report, Some(true));
+        assert_eq!(
+            config.fat_targets["universal-apple-tvos"],
+            ["x86_64-apple-tvos", "aarch64-apple-tvos"]
+        );
+    }
+}
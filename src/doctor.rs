@@ -0,0 +1,470 @@
+use std::env;
+use std::path::PathBuf;
+use std::process::{self, Command};
+
+use anyhow::Result;
+use clap::Parser;
+use serde::Serialize;
+use target_lexicon::{Aarch64Architecture, Architecture, X86_32Architecture};
+
+use crate::config::ZigbuildConfig;
+use crate::fat::FatTarget;
+use crate::windows::xwin::XwinSdk;
+use crate::zig::{Zig, cache_dir, get_dlltool_name, has_system_dlltool, prepare_zig_linker};
+
+/// Diagnose the cargo-zigbuild environment
+#[derive(Clone, Debug, Default, Parser)]
+#[command(display_order = 1)]
+pub struct Doctor {
+    /// Check whether the zig linker can be set up for the given targets
+    #[arg(long, value_name = "TRIPLE")]
+    pub target: Vec<String>,
+
+    /// Output format
+    #[arg(long, value_enum, default_value_t = DoctorFormat::Human)]
+    pub format: DoctorFormat,
+}
+
+/// Output format of `cargo zigbuild doctor`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum DoctorFormat {
+    /// Human readable report
+    #[default]
+    Human,
+    /// JSON report
+    Json,
+}
+
+#[derive(Debug, Serialize)]
+struct Report {
+    zig: ZigReport,
+    cache_dir: PathBuf,
+    /// Error loading the `[zigbuild]` configuration
+    config_error: Option<String>,
+    rustc: Option<RustcReport>,
+    /// `None` when rustup is not available
+    rustup_targets: Option<Vec<String>>,
+    sdkroot: EnvPathReport,
+    macos_sdk: MacosSdkReport,
+    ohos_ndk_home: EnvPathReport,
+    dlltool: Vec<DlltoolReport>,
+    cmake: Option<PathBuf>,
+    ninja: Option<PathBuf>,
+    targets: Vec<TargetReport>,
+}
+
+#[derive(Debug, Serialize)]
+struct ZigReport {
+    /// `python` for the `ziglang` Python package, `binary` for a plain zig executable
+    kind: Option<&'static str>,
+    path: Option<PathBuf>,
+    version: Option<String>,
+    lib_dir: Option<PathBuf>,
+    error: Option<String>,
+    hint: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct RustcReport {
+    version: String,
+    host: String,
+}
+
+#[derive(Debug, Serialize)]
+struct EnvPathReport {
+    name: &'static str,
+    value: Option<PathBuf>,
+    exists: bool,
+}
+
+#[derive(Debug, Default, Serialize)]
+struct MacosSdkReport {
+    path: Option<PathBuf>,
+    version: Option<String>,
+    error: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct DlltoolReport {
+    name: &'static str,
+    path: Option<PathBuf>,
+}
+
+#[derive(Debug, Serialize)]
+struct TargetReport {
+    target: String,
+    ok: bool,
+    rustup_installed: Option<bool>,
+    error: Option<String>,
+    hints: Vec<String>,
+}
+
+impl Doctor {
+    /// Execute `cargo zigbuild doctor` command
+    pub fn execute(&self) -> Result<()> {
+        let (config, config_error) = match ZigbuildConfig::load() {
+            Ok(config) => (config, None),
+            Err(err) => (ZigbuildConfig::default(), Some(format!("{err:#}"))),
+        };
+        let report = self.report(&config, config_error);
+        match self.format {
+            DoctorFormat::Human => print_human(&report),
+            DoctorFormat::Json => println!("{}", serde_json::to_string_pretty(&report)?),
+        }
+        if report.zig.error.is_some()
+            || report.config_error.is_some()
+            || report.targets.iter().any(|t| !t.ok)
+        {
+            process::exit(1);
+        }
+        Ok(())
+    }
+
+    fn report(&self, config: &ZigbuildConfig, config_error: Option<String>) -> Report {
+        let zig = zig_report();
+        let rustc = rustc_version::version_meta().ok().map(|meta| RustcReport {
+            version: meta.semver.to_string(),
+            host: meta.host,
+        });
+        let rustup_targets = rustup_installed_targets();
+        let sdkroot = env_path_report("SDKROOT");
+        let macos_sdk = match Zig::macos_sdk(config) {
+            Ok(sdk) => MacosSdkReport {
+                path: sdk.as_ref().map(|sdk| sdk.path.clone()),
+                version: sdk.and_then(|sdk| sdk.version),
+                error: None,
+            },
+            Err(err) => MacosSdkReport {
+                error: Some(format!("{err:#}")),
+                ..Default::default()
+            },
+        };
+        let ohos_ndk_home = env_path_report("OHOS_NDK_HOME");
+        let dlltool = [
+            Architecture::X86_64,
+            Architecture::X86_32(X86_32Architecture::I686),
+            Architecture::Aarch64(Aarch64Architecture::Aarch64),
+        ]
+        .iter()
+        .map(|arch| DlltoolReport {
+            name: get_dlltool_name(arch),
+            path: if has_system_dlltool(arch) {
+                which::which(get_dlltool_name(arch)).ok()
+            } else {
+                None
+            },
+        })
+        .collect();
+        let targets = self
+            .target
+            .iter()
+            .map(|target| {
+                target_report(
+                    target,
+                    zig.error.is_none(),
+                    rustup_targets.as_deref(),
+                    config,
+                    &sdkroot,
+                    &macos_sdk,
+                    &ohos_ndk_home,
+                )
+            })
+            .collect();
+        Report {
+            zig,
+            cache_dir: cache_dir(),
+            config_error,
+            rustc,
+            rustup_targets,
+            sdkroot,
+            macos_sdk,
+            ohos_ndk_home,
+            dlltool,
+            cmake: which::which("cmake").ok(),
+            ninja: which::which("ninja").ok(),
+            targets,
+        }
+    }
+}
+
+fn zig_report() -> ZigReport {
+    match Zig::find_zig() {
+        Ok((path, args)) => {
+            let kind = if args.is_empty() { "binary" } else { "python" };
+            let version = Zig::zig_version().map(|v| v.to_string());
+            let lib_dir = Zig::lib_dir();
+            let error = match (&version, &lib_dir) {
+                (Err(e), _) => Some(format!("{e:#}")),
+                (_, Err(e)) => Some(format!("Failed to find zig lib directory: {e:#}")),
+                _ => None,
+            };
+            ZigReport {
+                kind: Some(kind),
+                path: Some(path),
+                version: version.ok(),
+                lib_dir: lib_dir.ok(),
+                error,
+                hint: None,
+            }
+        }
+        Err(e) => ZigReport {
+            kind: None,
+            path: None,
+            version: None,
+            lib_dir: None,
+            error: Some(format!("{e:#}")),
+            hint: Some(
+                "install zig with `pip3 install ziglang` or from https://ziglang.org/download/, \
+                 or point `CARGO_ZIGBUILD_ZIG_PATH`/`CARGO_ZIGBUILD_PYTHON_PATH` at it"
+                    .to_string(),
+            ),
+        },
+    }
+}
+
+fn env_path_report(name: &'static str) -> EnvPathReport {
+    let value = env::var_os(name)
+        .filter(|v| !v.is_empty())
+        .map(PathBuf::from);
+    let exists = value.as_ref().is_some_and(|p| p.is_dir());
+    EnvPathReport {
+        name,
+        value,
+        exists,
+    }
+}
+
+fn rustup_installed_targets() -> Option<Vec<String>> {
+    let output = Command::new("rustup")
+        .args(["target", "list", "--installed"])
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let stdout = String::from_utf8(output.stdout).ok()?;
+    Some(stdout.lines().map(|l| l.trim().to_string()).collect())
+}
+
+fn target_report(
+    target: &str,
+    zig_found: bool,
+    rustup_targets: Option<&[String]>,
+    config: &ZigbuildConfig,
+    sdkroot: &EnvPathReport,
+    macos_sdk: &MacosSdkReport,
+    ohos_ndk_home: &EnvPathReport,
+) -> TargetReport {
+    let rust_target = target.split_once('.').map(|(t, _)| t).unwrap_or(target);
+    let mut hints = Vec::new();
+
+    let constituents = match FatTarget::resolve(rust_target, config) {
+        Some(fat) => fat.targets,
+        None => vec![rust_target.to_string()],
+    };
+
+    let rustup_installed =
+        rustup_targets.map(|targets| constituents.iter().all(|t| targets.iter().any(|x| x == t)));
+    if rustup_installed == Some(false) {
+        hints.push(format!(
+            "run `rustup target add {}`",
+            constituents.join(" ")
+        ));
+    }
+
+    let error = if !zig_found {
+        Some("zig not found".to_string())
+    } else if constituents.len() > 1 {
+        constituents.iter().find_map(|t| prepare_target(t).err())
+    } else {
+        prepare_target(target).err()
+    };
+    if let Some(error) = &error {
+        if error.contains("Malformed target version suffix") {
+            hints.push(
+                "the version suffix must look like `.2.17`, e.g. `x86_64-unknown-linux-gnu.2.17`"
+                    .to_string(),
+            );
+        } else if error.contains("is not supported by the installed zig") {
+            hints.push("upgrade zig or request an older glibc version".to_string());
+        } else if error.contains("unsupported target") || error.contains("Unsupported Rust target")
+        {
+            hints.push("this target is not supported by cargo-zigbuild yet".to_string());
+        }
+    }
+
+    if target.contains("apple") && sdkroot.value.is_none() && macos_sdk.path.is_none() {
+        hints.push(
+            "set `SDKROOT` or `macos-sdk-search-paths` to a macOS SDK to link against frameworks \
+             other than the system libraries"
+                .to_string(),
+        );
+    } else if target.contains("apple") && sdkroot.value.is_some() && !sdkroot.exists {
+        hints.push("`SDKROOT` points to a directory that doesn't exist".to_string());
+    }
+    if target.contains("windows-msvc") && !cfg!(windows) {
+        match XwinSdk::from_config(config) {
+            Ok(Some(_)) => {}
+            Ok(None) => hints.push(
+                "set `CARGO_ZIGBUILD_XWIN_DIR` or `xwin-dir` to an `xwin splat` directory \
+                 to link against the MSVC libraries"
+                    .to_string(),
+            ),
+            Err(err) => hints.push(format!("{err:#}")),
+        }
+    }
+    let mut ok = error.is_none();
+    if target.contains("ohos") && !ohos_ndk_home.exists {
+        ok = false;
+        hints.push("set `OHOS_NDK_HOME` to the OpenHarmony NDK directory".to_string());
+    }
+    if rustup_installed == Some(false) {
+        ok = false;
+    }
+    TargetReport {
+        target: target.to_string(),
+        ok,
+        rustup_installed,
+        error,
+        hints,
+    }
+}
+
+fn prepare_target(target: &str) -> Result<(), String> {
+    let cargo_config = cargo_config2::Config::load().map_err(|e| format!("{e:#}"))?;
+    prepare_zig_linker(target, &cargo_config)
+        .map(|_| ())
+        .map_err(|e| format!("{e:#}"))
+}
+
+fn print_human(report: &Report) {
+    let zig = &report.zig;
+    match (&zig.path, &zig.error) {
+        (Some(path), None) => println!(
+            "[ok] zig {} ({}): {}",
+            zig.version.as_deref().unwrap_or("unknown"),
+            if zig.kind == Some("python") {
+                "python ziglang package"
+            } else {
+                "zig binary"
+            },
+            path.display()
+        ),
+        (_, error) => println!(
+            "[error] zig: {}",
+            error.as_deref().unwrap_or("unknown error")
+        ),
+    }
+    if let Some(lib_dir) = &zig.lib_dir {
+        println!("[ok] zig lib dir: {}", lib_dir.display());
+    }
+    if let Some(hint) = &zig.hint {
+        println!("      hint: {hint}");
+    }
+    println!("[ok] cache dir: {}", report.cache_dir.display());
+    if let Some(error) = &report.config_error {
+        println!("[error] zigbuild config: {error}");
+    }
+    match &report.rustc {
+        Some(rustc) => println!("[ok] rustc {} (host: {})", rustc.version, rustc.host),
+        None => println!("[error] rustc: not found"),
+    }
+    match &report.rustup_targets {
+        Some(targets) => println!("[ok] rustup targets: {}", targets.join(", ")),
+        None => println!("[warning] rustup: not found"),
+    }
+    for env_path in [&report.sdkroot, &report.ohos_ndk_home] {
+        match &env_path.value {
+            Some(value) if env_path.exists => {
+                println!("[ok] {}: {}", env_path.name, value.display())
+            }
+            Some(value) => println!(
+                "[warning] {}: {} doesn't exist",
+                env_path.name,
+                value.display()
+            ),
+            None => println!("[info] {}: not set", env_path.name),
+        }
+    }
+    let macos_sdk = &report.macos_sdk;
+    match (&macos_sdk.path, &macos_sdk.error) {
+        (_, Some(error)) => println!("[error] macOS SDK: {error}"),
+        (Some(path), None) => println!(
+            "[ok] macOS SDK {}: {}",
+            macos_sdk.version.as_deref().unwrap_or("(unknown version)"),
+            path.display()
+        ),
+        (None, None) => println!("[info] macOS SDK: not found"),
+    }
+    for dlltool in &report.dlltool {
+        match &dlltool.path {
+            Some(path) => println!("[ok] system {}: {}", dlltool.name, path.display()),
+            None => println!(
+                "[info] system {}: not found, zig dlltool will be used",
+                dlltool.name
+            ),
+        }
+    }
+    for (name, path) in [("cmake", &report.cmake), ("ninja", &report.ninja)] {
+        match path {
+            Some(path) => println!("[ok] {name}: {}", path.display()),
+            None => println!("[info] {name}: not found"),
+        }
+    }
+    for target in &report.targets {
+        match &target.error {
+            None if target.ok => println!("[ok] target {}", target.target),
+            None => println!("[error] target {}", target.target),
+            Some(error) => println!("[error] target {}: {error}", target.target),
+        }
+        for hint in &target.hints {
+            println!("      hint: {hint}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_target_report_hints() {
+        let unset = |name| EnvPathReport {
+            name,
+            value: None,
+            exists: false,
+        };
+        let installed = vec!["x86_64-apple-darwin".to_string()];
+        let report = target_report(
+            "universal2-apple-darwin",
+            false,
+            Some(&installed),
+            &ZigbuildConfig::default(),
+            &unset("SDKROOT"),
+            &MacosSdkReport::default(),
+            &unset("OHOS_NDK_HOME"),
+        );
+        assert!(!report.ok);
+        assert_eq!(report.rustup_installed, Some(false));
+        assert_eq!(report.error.as_deref(), Some("zig not found"));
+        assert!(
+            report
+                .hints
+                .iter()
+                .any(|h| h.contains("aarch64-apple-darwin"))
+        );
+        assert!(report.hints.iter().any(|h| h.contains("SDKROOT")));
+
+        let report = target_report(
+            "aarch64-unknown-linux-ohos",
+            false,
+            None,
+            &ZigbuildConfig::default(),
+            &unset("SDKROOT"),
+            &MacosSdkReport::default(),
+            &unset("OHOS_NDK_HOME"),
+        );
+        assert_eq!(report.rustup_installed, None);
+        assert!(report.hints.iter().any(|h| h.contains("OHOS_NDK_HOME")));
+    }
+}
@@ -0,0 +1,466 @@
+//! Multi-arch "fat" targets
+//!
+//! A fat target is built for each of its constituent targets, then the Mach-O
+//! outputs are merged into fat binaries under `target/<fat target name>/`.
+
+#[cfg(feature = "universal2")]
+use std::collections::HashMap;
+
+#[cfg(feature = "universal2")]
+use anyhow::Context;
+use anyhow::{Result, bail};
+use cargo_metadata::camino::{Utf8Path, Utf8PathBuf};
+#[cfg(feature = "universal2")]
+use cargo_metadata::{Artifact, PackageId, TargetKind};
+use fs_err as fs;
+
+use crate::config::ZigbuildConfig;
+
+/// Built-in fat targets and their constituent targets
+const BUILTIN_FAT_TARGETS: &[(&str, &[&str])] = &[
+    (
+        "universal2-apple-darwin",
+        &["x86_64-apple-darwin", "aarch64-apple-darwin"],
+    ),
+    (
+        "universal-apple-ios-sim",
+        &["x86_64-apple-ios", "aarch64-apple-ios-sim"],
+    ),
+    (
+        "universal-apple-tvos-sim",
+        &["x86_64-apple-tvos", "aarch64-apple-tvos-sim"],
+    ),
+    (
+        "universal-apple-watchos-sim",
+        &["x86_64-apple-watchos-sim", "aarch64-apple-watchos-sim"],
+    ),
+];
+
+/// A fat target made of several targets
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct FatTarget {
+    /// Name of the fat target, also used as the output directory name
+    pub name: String,
+    /// Constituent targets
+    pub targets: Vec<String>,
+}
+
+impl FatTarget {
+    /// Resolve a `--target` value to a built-in fat target or one defined in `[zigbuild.fat-targets]`
+    pub(crate) fn resolve(name: &str, config: &ZigbuildConfig) -> Option<Self> {
+        if let Some(targets) = config.fat_targets.get(name) {
+            return Some(Self {
+                name: name.to_string(),
+                targets: targets.clone(),
+            });
+        }
+        BUILTIN_FAT_TARGETS
+            .iter()
+            .find(|(fat_name, _)| *fat_name == name)
+            .map(|(fat_name, targets)| Self {
+                name: fat_name.to_string(),
+                targets: targets.iter().map(|t| t.to_string()).collect(),
+            })
+    }
+
+    /// Parse a comma separated `--fat` target list, the fat target is named
+    /// after its constituent targets joined with `+`
+    pub(crate) fn from_list(list: &str) -> Result<Self> {
+        let targets: Vec<String> = list
+            .split(',')
+            .map(|t| t.trim().to_string())
+            .filter(|t| !t.is_empty())
+            .collect();
+        let fat = Self {
+            name: targets.join("+"),
+            targets,
+        };
+        fat.validate()?;
+        Ok(fat)
+    }
+
+    /// Check that the fat target has at least two distinct constituent targets
+    pub(crate) fn validate(&self) -> Result<()> {
+        if self.targets.len() < 2 {
+            bail!(
+                "Fat target `{}` needs at least two targets, got {:?}",
+                self.name,
+                self.targets
+            );
+        }
+        for (i, target) in self.targets.iter().enumerate() {
+            if self.targets[..i].contains(target) {
+                bail!("Fat target `{}` lists `{target}` twice", self.name);
+            }
+        }
+        Ok(())
+    }
+
+    /// Merge the artifacts of the constituent targets into fat binaries
+    ///
+    /// Artifacts are paired by package, target name, crate kind and file name.
+    /// Executables, dynamic libraries, static archives and dSYM bundles are merged,
+    /// other files like `.rlib` or `.d` files are not. Fails if an artifact was
+    /// built for some of the constituent targets only.
+    ///
+    /// Returns the artifacts of the first constituent target with their filenames
+    /// pointing at the merged files.
+    #[cfg(feature = "universal2")]
+    pub(crate) fn write_fat_artifacts(&self, artifacts: &[Artifact]) -> Result<Vec<Artifact>> {
+        type Key<'a> = (&'a PackageId, &'a str, &'a [TargetKind], &'a str);
+
+        let mut keys: HashMap<Key, usize> = HashMap::new();
+        let mut groups: Vec<Vec<Option<(&Artifact, &Utf8PathBuf)>>> = Vec::new();
+        for artifact in artifacts {
+            for path in &artifact.filenames {
+                let Some(file_name) = path.file_name() else {
+                    continue;
+                };
+                if fat_file_kind(path).is_none() {
+                    continue;
+                }
+                let Some(index) = self.targets.iter().position(|target| {
+                    path.components()
+                        .any(|component| component.as_str() == target)
+                }) else {
+                    continue;
+                };
+                let key = (
+                    &artifact.package_id,
+                    artifact.target.name.as_str(),
+                    artifact.target.kind.as_slice(),
+                    file_name,
+                );
+                let group = *keys.entry(key).or_insert_with(|| {
+                    groups.push(vec![None; self.targets.len()]);
+                    groups.len() - 1
+                });
+                groups[group][index] = Some((artifact, path));
+            }
+        }
+
+        // Check that every artifact was built for all targets before writing anything
+        let mut paired = Vec::with_capacity(groups.len());
+        for group in groups {
+            let Some(inputs) = group.iter().copied().collect::<Option<Vec<_>>>() else {
+                let (artifact, path) = group.iter().flatten().next().unwrap();
+                let targets_where = |built: bool| {
+                    self.targets
+                        .iter()
+                        .zip(&group)
+                        .filter(|(_, input)| input.is_some() == built)
+                        .map(|(target, _)| format!("`{target}`"))
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                };
+                bail!(
+                    "Can't merge `{}` of `{}` into `{}`: it was built for {} but not for {}",
+                    path.file_name().unwrap_or_default(),
+                    artifact.target.name,
+                    self.name,
+                    targets_where(true),
+                    targets_where(false),
+                );
+            };
+            paired.push(inputs);
+        }
+
+        let first = &self.targets[0];
+        let mut fat_artifacts: Vec<Artifact> = Vec::new();
+        for inputs in paired {
+            let (artifact, path) = inputs[0];
+            let output = replace_target_dir(path, first, &self.name)
+                .with_context(|| format!("`{path}` is not in the `{first}` target directory"))?;
+            let input_paths: Vec<&Utf8PathBuf> = inputs.iter().map(|(_, path)| *path).collect();
+            match fat_file_kind(path) {
+                Some(FatFileKind::Dsym) => merge_dsym(&input_paths, &output)?,
+                Some(FatFileKind::Binary) => merge_binaries(&input_paths, &output)?,
+                None => continue,
+            }
+            let existing = fat_artifacts.iter_mut().find(|fat_artifact| {
+                fat_artifact.package_id == artifact.package_id
+                    && fat_artifact.target == artifact.target
+            });
+            let fat_artifact = match existing {
+                Some(fat_artifact) => fat_artifact,
+                None => {
+                    let mut fat_artifact = artifact.clone();
+                    fat_artifact.filenames.clear();
+                    fat_artifact.executable = None;
+                    fat_artifacts.push(fat_artifact);
+                    fat_artifacts.last_mut().unwrap()
+                }
+            };
+            if artifact.executable.as_ref() == Some(path) {
+                fat_artifact.executable = Some(output.clone());
+            }
+            fat_artifact.filenames.push(output);
+        }
+        Ok(fat_artifacts)
+    }
+}
+
+/// Kinds of artifact files merged into fat files
+#[cfg(feature = "universal2")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FatFileKind {
+    /// Executables, dynamic libraries and static archives
+    Binary,
+    /// dSYM debug info bundles
+    Dsym,
+}
+
+#[cfg(feature = "universal2")]
+fn fat_file_kind(path: &Utf8Path) -> Option<FatFileKind> {
+    match path.extension() {
+        Some("dSYM") => Some(FatFileKind::Dsym),
+        None | Some("dylib" | "a") => Some(FatFileKind::Binary),
+        _ => None,
+    }
+}
+
+/// Merge thin or fat Mach-O files into a fat file
+#[cfg(feature = "universal2")]
+pub(crate) fn merge_binaries(inputs: &[&Utf8PathBuf], output: &Utf8Path) -> Result<()> {
+    let mut fat = fat_macho::FatWriter::new();
+    for input in inputs {
+        fat.add(fs::read(input)?)
+            .with_context(|| format!("Failed to add `{input}` to fat file `{output}`"))?;
+    }
+    fs::create_dir_all(output.parent().unwrap())?;
+    fat.write_to_file(output)?;
+    Ok(())
+}
+
+/// Merge dSYM bundles, the DWARF files in `Contents/Resources/DWARF` are merged
+/// into fat files and the other files are copied
+#[cfg(feature = "universal2")]
+fn merge_dsym(inputs: &[&Utf8PathBuf], output: &Utf8Path) -> Result<()> {
+    if output.exists() {
+        fs::remove_dir_all(output)?;
+    }
+    for input in inputs {
+        copy_dir_all(input, output)?;
+    }
+    let dwarf_dir = Utf8Path::new("Contents").join("Resources").join("DWARF");
+    for entry in fs::read_dir(inputs[0].join(&dwarf_dir))? {
+        let file_name = entry?.file_name();
+        let dwarf_files: Vec<Utf8PathBuf> = inputs
+            .iter()
+            .map(|input| {
+                input
+                    .join(&dwarf_dir)
+                    .join(file_name.to_string_lossy().as_ref())
+            })
+            .collect();
+        let dwarf_files: Vec<&Utf8PathBuf> = dwarf_files.iter().collect();
+        merge_binaries(
+            &dwarf_files,
+            &output
+                .join(&dwarf_dir)
+                .join(file_name.to_string_lossy().as_ref()),
+        )?;
+    }
+    Ok(())
+}
+
+pub(crate) fn copy_dir_all(from: &Utf8Path, to: &Utf8Path) -> Result<()> {
+    fs::create_dir_all(to)?;
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        let file_name = entry.file_name();
+        let file_name = file_name.to_string_lossy();
+        if entry.file_type()?.is_dir() {
+            copy_dir_all(&from.join(file_name.as_ref()), &to.join(file_name.as_ref()))?;
+        } else {
+            fs::copy(entry.path(), to.join(file_name.as_ref()))?;
+        }
+    }
+    Ok(())
+}
+
+/// Replace the `from` target directory component of an artifact path with `to`
+#[cfg_attr(not(feature = "universal2"), allow(dead_code))]
+pub(crate) fn replace_target_dir(path: &Utf8Path, from: &str, to: &str) -> Option<Utf8PathBuf> {
+    let components: Vec<_> = path.components().collect();
+    let index = components.iter().rposition(|c| c.as_str() == from)?;
+    let mut replaced = Utf8PathBuf::new();
+    for (i, component) in components.iter().enumerate() {
+        if i == index {
+            replaced.push(to);
+        } else {
+            replaced.push(component.as_str());
+        }
+    }
+    Some(replaced)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(feature = "universal2")]
+    use crate::test_utils::{archive, fixture, fixtures_dir};
+
+    #[test]
+    fn test_resolve_fat_target() {
+        let mut config = ZigbuildConfig::default();
+        let ios_sim = FatTarget::resolve("universal-apple-ios-sim", &config).unwrap();
+        assert_eq!(
+            ios_sim.targets,
+            ["x86_64-apple-ios", "aarch64-apple-ios-sim"]
+        );
+        assert!(FatTarget::resolve("x86_64-apple-darwin", &config).is_none());
+
+        config.fat_targets.insert(
+            "universal2-apple-darwin".to_string(),
+            vec![
+                "x86_64h-apple-darwin".to_string(),
+                "aarch64-apple-darwin".to_string(),
+            ],
+        );
+        let universal2 = FatTarget::resolve("universal2-apple-darwin", &config).unwrap();
+        assert_eq!(universal2.targets[0], "x86_64h-apple-darwin");
+
+        let fat = FatTarget::from_list("x86_64-apple-ios, aarch64-apple-ios-sim").unwrap();
+        assert_eq!(fat.name, "x86_64-apple-ios+aarch64-apple-ios-sim");
+        assert_eq!(fat.targets, ios_sim.targets);
+        assert!(FatTarget::from_list("x86_64-apple-ios").is_err());
+        assert!(FatTarget::from_list("x86_64-apple-ios,x86_64-apple-ios").is_err());
+    }
+
+    #[test]
+    fn test_replace_target_dir() {
+        let path = Utf8Path::new("/work/x86_64-apple-ios/target/x86_64-apple-ios/debug/libfoo.a");
+        assert_eq!(
+            replace_target_dir(path, "x86_64-apple-ios", "universal-apple-ios-sim").unwrap(),
+            "/work/x86_64-apple-ios/target/universal-apple-ios-sim/debug/libfoo.a"
+        );
+        assert!(replace_target_dir(path, "aarch64-apple-ios-sim", "universal").is_none());
+    }
+
+    #[cfg(feature = "universal2")]
+    fn artifact(name: &str, kind: &str, filenames: &[&Utf8Path]) -> Artifact {
+        let executable = (kind == "bin").then(|| filenames[0]);
+        serde_json::from_value(serde_json::json!({
+            "package_id": "path+file:///work/foo#0.1.0",
+            "manifest_path": "/work/foo/Cargo.toml",
+            "target": {
+                "name": name,
+                "kind": [kind],
+                "crate_types": [kind],
+                "src_path": "/work/foo/src/lib.rs",
+            },
+            "profile": {
+                "opt_level": "0",
+                "debuginfo": 2,
+                "debug_assertions": true,
+                "overflow_checks": true,
+                "test": false,
+            },
+            "features": [],
+            "filenames": filenames,
+            "executable": executable,
+            "fresh": false,
+        }))
+        .unwrap()
+    }
+
+    #[cfg(feature = "universal2")]
+    fn narches(path: &Utf8Path) -> usize {
+        let data = fs::read(path).unwrap();
+        let goblin::mach::Mach::Fat(multi_arch) = goblin::mach::Mach::parse(&data).unwrap() else {
+            panic!("expected a fat Mach-O file");
+        };
+        multi_arch.narches
+    }
+
+    #[cfg(feature = "universal2")]
+    #[test]
+    fn test_write_fat_artifacts() {
+        let tmp = tempfile::tempdir().unwrap();
+        let target_dir = Utf8Path::from_path(tmp.path()).unwrap().join("target");
+        let fat =
+            FatTarget::resolve("universal2-apple-darwin", &ZigbuildConfig::default()).unwrap();
+        let mut x86_64_artifacts = Vec::new();
+        let mut aarch64_artifacts = Vec::new();
+        for (target, fixture_name, artifacts) in [
+            (
+                "x86_64-apple-darwin",
+                "test_x86_64.dylib",
+                &mut x86_64_artifacts,
+            ),
+            (
+                "aarch64-apple-darwin",
+                "test_aarch64.dylib",
+                &mut aarch64_artifacts,
+            ),
+        ] {
+            let dir = target_dir.join(target).join("debug");
+            let dwarf_dir = dir.join("foo.dSYM/Contents/Resources/DWARF");
+            fs::create_dir_all(&dwarf_dir).unwrap();
+            let macho = fixture(fixture_name);
+            fs::write(dir.join("libfoo.dylib"), &macho).unwrap();
+            fs::write(dir.join("libfoo.a"), archive(&macho)).unwrap();
+            fs::write(dir.join("libfoo.rlib"), archive(&macho)).unwrap();
+            fs::write(dir.join("foo"), &macho).unwrap();
+            fs::write(dwarf_dir.join("foo"), &macho).unwrap();
+            fs::write(dir.join("foo.dSYM/Contents/Info.plist"), "<plist/>").unwrap();
+            artifacts.push(artifact(
+                "foo",
+                "lib",
+                &[
+                    &dir.join("libfoo.dylib"),
+                    &dir.join("libfoo.a"),
+                    &dir.join("libfoo.rlib"),
+                ],
+            ));
+            artifacts.push(artifact(
+                "foo",
+                "bin",
+                &[&dir.join("foo"), &dir.join("foo.dSYM")],
+            ));
+        }
+        // Artifacts of different targets are reported in different orders
+        aarch64_artifacts.reverse();
+        let artifacts: Vec<_> = x86_64_artifacts
+            .into_iter()
+            .chain(aarch64_artifacts)
+            .collect();
+
+        let fat_artifacts = fat.write_fat_artifacts(&artifacts).unwrap();
+        let fat_dir = target_dir.join("universal2-apple-darwin/debug");
+        assert_eq!(fat_artifacts.len(), 2);
+        assert_eq!(
+            fat_artifacts[0].filenames,
+            [fat_dir.join("libfoo.dylib"), fat_dir.join("libfoo.a")]
+        );
+        assert_eq!(
+            fat_artifacts[1].filenames,
+            [fat_dir.join("foo"), fat_dir.join("foo.dSYM")]
+        );
+        assert_eq!(fat_artifacts[1].executable, Some(fat_dir.join("foo")));
+        assert!(!fat_dir.join("libfoo.rlib").exists());
+        assert_eq!(narches(&fat_dir.join("libfoo.dylib")), 2);
+        assert_eq!(narches(&fat_dir.join("libfoo.a")), 2);
+        assert_eq!(narches(&fat_dir.join("foo")), 2);
+        assert_eq!(
+            narches(&fat_dir.join("foo.dSYM/Contents/Resources/DWARF/foo")),
+            2
+        );
+        assert!(fat_dir.join("foo.dSYM/Contents/Info.plist").is_file());
+
+        // Only built for one of the targets
+        let x86_64_only = target_dir.join("x86_64-apple-darwin/debug/bar");
+        fs::copy(fixtures_dir().join("test_x86_64.dylib"), &x86_64_only).unwrap();
+        let mut artifacts = artifacts;
+        artifacts.push(artifact("bar", "bin", &[&x86_64_only]));
+        fs::remove_dir_all(&fat_dir).unwrap();
+        let err = fat.write_fat_artifacts(&artifacts).unwrap_err().to_string();
+        assert_eq!(
+            err,
+            "Can't merge `bar` of `bar` into `universal2-apple-darwin`: \
+             it was built for `x86_64-apple-darwin` but not for `aarch64-apple-darwin`"
+        );
+        assert!(!fat_dir.exists());
+    }
+}
@@ -0,0 +1,71 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use clap::Parser;
+use fs_err as fs;
+
+use crate::Zig;
+use crate::windows::gendef::ModuleDefinition;
+
+/// Generate a module-definition (`.def`) file from the exports of a Windows DLL,
+/// and optionally its import library
+#[derive(Clone, Debug, Default, Parser)]
+#[command(display_order = 1)]
+pub struct Gendef {
+    /// The DLL to read the exports of
+    #[arg(value_name = "DLL")]
+    pub input: PathBuf,
+
+    /// Write the `.def` file to this file instead of stdout
+    #[arg(short, long, value_name = "PATH")]
+    pub output: Option<PathBuf>,
+
+    /// Also generate the import library with `zig dlltool`, like `libfoo.dll.a` or `foo.lib`
+    #[arg(short = 'l', long, value_name = "PATH")]
+    pub import_lib: Option<PathBuf>,
+}
+
+impl Gendef {
+    /// Execute `cargo zigbuild gendef` command
+    pub fn execute(&self) -> Result<()> {
+        let data = fs::read(&self.input)?;
+        let file_name = self
+            .input
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let module = ModuleDefinition::from_dll(&data, &file_name)
+            .with_context(|| format!("failed to read the exports of '{}'", self.input.display()))?;
+        if !module.undetermined.is_empty() {
+            eprintln!(
+                "cargo-zigbuild: couldn't determine the stdcall argument size of {}, \
+                 add the `@N` decorations to the `.def` file if they aren't cdecl",
+                module.undetermined.join(", ")
+            );
+        }
+        let def = module.to_def();
+
+        let Some(import_lib) = &self.import_lib else {
+            match &self.output {
+                Some(output) => fs::write(output, def)?,
+                None => print!("{def}"),
+            }
+            return Ok(());
+        };
+        // dlltool reads the `.def` file from disk, put it next to the import library by default
+        let def_path = match &self.output {
+            Some(output) => output.clone(),
+            None => import_lib
+                .parent()
+                .unwrap_or(Path::new(""))
+                .join(Path::new(&file_name).with_extension("def")),
+        };
+        fs::write(&def_path, def)?;
+        let zig = Zig::Dlltool {
+            args: module.dlltool_args(&def_path.to_string_lossy(), &import_lib.to_string_lossy()),
+        };
+        zig.execute()?;
+        eprintln!("cargo-zigbuild: wrote `{}`", import_lib.display());
+        Ok(())
+    }
+}
@@ -1,21 +1,38 @@
 mod build;
 mod check;
 mod clippy;
+mod config;
 mod doc;
+mod doctor;
+mod fat;
+mod gendef;
 mod install;
+mod linker_rules;
 pub mod linux;
 pub mod macos;
+mod print_env;
 mod run;
 mod rustc;
+mod target_version;
+mod tbd;
 mod test;
+#[cfg(test)]
+mod test_utils;
+pub mod windows;
+mod xcframework;
 pub mod zig;
 
 pub use crate::clippy::Clippy;
 pub use build::Build;
 pub use check::Check;
 pub use doc::Doc;
+pub use doctor::Doctor;
+pub use gendef::Gendef;
 pub use install::Install;
+pub use print_env::PrintEnv;
 pub use run::Run;
 pub use rustc::Rustc;
+pub use tbd::Tbd;
 pub use test::Test;
+pub use xcframework::Xcframework;
 pub use zig::Zig;
@@ -0,0 +1,605 @@
+//! Declarative linker argument rewrite rules
+//!
+//! `zig cc` rejects a number of arguments that rustc passes to the linker, so every
+//! argument goes through an ordered rule table before being handed to zig.
+//! The first matching rule decides what happens to the argument:
+//!
+//! - `action = "skip"`: drop the argument
+//! - `action = "skip-with-next"`: drop the argument and the one following it
+//! - `replace = [...]`: replace the argument with the given arguments
+//!
+//! An argument matches a rule when it matches every specified matcher
+//! (`exact`, `prefix`, `suffix`, `contains`, `regex`), each of which may be
+//! a single string or a list of alternatives, and doesn't match `except`.
+//! A rule can be further restricted by a `cfg`-like target predicate,
+//! for example `cfg = "all(windows_gnu, not(i686))"`, and by zig and rustc
+//! version requirements such as `zig = ">=0.11, <0.16"`.
+//! Pre-release versions are compared by their `major.minor.patch` part.
+//!
+//! Replacements may refer to regex captures with `$0`, `$1`, ... and
+//! to the Apple CPU name of the target with `{apple_cpu}`.
+//!
+//! Built-in rules live in `linker-arg-rules.toml`, extra rules can be added to
+//! the `[zigbuild]` configuration, see [`crate::config`]. User rules are evaluated
+//! before the built-in rules so they can override them.
+
+use std::sync::OnceLock;
+
+use anyhow::{Context, Result, bail};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use crate::config::ZigbuildConfig;
+use crate::zig::TargetInfo;
+
+static BUILTIN_RULES: &str = include_str!("linker-arg-rules.toml");
+
+pub(crate) enum FilteredArg {
+    Keep(Vec<String>),
+    Skip,
+    SkipWithNext,
+}
+
+/// One or more strings
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(from = "OneOrMany", into = "OneOrMany")]
+pub struct StringList(pub Vec<String>);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+enum OneOrMany {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl From<OneOrMany> for StringList {
+    fn from(value: OneOrMany) -> Self {
+        match value {
+            OneOrMany::One(s) => Self(vec![s]),
+            OneOrMany::Many(v) => Self(v),
+        }
+    }
+}
+
+impl From<StringList> for OneOrMany {
+    fn from(value: StringList) -> Self {
+        OneOrMany::Many(value.0)
+    }
+}
+
+/// Argument matchers, see the module documentation
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct ArgMatcherConfig {
+    #[serde(default, skip_serializing_if = "StringList::is_empty")]
+    pub exact: StringList,
+    #[serde(default, skip_serializing_if = "StringList::is_empty")]
+    pub prefix: StringList,
+    #[serde(default, skip_serializing_if = "StringList::is_empty")]
+    pub suffix: StringList,
+    #[serde(default, skip_serializing_if = "StringList::is_empty")]
+    pub contains: StringList,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub regex: Option<String>,
+}
+
+/// What to do with a matched argument
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum RuleAction {
+    Skip,
+    SkipWithNext,
+    Replace,
+}
+
+/// A linker argument rewrite rule as written in configuration files
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct LinkerArgRuleConfig {
+    #[serde(default, skip_serializing_if = "StringList::is_empty")]
+    pub exact: StringList,
+    #[serde(default, skip_serializing_if = "StringList::is_empty")]
+    pub prefix: StringList,
+    #[serde(default, skip_serializing_if = "StringList::is_empty")]
+    pub suffix: StringList,
+    #[serde(default, skip_serializing_if = "StringList::is_empty")]
+    pub contains: StringList,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub regex: Option<String>,
+    /// Arguments matching this are not affected by the rule
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub except: Option<ArgMatcherConfig>,
+    /// Target predicate
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cfg: Option<String>,
+    /// zig version requirement
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub zig: Option<String>,
+    /// rustc version requirement
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rustc: Option<String>,
+    /// Defaults to `replace` when `replace` is set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub action: Option<RuleAction>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub replace: Option<Vec<String>>,
+}
+
+impl LinkerArgRuleConfig {
+    fn matcher(&self) -> ArgMatcherConfig {
+        ArgMatcherConfig {
+            exact: self.exact.clone(),
+            prefix: self.prefix.clone(),
+            suffix: self.suffix.clone(),
+            contains: self.contains.clone(),
+            regex: self.regex.clone(),
+        }
+    }
+}
+
+impl StringList {
+    fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl std::ops::Deref for StringList {
+    type Target = [String];
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+#[derive(Debug, Clone)]
+struct ArgMatcher {
+    exact: Vec<String>,
+    prefix: Vec<String>,
+    suffix: Vec<String>,
+    contains: Vec<String>,
+    regex: Option<Regex>,
+}
+
+impl ArgMatcher {
+    fn new(config: &ArgMatcherConfig) -> Result<Self> {
+        let regex = config
+            .regex
+            .as_deref()
+            .map(Regex::new)
+            .transpose()
+            .context("Invalid regex")?;
+        let matcher = Self {
+            exact: config.exact.0.clone(),
+            prefix: config.prefix.0.clone(),
+            suffix: config.suffix.0.clone(),
+            contains: config.contains.0.clone(),
+            regex,
+        };
+        if matcher.is_empty() {
+            bail!("at least one of `exact`, `prefix`, `suffix`, `contains` or `regex` is required");
+        }
+        Ok(matcher)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.exact.is_empty()
+            && self.prefix.is_empty()
+            && self.suffix.is_empty()
+            && self.contains.is_empty()
+            && self.regex.is_none()
+    }
+
+    fn matches(&self, arg: &str) -> bool {
+        (self.exact.is_empty() || self.exact.iter().any(|x| arg == x))
+            && (self.prefix.is_empty() || self.prefix.iter().any(|x| arg.starts_with(x)))
+            && (self.suffix.is_empty() || self.suffix.iter().any(|x| arg.ends_with(x)))
+            && (self.contains.is_empty() || self.contains.iter().any(|x| arg.contains(x)))
+            && self.regex.as_ref().is_none_or(|re| re.is_match(arg))
+    }
+}
+
+/// Target predicate expression, modeled after `cfg(...)`
+#[derive(Debug, Clone, PartialEq)]
+enum TargetPredicate {
+    Is(String),
+    All(Vec<TargetPredicate>),
+    Any(Vec<TargetPredicate>),
+    Not(Box<TargetPredicate>),
+}
+
+impl TargetPredicate {
+    fn parse(s: &str) -> Result<Self> {
+        let mut parser = PredicateParser { input: s, pos: 0 };
+        let predicate = parser.parse_expr()?;
+        parser.skip_whitespace();
+        if parser.pos != s.len() {
+            bail!(
+                "unexpected `{}` in target predicate `{s}`",
+                &s[parser.pos..]
+            );
+        }
+        Ok(predicate)
+    }
+
+    fn validate(&self) -> Result<()> {
+        match self {
+            TargetPredicate::Is(name) => {
+                if TargetInfo::new(None).predicate(name).is_none() {
+                    bail!("unknown target predicate `{name}`");
+                }
+                Ok(())
+            }
+            TargetPredicate::All(list) | TargetPredicate::Any(list) => {
+                list.iter().try_for_each(TargetPredicate::validate)
+            }
+            TargetPredicate::Not(inner) => inner.validate(),
+        }
+    }
+
+    fn eval(&self, target_info: &TargetInfo) -> bool {
+        match self {
+            TargetPredicate::Is(name) => target_info.predicate(name).unwrap_or_default(),
+            TargetPredicate::All(list) => list.iter().all(|p| p.eval(target_info)),
+            TargetPredicate::Any(list) => list.iter().any(|p| p.eval(target_info)),
+            TargetPredicate::Not(inner) => !inner.eval(target_info),
+        }
+    }
+}
+
+struct PredicateParser<'a> {
+    input: &'a str,
+    pos: usize,
+}
+
+impl PredicateParser<'_> {
+    fn skip_whitespace(&mut self) {
+        let rest = &self.input[self.pos..];
+        self.pos += rest.len() - rest.trim_start().len();
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        self.skip_whitespace();
+        if self.input[self.pos..].starts_with(c) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn parse_ident(&mut self) -> Result<&str> {
+        self.skip_whitespace();
+        let rest = &self.input[self.pos..];
+        let len = rest
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+            .unwrap_or(rest.len());
+        if len == 0 {
+            bail!("expected identifier in target predicate `{}`", self.input);
+        }
+        self.pos += len;
+        Ok(&rest[..len])
+    }
+
+    fn parse_expr(&mut self) -> Result<TargetPredicate> {
+        let ident = self.parse_ident()?.to_string();
+        if !self.eat('(') {
+            return Ok(TargetPredicate::Is(ident));
+        }
+        let mut list = Vec::new();
+        while !self.eat(')') {
+            list.push(self.parse_expr()?);
+            if !self.eat(',') {
+                if !self.eat(')') {
+                    bail!("expected `,` or `)` in target predicate `{}`", self.input);
+                }
+                break;
+            }
+        }
+        match ident.as_str() {
+            "all" => Ok(TargetPredicate::All(list)),
+            "any" => Ok(TargetPredicate::Any(list)),
+            "not" if list.len() == 1 => Ok(TargetPredicate::Not(Box::new(list.remove(0)))),
+            "not" => bail!("`not` takes exactly one predicate in `{}`", self.input),
+            _ => bail!(
+                "unknown operator `{ident}` in target predicate `{}`",
+                self.input
+            ),
+        }
+    }
+}
+
+/// Version requirement, checked against the version without pre-release and build metadata
+#[derive(Debug, Clone)]
+struct VersionRange(semver::VersionReq);
+
+impl VersionRange {
+    fn parse(s: &str) -> Result<Self> {
+        Ok(Self(s.parse().with_context(|| {
+            format!("Invalid version requirement `{s}`")
+        })?))
+    }
+
+    fn matches(&self, version: &semver::Version) -> bool {
+        self.0.matches(&semver::Version::new(
+            version.major,
+            version.minor,
+            version.patch,
+        ))
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Action {
+    Skip,
+    SkipWithNext,
+    Replace(Vec<String>),
+}
+
+#[derive(Debug, Clone)]
+struct LinkerArgRule {
+    matcher: ArgMatcher,
+    except: Option<ArgMatcher>,
+    cfg: Option<TargetPredicate>,
+    zig: Option<VersionRange>,
+    rustc: Option<VersionRange>,
+    action: Action,
+}
+
+impl LinkerArgRule {
+    fn new(config: &LinkerArgRuleConfig) -> Result<Self> {
+        let matcher = ArgMatcher::new(&config.matcher())?;
+        let except = config.except.as_ref().map(ArgMatcher::new).transpose()?;
+        let cfg = config
+            .cfg
+            .as_deref()
+            .map(TargetPredicate::parse)
+            .transpose()?;
+        if let Some(cfg) = &cfg {
+            cfg.validate()?;
+        }
+        let zig = config.zig.as_deref().map(VersionRange::parse).transpose()?;
+        let rustc = config
+            .rustc
+            .as_deref()
+            .map(VersionRange::parse)
+            .transpose()?;
+        let action = match (config.action, &config.replace) {
+            (Some(RuleAction::Skip), None) => Action::Skip,
+            (Some(RuleAction::SkipWithNext), None) => Action::SkipWithNext,
+            (Some(RuleAction::Replace) | None, Some(replace)) => Action::Replace(replace.clone()),
+            (Some(RuleAction::Replace) | None, None) => {
+                bail!("`replace` is required for the `replace` action")
+            }
+            (Some(_), Some(_)) => bail!("`replace` can only be used with the `replace` action"),
+        };
+        Ok(Self {
+            matcher,
+            except,
+            cfg,
+            zig,
+            rustc,
+            action,
+        })
+    }
+
+    fn apply(
+        &self,
+        arg: &str,
+        rustc_ver: &rustc_version::Version,
+        zig_version: &semver::Version,
+        target_info: &TargetInfo,
+    ) -> Option<FilteredArg> {
+        if !self.matcher.matches(arg)
+            || self
+                .except
+                .as_ref()
+                .is_some_and(|except| except.matches(arg))
+            || self.cfg.as_ref().is_some_and(|cfg| !cfg.eval(target_info))
+            || self
+                .zig
+                .as_ref()
+                .is_some_and(|zig| !zig.matches(zig_version))
+            || self
+                .rustc
+                .as_ref()
+                .is_some_and(|rustc| !rustc.matches(rustc_ver))
+        {
+            return None;
+        }
+        let filtered = match &self.action {
+            Action::Skip => FilteredArg::Skip,
+            Action::SkipWithNext => FilteredArg::SkipWithNext,
+            Action::Replace(replace) => {
+                let captures = self.matcher.regex.as_ref().and_then(|re| re.captures(arg));
+                let replaced = replace
+                    .iter()
+                    .map(|template| {
+                        let template = template.replace("{apple_cpu}", target_info.apple_cpu());
+                        match &captures {
+                            Some(captures) => {
+                                let mut expanded = String::new();
+                                captures.expand(&template, &mut expanded);
+                                expanded
+                            }
+                            None => template,
+                        }
+                    })
+                    .collect();
+                FilteredArg::Keep(replaced)
+            }
+        };
+        Some(filtered)
+    }
+}
+
+/// Ordered linker argument rewrite rule table
+#[derive(Debug, Clone)]
+pub(crate) struct LinkerArgRules {
+    rules: Vec<LinkerArgRule>,
+}
+
+#[derive(Deserialize)]
+struct BuiltinRules {
+    rules: Vec<LinkerArgRuleConfig>,
+}
+
+impl LinkerArgRules {
+    fn from_configs<'a>(
+        configs: impl IntoIterator<Item = &'a LinkerArgRuleConfig>,
+    ) -> Result<Self> {
+        let rules = configs
+            .into_iter()
+            .enumerate()
+            .map(|(index, config)| {
+                LinkerArgRule::new(config)
+                    .with_context(|| format!("Invalid linker argument rule #{}", index + 1))
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self { rules })
+    }
+
+    /// The built-in rules shipped with cargo-zigbuild
+    pub fn builtin() -> &'static Self {
+        static RULES: OnceLock<LinkerArgRules> = OnceLock::new();
+
+        RULES.get_or_init(|| {
+            let builtin: BuiltinRules =
+                toml::from_str(BUILTIN_RULES).expect("built-in linker rules should be valid");
+            Self::from_configs(&builtin.rules).expect("built-in linker rules should be valid")
+        })
+    }
+
+    /// User rules from the `[zigbuild]` configuration followed by the built-in rules
+    pub fn new(config: &ZigbuildConfig) -> Result<Self> {
+        let mut rules = Self::from_configs(&config.linker_arg_rules)
+            .context("Failed to load linker argument rules from `[zigbuild]` configuration")?;
+        rules.rules.extend(Self::builtin().rules.iter().cloned());
+        Ok(rules)
+    }
+
+    /// Apply the first matching rule to `arg`
+    pub fn apply(
+        &self,
+        arg: &str,
+        rustc_ver: &rustc_version::Version,
+        zig_version: &semver::Version,
+        target_info: &TargetInfo,
+    ) -> FilteredArg {
+        self.rules
+            .iter()
+            .find_map(|rule| rule.apply(arg, rustc_ver, zig_version, target_info))
+            .unwrap_or_else(|| FilteredArg::Keep(vec![arg.to_string()]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules(toml: &str) -> Result<LinkerArgRules> {
+        let config: ZigbuildConfig = toml::from_str(toml)?;
+        LinkerArgRules::new(&config)
+    }
+
+    fn apply(rules: &LinkerArgRules, arg: &str, target: &str, zig: (u64, u64)) -> Vec<String> {
+        let target = target.to_string();
+        let target_info = TargetInfo::new(Some(&target));
+        match rules.apply(
+            arg,
+            &rustc_version::Version::new(1, 80, 0),
+            &semver::Version::new(0, zig.0, zig.1),
+            &target_info,
+        ) {
+            FilteredArg::Keep(args) => args,
+            FilteredArg::Skip => Vec::new(),
+            FilteredArg::SkipWithNext => vec!["<skip-with-next>".to_string()],
+        }
+    }
+
+    #[test]
+    fn test_builtin_rules_are_valid() {
+        assert!(!LinkerArgRules::builtin().rules.is_empty());
+    }
+
+    #[test]
+    fn test_target_predicate() {
+        let predicate = TargetPredicate::parse("all(windows_gnu, not(i686))").unwrap();
+        assert_eq!(
+            predicate,
+            TargetPredicate::All(vec![
+                TargetPredicate::Is("windows_gnu".to_string()),
+                TargetPredicate::Not(Box::new(TargetPredicate::Is("i686".to_string()))),
+            ])
+        );
+        let x86_64 = "x86_64-pc-windows-gnu".to_string();
+        let i686 = "i686-pc-windows-gnu".to_string();
+        assert!(predicate.eval(&TargetInfo::new(Some(&x86_64))));
+        assert!(!predicate.eval(&TargetInfo::new(Some(&i686))));
+        assert!(!predicate.eval(&TargetInfo::new(None)));
+
+        assert!(TargetPredicate::parse("any(arm,)").is_ok());
+        assert!(TargetPredicate::parse("not(arm, musl)").is_err());
+        assert!(TargetPredicate::parse("all(arm").is_err());
+        assert!(TargetPredicate::parse("xor(arm)").is_err());
+        assert!(
+            TargetPredicate::parse("nonexistent")
+                .unwrap()
+                .validate()
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_version_range_ignores_prerelease() {
+        let range = VersionRange::parse(">=0.16").unwrap();
+        assert!(range.matches(&semver::Version::parse("0.16.0-dev.1234+abcdef").unwrap()));
+        assert!(!range.matches(&semver::Version::parse("0.15.2").unwrap()));
+    }
+
+    #[test]
+    fn test_user_rules_take_precedence() {
+        let rules = rules(
+            r#"
+[[linker-arg-rules]]
+exact = "-lgcc_s"
+cfg = "musl"
+action = "skip"
+
+[[linker-arg-rules]]
+regex = '^-Wl,--foo=(\w+)$'
+zig = "<0.16"
+replace = ["-Wl,--bar=$1", "-Wl,--baz"]
+"#,
+        )
+        .unwrap();
+        let musl = "x86_64-unknown-linux-musl";
+        let gnu = "x86_64-unknown-linux-gnu";
+        assert!(apply(&rules, "-lgcc_s", musl, (13, 0)).is_empty());
+        assert_eq!(apply(&rules, "-lgcc_s", gnu, (13, 0)), vec!["-lunwind"]);
+        assert_eq!(
+            apply(&rules, "-Wl,--foo=abc", gnu, (13, 0)),
+            vec!["-Wl,--bar=abc", "-Wl,--baz"]
+        );
+        assert_eq!(
+            apply(&rules, "-Wl,--foo=abc", gnu, (16, 0)),
+            vec!["-Wl,--foo=abc"]
+        );
+    }
+
+    #[test]
+    fn test_invalid_user_rules() {
+        // no matcher
+        assert!(rules("[[linker-arg-rules]]\naction = \"skip\"").is_err());
+        // no action
+        assert!(rules("[[linker-arg-rules]]\nexact = \"-lfoo\"").is_err());
+        // replace with a non-replace action
+        assert!(
+            rules("[[linker-arg-rules]]\nexact = \"-lfoo\"\naction = \"skip\"\nreplace = []")
+                .is_err()
+        );
+        // invalid regex
+        assert!(rules("[[linker-arg-rules]]\nregex = \"(\"\naction = \"skip\"").is_err());
+        // unknown key
+        assert!(rules("[[linker-arg-rules]]\nexcat = \"-lfoo\"\naction = \"skip\"").is_err());
+    }
+}
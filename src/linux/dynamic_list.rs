@@ -0,0 +1,46 @@
+//! Turn a `--dynamic-list` into a linker version script
+//!
+//! zig < 0.16 doesn't support `--dynamic-list`, which rustc passes to export symbols
+//! from executables. A version script hiding every other symbol combined with
+//! `--export-dynamic` exports the same symbols.
+
+use anyhow::{Result, bail};
+
+/// Convert the content of a dynamic list file to a version script
+///
+/// Both share the syntax of symbol entries, so the entries are kept as is
+/// under `global:`.
+pub(crate) fn version_script(dynamic_list: &str) -> Result<String> {
+    let block = dynamic_list
+        .find('{')
+        .zip(dynamic_list.rfind('}'))
+        .filter(|(start, end)| start < end);
+    let Some((start, end)) = block else {
+        bail!("dynamic list is missing its `{{ ... }};` block");
+    };
+    let entries = dynamic_list[start + 1..end].trim();
+    if entries.is_empty() {
+        return Ok("{\n  local:\n    *;\n};\n".to_string());
+    }
+    Ok(format!(
+        "{{\n  global:\n    {entries}\n  local:\n    *;\n}};\n"
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_version_script() {
+        let script = version_script("{\n  foo;\n  bar;\n};\n").unwrap();
+        assert_eq!(
+            script,
+            "{\n  global:\n    foo;\n  bar;\n  local:\n    *;\n};\n"
+        );
+        let script = version_script("{\n};\n").unwrap();
+        assert_eq!(script, "{\n  local:\n    *;\n};\n");
+        assert!(version_script("foo;").is_err());
+        assert!(version_script("} {").is_err());
+    }
+}
@@ -0,0 +1,155 @@
+//! Check the glibc symbol versions required by linked ELF artifacts
+//!
+//! Prebuilt static libraries pulled in by `-sys` crates may reference symbols
+//! from a newer glibc than the one requested with the `.2.17` target suffix,
+//! which silently raises the minimum glibc version of the final artifact.
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use anyhow::{Context, Result, bail};
+use fs_err as fs;
+use goblin::elf::Elf;
+
+use crate::target_version::TargetVersion;
+
+/// A dynamic symbol imported with a `GLIBC_x.y` version
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct GlibcSymbol {
+    pub name: String,
+    pub version: TargetVersion,
+}
+
+/// Collect the imported symbols that require a `GLIBC_x.y` version
+///
+/// Returns `None` if `data` is not an ELF file.
+pub(crate) fn glibc_symbols(data: &[u8]) -> Result<Option<Vec<GlibcSymbol>>> {
+    let Ok(elf) = Elf::parse(data) else {
+        return Ok(None);
+    };
+    let (Some(verneed), Some(versym)) = (&elf.verneed, &elf.versym) else {
+        // Statically linked or doesn't use versioned symbols
+        return Ok(Some(Vec::new()));
+    };
+    let mut versions = HashMap::new();
+    for need in verneed.iter() {
+        for aux in need.iter() {
+            let version = elf
+                .dynstrtab
+                .get_at(aux.vna_name)
+                .and_then(|name| name.strip_prefix("GLIBC_"))
+                .and_then(|version| version.parse::<TargetVersion>().ok());
+            if let Some(version) = version {
+                versions.insert(aux.vna_other, version);
+            }
+        }
+    }
+    let mut symbols = Vec::new();
+    for (index, sym) in elf.dynsyms.iter().enumerate() {
+        if !sym.is_import() {
+            continue;
+        }
+        let Some(version) = versym
+            .get_at(index)
+            .and_then(|versym| versions.get(&versym.version()))
+        else {
+            continue;
+        };
+        let name = elf
+            .dynstrtab
+            .get_at(sym.st_name)
+            .context("Invalid dynamic symbol name")?;
+        symbols.push(GlibcSymbol {
+            name: name.to_string(),
+            version: *version,
+        });
+    }
+    Ok(Some(symbols))
+}
+
+/// Check that the ELF artifact at `path` doesn't require a glibc newer than `floor`
+///
+/// Returns the highest glibc version the artifact requires, `None` for non-ELF files
+/// and artifacts without versioned glibc symbols.
+pub(crate) fn audit_glibc_artifact(
+    path: &Path,
+    floor: TargetVersion,
+) -> Result<Option<TargetVersion>> {
+    let data = fs::read(path)?;
+    let Some(symbols) = glibc_symbols(&data)
+        .with_context(|| format!("Failed to read dynamic symbols of `{}`", path.display()))?
+    else {
+        return Ok(None);
+    };
+    let Some(required) = symbols
+        .iter()
+        .map(|sym| sym.version)
+        .max_by_key(|v| v.triple())
+    else {
+        return Ok(None);
+    };
+    if required.triple() > floor.triple() {
+        let mut offending: Vec<String> = symbols
+            .iter()
+            .filter(|sym| sym.version.triple() > floor.triple())
+            .map(|sym| format!("{}@GLIBC_{}", sym.name, sym.version))
+            .collect();
+        offending.sort();
+        offending.dedup();
+        bail!(
+            "`{}` requires glibc {required}, which is newer than the requested glibc {floor}, \
+             offending symbols: {}",
+            path.display(),
+            offending.join(", ")
+        );
+    }
+    Ok(Some(required))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{fixture, fixtures_dir};
+
+    #[test]
+    fn test_glibc_symbols() {
+        let data = fixture("test_glibc_2.34.so");
+        let mut symbols = glibc_symbols(&data).unwrap().unwrap();
+        symbols.sort_by_key(|sym| sym.version.triple());
+        let pthread_create = symbols.last().unwrap();
+        assert_eq!(pthread_create.name, "pthread_create");
+        assert_eq!(pthread_create.version.to_string(), "2.34");
+        assert!(
+            symbols
+                .iter()
+                .any(|sym| sym.name == "getrandom" && sym.version.to_string() == "2.25")
+        );
+
+        assert!(glibc_symbols(b"not an elf file").unwrap().is_none());
+    }
+
+    #[test]
+    fn test_audit_glibc_artifact() {
+        let path = fixtures_dir().join("test_glibc_2.34.so");
+        let required = audit_glibc_artifact(&path, "2.34".parse().unwrap()).unwrap();
+        assert_eq!(required.unwrap().to_string(), "2.34");
+        let required = audit_glibc_artifact(&path, "2.35".parse().unwrap()).unwrap();
+        assert_eq!(required.unwrap().to_string(), "2.34");
+        assert!(
+            audit_glibc_artifact(
+                &fixtures_dir().join("test_x86_64.dylib"),
+                "2.17".parse().unwrap()
+            )
+            .unwrap()
+            .is_none()
+        );
+        let err = audit_glibc_artifact(&path, "2.17".parse().unwrap())
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("requires glibc 2.34"));
+        assert!(err.contains("getrandom@GLIBC_2.25"));
+        assert!(err.contains("pthread_create@GLIBC_2.34"));
+        assert!(err.contains("reallocarray@GLIBC_2.26"));
+        assert!(!err.contains("free@"));
+    }
+}
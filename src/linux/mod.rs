@@ -1,3 +1,6 @@
+pub(crate) mod dynamic_list;
+pub(crate) mod glibc_audit;
+
 /// arm-features.h
 pub static ARM_FEATURES_H: &str = include_str!("arm-features.h");
 
@@ -0,0 +1,118 @@
+//! Minimum OS versions recorded in `LC_BUILD_VERSION` and `LC_VERSION_MIN_*` load commands
+
+use std::path::Path;
+
+use anyhow::{Context, Result, bail};
+use fs_err as fs;
+use goblin::mach::constants::cputype::{CPU_SUBTYPE_MASK, get_arch_name_from_types};
+use goblin::mach::load_command::CommandVariant;
+use goblin::mach::{Mach, MachO, SingleArch};
+
+use crate::target_version::TargetVersion;
+
+/// The architecture name of a slice and its minimum OS version, if recorded
+type SliceVersion = (String, Option<TargetVersion>);
+
+/// The minimum OS version of each slice
+///
+/// Returns `None` if `data` is not a Mach-O file.
+pub(crate) fn min_os_versions(data: &[u8]) -> Result<Option<Vec<SliceVersion>>> {
+    let Ok(mach) = Mach::parse(data) else {
+        return Ok(None);
+    };
+    let mut versions = Vec::new();
+    match mach {
+        Mach::Binary(macho) => versions.push(slice_min_os_version(&macho)),
+        Mach::Fat(multi) => {
+            for slice in &multi {
+                let SingleArch::MachO(macho) = slice? else {
+                    bail!("archives in fat files are not supported");
+                };
+                versions.push(slice_min_os_version(&macho));
+            }
+        }
+    }
+    Ok(Some(versions))
+}
+
+fn slice_min_os_version(macho: &MachO) -> SliceVersion {
+    let cputype = macho.header.cputype;
+    let arch = get_arch_name_from_types(cputype, macho.header.cpusubtype & !CPU_SUBTYPE_MASK)
+        .map(str::to_string)
+        .unwrap_or_else(|| format!("cputype {cputype}"));
+    let minos = macho.load_commands.iter().find_map(|lc| match &lc.command {
+        CommandVariant::BuildVersion(cmd) => Some(cmd.minos),
+        CommandVariant::VersionMinMacosx(cmd)
+        | CommandVariant::VersionMinIphoneos(cmd)
+        | CommandVariant::VersionMinTvos(cmd)
+        | CommandVariant::VersionMinWatchos(cmd) => Some(cmd.version),
+        _ => None,
+    });
+    (arch, minos.map(unpack_version))
+}
+
+/// Unpack a `xxxx.yy.zz` version packed as nibbles, a zero patch version is left out
+fn unpack_version(version: u32) -> TargetVersion {
+    let patch = version & 0xff;
+    TargetVersion {
+        major: version >> 16,
+        minor: Some((version >> 8) & 0xff),
+        patch: (patch != 0).then_some(patch),
+    }
+}
+
+/// Check that every slice of a linked Mach-O file has the requested minimum OS version
+///
+/// Files that aren't Mach-O are ignored.
+pub(crate) fn check_min_os_version(path: &Path, expected: TargetVersion) -> Result<()> {
+    let data = fs::read(path)?;
+    let Some(versions) = min_os_versions(&data)
+        .with_context(|| format!("Failed to read load commands of `{}`", path.display()))?
+    else {
+        return Ok(());
+    };
+    for (arch, minos) in versions {
+        match minos {
+            Some(minos) if minos.triple() == expected.triple() => {}
+            Some(minos) => bail!(
+                "`{}` ({arch}) has a minimum OS version of {minos}, \
+                 but {expected} was requested",
+                path.display()
+            ),
+            None => bail!(
+                "`{}` ({arch}) doesn't record a minimum OS version, but {expected} was requested",
+                path.display()
+            ),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{fixture, fixtures_dir};
+
+    #[test]
+    fn test_min_os_versions() {
+        let data = fixture("test_universal2.dylib");
+        let versions = min_os_versions(&data).unwrap().unwrap();
+        let minos = Some("26.0".parse().unwrap());
+        assert_eq!(
+            versions,
+            [("x86_64".to_string(), minos), ("arm64".to_string(), minos)]
+        );
+        assert_eq!(unpack_version(0x000a_0f02).to_string(), "10.15.2");
+        assert_eq!(min_os_versions(b"not a Mach-O file").unwrap(), None);
+
+        let path = fixtures_dir().join("test_aarch64.dylib");
+        check_min_os_version(&path, "26.0".parse().unwrap()).unwrap();
+        check_min_os_version(&path, "26.0.0".parse().unwrap()).unwrap();
+        let err = check_min_os_version(&path, "11.0".parse().unwrap()).unwrap_err();
+        assert!(
+            err.to_string()
+                .ends_with("(arm64) has a minimum OS version of 26.0, but 11.0 was requested"),
+            "{err}"
+        );
+    }
+}
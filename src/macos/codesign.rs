@@ -0,0 +1,647 @@
+//! A minimal ad-hoc `codesign` for cross-compilation.
+//!
+//! arm64 Mach-O binaries must carry at least an ad-hoc signature, otherwise the
+//! kernel refuses to run them. An ad-hoc signature is a code directory with the
+//! SHA-256 hashes of every page of the file, without any certificate.
+//!
+//! Supported options:
+//! - `-s -` / `--sign -`: Sign ad-hoc, signing identities aren't supported
+//! - `-f` / `--force`: Replace an existing signature
+//! - `-i identifier` / `--identifier identifier`: Set the signing identifier
+//! - `-v` / `--verbose`: Accepted and ignored
+
+use std::ffi::{CStr, OsString};
+use std::path::Path;
+
+use anyhow::{Context, Result, bail};
+use goblin::mach::constants::cputype::{CPU_ARCH_MASK, CPU_TYPE_ARM};
+use goblin::mach::fat::{FAT_MAGIC, FatArch, FatHeader, SIZEOF_FAT_ARCH, SIZEOF_FAT_HEADER};
+use goblin::mach::header::{Header, MH_EXECUTE};
+use goblin::mach::load_command::{
+    CommandVariant, LC_CODE_SIGNATURE, LinkeditDataCommand, SIZEOF_LINKEDIT_DATA_COMMAND,
+};
+use goblin::mach::{MachO, MultiArch, parse_magic_and_ctx, peek};
+use scroll::{BE, Pread, Pwrite};
+use sha2::{Digest, Sha256};
+
+use super::install_name_tool::{header_size, insert_load_command};
+
+const CSMAGIC_EMBEDDED_SIGNATURE: u32 = 0xfade_0cc0;
+const CSMAGIC_CODEDIRECTORY: u32 = 0xfade_0c02;
+const CSMAGIC_REQUIREMENTS: u32 = 0xfade_0c01;
+const CSSLOT_CODEDIRECTORY: u32 = 0;
+const CSSLOT_REQUIREMENTS: u32 = 2;
+/// Code directory version with the executable segment fields
+const CS_SUPPORTSEXECSEG: u32 = 0x20400;
+const CS_ADHOC: u32 = 0x2;
+const CS_LINKER_SIGNED: u32 = 0x2_0000;
+const CS_EXECSEG_MAIN_BINARY: u64 = 0x1;
+const CS_HASHTYPE_SHA256: u8 = 2;
+const CS_HASH_SIZE: usize = 32;
+const CS_PAGE_SIZE_LOG2: u8 = 12;
+const CS_PAGE_SIZE: usize = 1 << CS_PAGE_SIZE_LOG2;
+/// Size of the code directory header up to and including the executable segment fields
+const CODE_DIRECTORY_SIZE: usize = 88;
+/// Info.plist and requirements
+const SPECIAL_SLOTS: usize = 2;
+/// An empty requirements set: magic, length and a zero count
+const EMPTY_REQUIREMENTS: [u8; 12] = {
+    let magic = CSMAGIC_REQUIREMENTS.to_be_bytes();
+    [
+        magic[0], magic[1], magic[2], magic[3], 0, 0, 0, 12, 0, 0, 0, 0,
+    ]
+};
+
+/// Parsed command-line arguments for codesign
+#[derive(Debug, Default)]
+struct Args {
+    identity: Option<String>,
+    force: bool,
+    identifier: Option<String>,
+    inputs: Vec<String>,
+}
+
+fn parse_args(args: &[String]) -> Result<Args> {
+    let mut parsed = Args::default();
+    let mut i = 0;
+    while i < args.len() {
+        match args[i].as_str() {
+            "-s" | "--sign" => {
+                if i + 1 >= args.len() {
+                    bail!("{} requires an argument", args[i]);
+                }
+                parsed.identity = Some(args[i + 1].clone());
+                i += 2;
+            }
+            "-i" | "--identifier" => {
+                if i + 1 >= args.len() {
+                    bail!("{} requires an argument", args[i]);
+                }
+                parsed.identifier = Some(args[i + 1].clone());
+                i += 2;
+            }
+            "-f" | "--force" => {
+                parsed.force = true;
+                i += 1;
+            }
+            "-v" | "--verbose" => {
+                i += 1;
+            }
+            arg if arg.starts_with('-') => {
+                bail!("unknown option: {arg}");
+            }
+            _ => {
+                parsed.inputs.push(args[i].clone());
+                i += 1;
+            }
+        }
+    }
+    match parsed.identity.as_deref() {
+        Some("-") => {}
+        Some(identity) => {
+            bail!(
+                "signing identity `{identity}` is not supported, only ad-hoc signing with `-s -` is"
+            )
+        }
+        None => bail!("no signing identity specified, use `-s -` for ad-hoc signing"),
+    }
+    if parsed.inputs.is_empty() {
+        bail!("no input file specified");
+    }
+    Ok(parsed)
+}
+
+// -- Existing signatures --
+
+/// The identifier and flags of the code directory of a signed Mach-O slice
+fn existing_signature(data: &[u8], macho: &MachO) -> Option<(String, u32)> {
+    let sig = code_signature_command(macho)?;
+    let blob = data.get(sig.dataoff as usize..(sig.dataoff + sig.datasize) as usize)?;
+    if blob.pread_with::<u32>(0, BE).ok()? != CSMAGIC_EMBEDDED_SIGNATURE {
+        return None;
+    }
+    let count: u32 = blob.pread_with(8, BE).ok()?;
+    for index in 0..count as usize {
+        let slot: u32 = blob.pread_with(12 + index * 8, BE).ok()?;
+        let offset: u32 = blob.pread_with(16 + index * 8, BE).ok()?;
+        if slot != CSSLOT_CODEDIRECTORY {
+            continue;
+        }
+        let cd = blob.get(offset as usize..)?;
+        let flags: u32 = cd.pread_with(12, BE).ok()?;
+        let ident_offset: u32 = cd.pread_with(20, BE).ok()?;
+        let ident = CStr::from_bytes_until_nul(cd.get(ident_offset as usize..)?).ok()?;
+        return Some((ident.to_string_lossy().into_owned(), flags));
+    }
+    None
+}
+
+fn code_signature_command(macho: &MachO) -> Option<LinkeditDataCommand> {
+    macho.load_commands.iter().find_map(|lc| match lc.command {
+        CommandVariant::CodeSignature(cmd) => Some(cmd),
+        _ => None,
+    })
+}
+
+/// Whether the kernel requires a signature for this slice, arm slices always need one
+/// and an existing signature is invalidated by any modification.
+fn needs_signature(macho: &MachO) -> bool {
+    is_arm(&macho.header) || code_signature_command(macho).is_some()
+}
+
+/// Whether a slice is for any arm CPU, including arm64 and arm64_32
+fn is_arm(header: &Header) -> bool {
+    header.cputype & !CPU_ARCH_MASK == CPU_TYPE_ARM
+}
+
+// -- Signing --
+
+/// Ad-hoc sign a single Mach-O slice, replacing any existing signature
+///
+/// `flags` are the code directory flags, `CS_ADHOC` possibly with `CS_LINKER_SIGNED`.
+fn sign_macho(data: &[u8], identifier: &str, flags: u32) -> Result<Vec<u8>> {
+    let macho = MachO::parse(data, 0).context("failed to parse Mach-O")?;
+    let (_, maybe_ctx) = parse_magic_and_ctx(data, 0)?;
+    let ctx = maybe_ctx.context("could not determine endianness")?;
+
+    let mut text = None;
+    let mut linkedit = None;
+    for lc in &macho.load_commands {
+        let (name, fileoff, filesize) = match &lc.command {
+            CommandVariant::Segment64(seg) => (seg.segname, seg.fileoff, seg.filesize),
+            CommandVariant::Segment32(seg) => {
+                (seg.segname, u64::from(seg.fileoff), u64::from(seg.filesize))
+            }
+            _ => continue,
+        };
+        match CStr::from_bytes_until_nul(&name).map(|name| name.to_bytes()) {
+            Ok(b"__TEXT") => text = Some((fileoff, filesize)),
+            Ok(b"__LINKEDIT") => linkedit = Some((lc.offset, fileoff, filesize)),
+            _ => {}
+        }
+    }
+    let (exec_seg_base, exec_seg_limit) = text.context("no __TEXT segment found")?;
+    let (linkedit_offset, linkedit_fileoff, linkedit_filesize) =
+        linkedit.context("no __LINKEDIT segment found, can't add a code signature")?;
+
+    // The signature goes at the end of __LINKEDIT, replacing the existing one
+    let code_limit = match code_signature_command(&macho) {
+        Some(sig) => sig.dataoff as usize,
+        None => ((linkedit_fileoff + linkedit_filesize) as usize).next_multiple_of(16),
+    };
+    let n_code_slots = code_limit.div_ceil(CS_PAGE_SIZE);
+    let ident_offset = CODE_DIRECTORY_SIZE;
+    let hash_offset = ident_offset + identifier.len() + 1 + SPECIAL_SLOTS * CS_HASH_SIZE;
+    let cd_len = hash_offset + n_code_slots * CS_HASH_SIZE;
+    let superblob_header_len = 12 + 2 * 8;
+    let signature_len = superblob_header_len + cd_len + EMPTY_REQUIREMENTS.len();
+
+    // Reserve room for the new signature, the file is truncated to `code_limit` before hashing
+    let mut signed = data.to_vec();
+    signed.resize(data.len().max(code_limit + signature_len), 0);
+
+    if code_signature_command(&macho).is_none() {
+        let mut header = macho.header;
+        let offset = header_size(ctx) + header.sizeofcmds as usize;
+        let mut cmd = vec![0u8; SIZEOF_LINKEDIT_DATA_COMMAND];
+        cmd.pwrite_with(
+            LinkeditDataCommand {
+                cmd: LC_CODE_SIGNATURE,
+                cmdsize: SIZEOF_LINKEDIT_DATA_COMMAND as u32,
+                dataoff: 0,
+                datasize: 0,
+            },
+            0,
+            ctx.le,
+        )?;
+        insert_load_command(&mut signed, &mut header, ctx, offset, &cmd)?;
+    }
+
+    // Update the load commands before hashing, they're covered by the signature
+    let macho = MachO::parse(&signed, 0).context("failed to re-parse Mach-O")?;
+    let header = macho.header;
+    let mut sig_command = None;
+    let mut linkedit_command = None;
+    for lc in macho.load_commands {
+        match lc.command {
+            CommandVariant::CodeSignature(sig) => sig_command = Some((lc.offset, sig)),
+            command if lc.offset == linkedit_offset => linkedit_command = Some(command),
+            _ => {}
+        }
+    }
+    let (sig_offset, mut sig) = sig_command.context("LC_CODE_SIGNATURE not found")?;
+    sig.dataoff = code_limit as u32;
+    sig.datasize = signature_len as u32;
+    signed.pwrite_with(sig, sig_offset, ctx.le)?;
+
+    let page_size = if is_arm(&header) { 0x4000 } else { 0x1000 };
+    let linkedit_filesize = (code_limit + signature_len) as u64 - linkedit_fileoff;
+    let linkedit_vmsize = linkedit_filesize.next_multiple_of(page_size);
+    match linkedit_command {
+        Some(CommandVariant::Segment64(mut seg)) => {
+            seg.filesize = linkedit_filesize;
+            seg.vmsize = seg.vmsize.max(linkedit_vmsize);
+            signed.pwrite_with(seg, linkedit_offset, ctx.le)?;
+        }
+        Some(CommandVariant::Segment32(mut seg)) => {
+            seg.filesize = linkedit_filesize as u32;
+            seg.vmsize = seg.vmsize.max(linkedit_vmsize as u32);
+            signed.pwrite_with(seg, linkedit_offset, ctx.le)?;
+        }
+        _ => bail!("__LINKEDIT segment moved while adding LC_CODE_SIGNATURE"),
+    }
+    let exec_seg_flags = if header.filetype == MH_EXECUTE {
+        CS_EXECSEG_MAIN_BINARY
+    } else {
+        0
+    };
+
+    signed.truncate(code_limit);
+
+    // Code directory
+    let mut cd = vec![0u8; cd_len];
+    let mut offset = 0;
+    for value in [
+        CSMAGIC_CODEDIRECTORY,
+        cd_len as u32,
+        CS_SUPPORTSEXECSEG,
+        flags,
+        hash_offset as u32,
+        ident_offset as u32,
+        SPECIAL_SLOTS as u32,
+        n_code_slots as u32,
+        code_limit as u32,
+    ] {
+        cd.gwrite_with(value, &mut offset, BE)?;
+    }
+    for value in [CS_HASH_SIZE as u8, CS_HASHTYPE_SHA256, 0, CS_PAGE_SIZE_LOG2] {
+        cd.gwrite_with(value, &mut offset, BE)?;
+    }
+    // spare2, scatterOffset, teamOffset, spare3
+    offset += 16;
+    // codeLimit64 is only used for files larger than 4GiB
+    offset += 8;
+    for value in [exec_seg_base, exec_seg_limit, exec_seg_flags] {
+        cd.gwrite_with(value, &mut offset, BE)?;
+    }
+    debug_assert_eq!(offset, CODE_DIRECTORY_SIZE);
+    cd[ident_offset..ident_offset + identifier.len()].copy_from_slice(identifier.as_bytes());
+    // Special slots are stored in reverse order before the code slots, the Info.plist slot stays zero
+    let requirements_slot = hash_offset - SPECIAL_SLOTS * CS_HASH_SIZE;
+    cd[requirements_slot..requirements_slot + CS_HASH_SIZE]
+        .copy_from_slice(&Sha256::digest(EMPTY_REQUIREMENTS));
+    for (index, page) in signed.chunks(CS_PAGE_SIZE).enumerate() {
+        let slot = hash_offset + index * CS_HASH_SIZE;
+        cd[slot..slot + CS_HASH_SIZE].copy_from_slice(&Sha256::digest(page));
+    }
+
+    // Embedded signature superblob
+    let mut superblob = vec![0u8; superblob_header_len];
+    let mut offset = 0;
+    for value in [
+        CSMAGIC_EMBEDDED_SIGNATURE,
+        signature_len as u32,
+        2,
+        CSSLOT_CODEDIRECTORY,
+        superblob_header_len as u32,
+        CSSLOT_REQUIREMENTS,
+        (superblob_header_len + cd_len) as u32,
+    ] {
+        superblob.gwrite_with(value, &mut offset, BE)?;
+    }
+    signed.extend(superblob);
+    signed.extend(cd);
+    signed.extend(EMPTY_REQUIREMENTS);
+    Ok(signed)
+}
+
+/// Ad-hoc sign a Mach-O file or every slice of a fat file
+///
+/// When `all_slices` is false, only the slices that need a signature are signed,
+/// see [`needs_signature`]. Existing identifiers are kept, `identifier` is used for
+/// slices that weren't signed before. Linker signatures stay linker signatures, like
+/// Apple's tools do after editing a linked file.
+pub(crate) fn adhoc_sign(data: &[u8], identifier: &str, all_slices: bool) -> Result<Vec<u8>> {
+    sign_slices(data, all_slices, &|existing| match existing {
+        Some((ident, flags)) => (ident, CS_ADHOC | (flags & CS_LINKER_SIGNED)),
+        None => (identifier.to_string(), CS_ADHOC),
+    })
+}
+
+/// Sign the slices of a Mach-O or fat file, `signature` maps the existing identifier and code
+/// directory flags of a slice to the ones to sign it with
+fn sign_slices(
+    data: &[u8],
+    all_slices: bool,
+    signature: &dyn Fn(Option<(String, u32)>) -> (String, u32),
+) -> Result<Vec<u8>> {
+    let sign_slice = |slice: &[u8]| -> Result<Option<Vec<u8>>> {
+        let macho = MachO::parse(slice, 0).context("failed to parse Mach-O")?;
+        if !all_slices && !needs_signature(&macho) {
+            return Ok(None);
+        }
+        let (identifier, flags) = signature(existing_signature(slice, &macho));
+        sign_macho(slice, &identifier, flags).map(Some)
+    };
+
+    if peek(data, 0)? != FAT_MAGIC {
+        return Ok(sign_slice(data)?.unwrap_or_else(|| data.to_vec()));
+    }
+    let multi = MultiArch::new(data)?;
+    let arches: Vec<FatArch> = multi.iter_arches().collect::<std::result::Result<_, _>>()?;
+    let mut slices = Vec::with_capacity(arches.len());
+    for arch in &arches {
+        let slice = arch.slice(data);
+        slices.push(sign_slice(slice)?.unwrap_or_else(|| slice.to_vec()));
+    }
+
+    // Signing changes the slice sizes, lay out the slices again keeping their alignment
+    let mut fat = vec![0u8; SIZEOF_FAT_HEADER + arches.len() * SIZEOF_FAT_ARCH];
+    fat.pwrite_with(
+        FatHeader {
+            magic: FAT_MAGIC,
+            nfat_arch: arches.len() as u32,
+        },
+        0,
+        BE,
+    )?;
+    for (index, (arch, slice)) in arches.iter().zip(&slices).enumerate() {
+        let offset = fat.len().next_multiple_of(1 << arch.align);
+        fat.resize(offset, 0);
+        fat.extend_from_slice(slice);
+        let arch = FatArch {
+            offset: offset as u32,
+            size: slice.len() as u32,
+            ..*arch
+        };
+        fat.pwrite_with(arch, SIZEOF_FAT_HEADER + index * SIZEOF_FAT_ARCH, BE)?;
+    }
+    Ok(fat)
+}
+
+/// Whether every slice that needs a signature already has one
+pub(crate) fn is_adhoc_signed(data: &[u8]) -> Result<bool> {
+    let check = |slice: &[u8]| -> Result<bool> {
+        let macho = MachO::parse(slice, 0).context("failed to parse Mach-O")?;
+        Ok(!needs_signature(&macho) || code_signature_command(&macho).is_some())
+    };
+    if peek(data, 0)? != FAT_MAGIC {
+        return check(data);
+    }
+    for arch in MultiArch::new(data)?.iter_arches() {
+        if !check(arch?.slice(data))? {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+/// Ad-hoc sign a linked output whose slices are missing a required signature
+///
+/// Older zig versions don't sign arm64 outputs, files that aren't Mach-O are ignored.
+pub(crate) fn sign_linked_output(path: &Path) -> Result<()> {
+    let data = fs_err::read(path)?;
+    if goblin::mach::Mach::parse(&data).is_err() || is_adhoc_signed(&data)? {
+        return Ok(());
+    }
+    let data = adhoc_sign(&data, &default_identifier(path), false)
+        .with_context(|| format!("failed to sign '{}'", path.display()))?;
+    fs_err::write(path, data)?;
+    Ok(())
+}
+
+/// Default signing identifier for a file, its file name
+pub(crate) fn default_identifier(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+fn sign_file(path: &Path, args: &Args) -> Result<()> {
+    let data = fs_err::read(path)?;
+    if !args.force {
+        let already_signed = |slice: &[u8]| -> Result<bool> {
+            let macho = MachO::parse(slice, 0).context("failed to parse Mach-O")?;
+            // Like Apple's codesign, signatures added by the linker are replaced without --force
+            Ok(existing_signature(slice, &macho)
+                .is_some_and(|(_, flags)| flags & CS_LINKER_SIGNED == 0))
+        };
+        let signed = if peek(&data, 0)? == FAT_MAGIC {
+            let mut signed = false;
+            for arch in MultiArch::new(&data)?.iter_arches() {
+                signed |= already_signed(arch?.slice(&data))?;
+            }
+            signed
+        } else {
+            already_signed(&data)?
+        };
+        if signed {
+            bail!("is already signed, use --force to replace the existing signature");
+        }
+    }
+    // An explicit signature replaces the linker signature
+    let identifier = args.identifier.clone();
+    let default = default_identifier(path);
+    let data = sign_slices(&data, true, &|existing| {
+        let ident = identifier
+            .clone()
+            .or(existing.map(|(ident, _)| ident))
+            .unwrap_or_else(|| default.clone());
+        (ident, CS_ADHOC)
+    })?;
+    fs_err::write(path, data)?;
+    Ok(())
+}
+
+/// Execute codesign with the given arguments
+pub fn execute(args: impl IntoIterator<Item = impl Into<OsString>>) -> Result<()> {
+    let args: Vec<String> = args
+        .into_iter()
+        .map(|a| a.into().to_string_lossy().into_owned())
+        .collect();
+    let parsed = parse_args(&args)?;
+    for input in &parsed.inputs {
+        sign_file(Path::new(input), &parsed)
+            .with_context(|| format!("failed to sign '{input}'"))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{copy_fixture, fixtures_dir};
+
+    /// For fat binaries, get the slices, otherwise the whole file
+    fn slices(data: &[u8]) -> Vec<&[u8]> {
+        if peek(data, 0).unwrap() != FAT_MAGIC {
+            return vec![data];
+        }
+        MultiArch::new(data)
+            .unwrap()
+            .iter_arches()
+            .map(|arch| arch.unwrap().slice(data))
+            .collect()
+    }
+
+    /// Check the code directory hashes of a signed slice, returning its identifier
+    fn verify_signature(slice: &[u8]) -> String {
+        verify_signature_flags(slice).0
+    }
+
+    /// Check the code directory hashes of a signed slice, returning its identifier and flags
+    fn verify_signature_flags(slice: &[u8]) -> (String, u32) {
+        let macho = MachO::parse(slice, 0).unwrap();
+        let sig = code_signature_command(&macho).expect("no LC_CODE_SIGNATURE");
+        assert_eq!((sig.dataoff + sig.datasize) as usize, slice.len());
+        let linkedit = macho
+            .segments
+            .iter()
+            .find(|seg| seg.name().unwrap() == "__LINKEDIT")
+            .unwrap();
+        assert_eq!(
+            (linkedit.fileoff + linkedit.filesize) as usize,
+            slice.len(),
+            "__LINKEDIT must cover the signature"
+        );
+
+        let blob = &slice[sig.dataoff as usize..];
+        assert_eq!(
+            blob.pread_with::<u32>(0, BE).unwrap(),
+            CSMAGIC_EMBEDDED_SIGNATURE
+        );
+        let cd_offset: u32 = blob.pread_with(16, BE).unwrap();
+        let cd = &blob[cd_offset as usize..];
+        assert_eq!(cd.pread_with::<u32>(0, BE).unwrap(), CSMAGIC_CODEDIRECTORY);
+        let flags = cd.pread_with::<u32>(12, BE).unwrap();
+        assert_eq!(flags & !CS_LINKER_SIGNED, CS_ADHOC);
+        let hash_offset = cd.pread_with::<u32>(16, BE).unwrap() as usize;
+        let n_code_slots = cd.pread_with::<u32>(28, BE).unwrap() as usize;
+        let code_limit = cd.pread_with::<u32>(32, BE).unwrap() as usize;
+        assert_eq!(code_limit, sig.dataoff as usize);
+        assert_eq!(n_code_slots, code_limit.div_ceil(CS_PAGE_SIZE));
+        for (index, page) in slice[..code_limit].chunks(CS_PAGE_SIZE).enumerate() {
+            let slot = hash_offset + index * CS_HASH_SIZE;
+            assert_eq!(
+                &cd[slot..slot + CS_HASH_SIZE],
+                Sha256::digest(page).as_slice(),
+                "hash of page {index} doesn't match"
+            );
+        }
+        let requirements_slot = hash_offset - 2 * CS_HASH_SIZE;
+        assert_eq!(
+            &cd[requirements_slot..requirements_slot + CS_HASH_SIZE],
+            Sha256::digest(EMPTY_REQUIREMENTS).as_slice()
+        );
+        existing_signature(slice, &macho).unwrap()
+    }
+
+    #[test]
+    fn test_sign_unsigned_x86_64() {
+        let tmp = copy_fixture("test_x86_64.dylib");
+        let data = fs_err::read(tmp.path()).unwrap();
+        assert!(code_signature_command(&MachO::parse(&data, 0).unwrap()).is_none());
+        // x86_64 slices don't need a signature
+        assert!(is_adhoc_signed(&data).unwrap());
+        assert_eq!(adhoc_sign(&data, "test", false).unwrap(), data);
+
+        execute(["-s", "-", tmp.path().to_str().unwrap()]).unwrap();
+        let data = fs_err::read(tmp.path()).unwrap();
+        assert_eq!(
+            verify_signature(&data),
+            default_identifier(tmp.path()),
+            "identifier defaults to the file name"
+        );
+    }
+
+    #[test]
+    fn test_resign_aarch64() {
+        let data = fs_err::read(fixtures_dir().join("test_aarch64.dylib")).unwrap();
+        let macho = MachO::parse(&data, 0).unwrap();
+        let (identifier, flags) = existing_signature(&data, &macho).unwrap();
+        assert_eq!(
+            flags,
+            CS_ADHOC | CS_LINKER_SIGNED,
+            "zig output is linker-signed"
+        );
+
+        let signed = adhoc_sign(&data, "other", false).unwrap();
+        assert_eq!(verify_signature_flags(&signed), (identifier.clone(), flags));
+        // Signing is deterministic
+        assert_eq!(adhoc_sign(&signed, "other", false).unwrap(), signed);
+
+        // Signing with codesign replaces the linker signature
+        let tmp = copy_fixture("test_aarch64.dylib");
+        execute(["-s", "-", tmp.path().to_str().unwrap()]).unwrap();
+        let data = fs_err::read(tmp.path()).unwrap();
+        assert_eq!(verify_signature_flags(&data), (identifier, CS_ADHOC));
+    }
+
+    #[test]
+    fn test_sign_universal2() {
+        let tmp = copy_fixture("test_universal2.dylib");
+        execute([
+            "-f",
+            "-s",
+            "-",
+            "-i",
+            "com.example.test",
+            tmp.path().to_str().unwrap(),
+        ])
+        .unwrap();
+
+        let data = fs_err::read(tmp.path()).unwrap();
+        let multi = MultiArch::new(&data).unwrap();
+        for arch in multi.iter_arches() {
+            let arch = arch.unwrap();
+            assert_eq!(arch.offset % (1 << arch.align), 0);
+            assert_eq!(verify_signature(arch.slice(&data)), "com.example.test");
+        }
+    }
+
+    #[test]
+    fn test_already_signed_requires_force() {
+        let tmp = copy_fixture("test_x86_64.dylib");
+        let path = tmp.path().to_str().unwrap();
+        execute(["-s", "-", path]).unwrap();
+        let err = execute(["-s", "-", path]).unwrap_err();
+        assert!(format!("{err:#}").contains("is already signed"));
+        execute(["--force", "--sign", "-", "--identifier", "renamed", path]).unwrap();
+        let data = fs_err::read(tmp.path()).unwrap();
+        assert_eq!(verify_signature(&data), "renamed");
+    }
+
+    #[test]
+    fn test_parse_args() {
+        let args = |args: &[&str]| args.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        assert!(parse_args(&args(&["-s", "-", "-v", "a", "b"])).is_ok());
+        let err = parse_args(&args(&["-s", "Developer ID", "a"])).unwrap_err();
+        assert!(err.to_string().contains("only ad-hoc signing"));
+        assert!(parse_args(&args(&["a"])).is_err());
+        assert!(parse_args(&args(&["-s", "-"])).is_err());
+        assert!(parse_args(&args(&["-s", "-", "--deep", "a"])).is_err());
+    }
+
+    #[test]
+    fn test_install_name_tool_resigns() {
+        for fixture in ["test_aarch64.dylib", "test_universal2.dylib"] {
+            let tmp = copy_fixture(fixture);
+            crate::macos::install_name_tool::execute([
+                "-id",
+                "@rpath/libtest.dylib",
+                tmp.path().to_str().unwrap(),
+            ])
+            .unwrap();
+            let data = fs_err::read(tmp.path()).unwrap();
+            for slice in slices(&data) {
+                let macho = MachO::parse(slice, 0).unwrap();
+                if needs_signature(&macho) {
+                    // Still replaceable by codesign without --force
+                    let (_, flags) = verify_signature_flags(slice);
+                    assert_eq!(flags, CS_ADHOC | CS_LINKER_SIGNED);
+                }
+            }
+            assert!(is_adhoc_signed(&data).unwrap());
+        }
+    }
+}
@@ -0,0 +1,404 @@
+//! Restrict the exports of a linked Mach-O file to an `-exported_symbols_list`
+//!
+//! zig < 0.16 doesn't support `-exported_symbols_list`, so every global symbol ends up
+//! exported. We rebuild the export trie dyld resolves symbols from with only the listed
+//! symbols after linking instead, and turn the unlisted symbols into private externals in
+//! the symbol table like ld64 does.
+//!
+//! The rewrite happens in place, so the file doesn't shrink: the code of the unlisted
+//! symbols and the unused part of the export trie are kept.
+
+use std::collections::HashSet;
+use std::path::Path;
+
+use anyhow::{Context, Result, bail};
+use fs_err as fs;
+use goblin::mach::fat::FAT_MAGIC;
+use goblin::mach::load_command::CommandVariant;
+use goblin::mach::symbols::{N_EXT, N_PEXT, N_SECT, N_STAB, N_TYPE};
+use goblin::mach::{MachO, MultiArch, peek};
+use scroll::Uleb128;
+
+use super::codesign;
+
+/// Parse an ld64 `-exported_symbols_list` file, one symbol per line
+///
+/// Blank lines and `#` comments are ignored, wildcards aren't supported.
+pub(crate) fn parse_exported_symbols_list(content: &str) -> HashSet<String> {
+    content
+        .lines()
+        .map(|line| line.split('#').next().unwrap_or_default().trim())
+        .filter(|line| !line.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// Only export `symbols` from the linked Mach-O file at `path`
+///
+/// Rewritten slices are signed again, files that aren't Mach-O are ignored.
+pub(crate) fn restrict_exported_symbols(path: &Path, symbols: &HashSet<String>) -> Result<()> {
+    let mut data = fs::read(path)?;
+    if goblin::mach::Mach::parse(&data).is_err() {
+        return Ok(());
+    }
+    restrict_exports(&mut data, symbols)
+        .with_context(|| format!("Failed to rewrite the export trie of `{}`", path.display()))?;
+    let data = codesign::adhoc_sign(&data, &codesign::default_identifier(path), false)
+        .with_context(|| format!("failed to sign '{}'", path.display()))?;
+    fs::write(path, data)?;
+    Ok(())
+}
+
+/// Rewrite the export trie and symbol table of every slice in place, keeping only `symbols`
+fn restrict_exports(data: &mut [u8], symbols: &HashSet<String>) -> Result<()> {
+    if peek(data, 0)? != FAT_MAGIC {
+        return restrict_slice_exports(data, symbols);
+    }
+    let ranges: Vec<_> = MultiArch::new(data)?
+        .iter_arches()
+        .map(|arch| arch.map(|arch| arch.offset as usize..(arch.offset + arch.size) as usize))
+        .collect::<std::result::Result<_, _>>()?;
+    for range in ranges {
+        let slice = data.get_mut(range).context("fat slice is out of bounds")?;
+        restrict_slice_exports(slice, symbols)?;
+    }
+    Ok(())
+}
+
+fn restrict_slice_exports(slice: &mut [u8], symbols: &HashSet<String>) -> Result<()> {
+    hide_unlisted_symbols(slice, symbols)?;
+    let Some((offset, size)) = export_trie_location(slice)? else {
+        return Ok(());
+    };
+    let trie = slice
+        .get_mut(offset..offset + size)
+        .context("export trie is out of bounds")?;
+    let exports = read_export_trie(trie)?
+        .into_iter()
+        .filter(|(name, _)| str::from_utf8(name).is_ok_and(|name| symbols.contains(name)))
+        .collect();
+    let new_trie = build_export_trie(exports);
+    if new_trie.len() > trie.len() {
+        bail!(
+            "the rebuilt export trie ({} bytes) doesn't fit in the original one ({} bytes)",
+            new_trie.len(),
+            trie.len()
+        );
+    }
+    // The trie is walked from its root, padding it with zeros keeps the `__LINKEDIT` layout
+    trie.fill(0);
+    trie[..new_trie.len()].copy_from_slice(&new_trie);
+    Ok(())
+}
+
+/// Turn the defined external symbols that aren't in `symbols` into private externals
+///
+/// The symbols stay in the extdef range of `LC_DYSYMTAB`, which dyld doesn't use to resolve
+/// symbols when there is an export trie.
+fn hide_unlisted_symbols(slice: &mut [u8], symbols: &HashSet<String>) -> Result<()> {
+    let macho = MachO::parse(slice, 0).context("failed to parse Mach-O")?;
+    let nlist_size = if macho.is_64 { 16 } else { 12 };
+    let Some(symtab) = macho.load_commands.iter().find_map(|lc| match &lc.command {
+        CommandVariant::Symtab(cmd) => Some(*cmd),
+        _ => None,
+    }) else {
+        return Ok(());
+    };
+    let strtab = slice
+        .get(symtab.stroff as usize..(symtab.stroff + symtab.strsize) as usize)
+        .context("string table is out of bounds")?
+        .to_vec();
+    for index in 0..symtab.nsyms as usize {
+        let entry = symtab.symoff as usize + index * nlist_size;
+        let nlist = slice
+            .get_mut(entry..entry + nlist_size)
+            .context("symbol table is out of bounds")?;
+        // `n_type` follows the 32-bit `n_strx` in both nlist layouts
+        let n_type = nlist[4];
+        if n_type & N_STAB != 0 || n_type & N_EXT == 0 || n_type & N_TYPE != N_SECT {
+            continue;
+        }
+        let n_strx = u32::from_le_bytes(nlist[..4].try_into().unwrap()) as usize;
+        let name = strtab
+            .get(n_strx..)
+            .and_then(|rest| rest.split(|&b| b == 0).next())
+            .context("symbol name is out of bounds")?;
+        if !str::from_utf8(name).is_ok_and(|name| symbols.contains(name)) {
+            nlist[4] = (n_type & !N_EXT) | N_PEXT;
+        }
+    }
+    Ok(())
+}
+
+/// File offset and size of the export trie from `LC_DYLD_INFO(_ONLY)` or `LC_DYLD_EXPORTS_TRIE`
+fn export_trie_location(slice: &[u8]) -> Result<Option<(usize, usize)>> {
+    let macho = MachO::parse(slice, 0).context("failed to parse Mach-O")?;
+    Ok(macho
+        .load_commands
+        .iter()
+        .find_map(|lc| match &lc.command {
+            CommandVariant::DyldInfo(cmd) | CommandVariant::DyldInfoOnly(cmd) => {
+                Some((cmd.export_off, cmd.export_size))
+            }
+            CommandVariant::DyldExportsTrie(cmd) => Some((cmd.dataoff, cmd.datasize)),
+            _ => None,
+        })
+        .filter(|&(_, size)| size > 0)
+        .map(|(offset, size)| (offset as usize, size as usize)))
+}
+
+/// A symbol name and the raw terminal info (flags and address) of its trie node
+type Export = (Vec<u8>, Vec<u8>);
+
+/// Read every export of an export trie
+fn read_export_trie(trie: &[u8]) -> Result<Vec<Export>> {
+    let mut exports = Vec::new();
+    let mut visited = HashSet::new();
+    let mut pending = vec![(Vec::new(), 0usize)];
+    while let Some((prefix, node)) = pending.pop() {
+        if !visited.insert(node) {
+            bail!("export trie has a loop at offset {node:#x}");
+        }
+        let mut offset = node;
+        let terminal_size = Uleb128::read(trie, &mut offset)? as usize;
+        if terminal_size > 0 {
+            let info = trie
+                .get(offset..offset + terminal_size)
+                .context("export trie terminal is out of bounds")?;
+            exports.push((prefix.clone(), info.to_vec()));
+            offset += terminal_size;
+        }
+        let children = *trie
+            .get(offset)
+            .context("export trie node is out of bounds")?;
+        offset += 1;
+        for _ in 0..children {
+            let label_len = trie
+                .get(offset..)
+                .and_then(|rest| rest.iter().position(|&b| b == 0))
+                .context("export trie edge label is not terminated")?;
+            let mut name = prefix.clone();
+            name.extend_from_slice(&trie[offset..offset + label_len]);
+            offset += label_len + 1;
+            let child = Uleb128::read(trie, &mut offset)? as usize;
+            pending.push((name, child));
+        }
+    }
+    exports.sort();
+    Ok(exports)
+}
+
+/// A node of the export trie being built
+#[derive(Default)]
+struct Node {
+    terminal: Option<Vec<u8>>,
+    edges: Vec<(Vec<u8>, usize)>,
+}
+
+/// Build an export trie, laid out in depth-first order like ld64 does
+fn build_export_trie(mut exports: Vec<Export>) -> Vec<u8> {
+    exports.sort();
+    let mut nodes = vec![Node::default()];
+    insert_exports(&mut nodes, 0, exports);
+
+    // Node sizes depend on the ULEB128 encoded offsets of their children, grow the offsets
+    // until they are stable
+    let mut offsets = vec![0usize; nodes.len()];
+    loop {
+        let mut offset = 0;
+        let mut changed = false;
+        for (index, node) in nodes.iter().enumerate() {
+            if offsets[index] != offset {
+                offsets[index] = offset;
+                changed = true;
+            }
+            offset += encode_node(node, &offsets).len();
+        }
+        if !changed {
+            break;
+        }
+    }
+    nodes
+        .iter()
+        .flat_map(|node| encode_node(node, &offsets))
+        .collect()
+}
+
+/// Insert sorted `exports` with their common prefix already stripped below `parent`
+fn insert_exports(nodes: &mut Vec<Node>, parent: usize, exports: Vec<Export>) {
+    let mut groups: Vec<Vec<Export>> = Vec::new();
+    for (name, info) in exports {
+        if name.is_empty() {
+            nodes[parent].terminal = Some(info);
+            continue;
+        }
+        match groups.last_mut() {
+            Some(group) if group[0].0[0] == name[0] => group.push((name, info)),
+            _ => groups.push(vec![(name, info)]),
+        }
+    }
+    for group in groups {
+        let prefix_len = group
+            .iter()
+            .map(|(name, _)| common_prefix_len(name, &group[0].0))
+            .min()
+            .unwrap_or_default();
+        let label = group[0].0[..prefix_len].to_vec();
+        let child = nodes.len();
+        nodes.push(Node::default());
+        nodes[parent].edges.push((label, child));
+        let group = group
+            .into_iter()
+            .map(|(name, info)| (name[prefix_len..].to_vec(), info))
+            .collect();
+        insert_exports(nodes, child, group);
+    }
+}
+
+fn common_prefix_len(a: &[u8], b: &[u8]) -> usize {
+    a.iter().zip(b).take_while(|(a, b)| a == b).count()
+}
+
+fn encode_node(node: &Node, offsets: &[usize]) -> Vec<u8> {
+    let mut buf = Vec::new();
+    match &node.terminal {
+        Some(info) => {
+            write_uleb128(&mut buf, info.len() as u64);
+            buf.extend_from_slice(info);
+        }
+        None => buf.push(0),
+    }
+    // dyld reads the child count as a single byte, edges start with distinct bytes
+    buf.push(node.edges.len() as u8);
+    for (label, child) in &node.edges {
+        buf.extend_from_slice(label);
+        buf.push(0);
+        write_uleb128(&mut buf, offsets[*child] as u64);
+    }
+    buf
+}
+
+fn write_uleb128(buf: &mut Vec<u8>, mut value: u64) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            buf.push(byte);
+            break;
+        }
+        buf.push(byte | 0x80);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::fixture;
+
+    /// The exported symbol names of each slice
+    fn exported_names(data: &[u8]) -> Vec<Vec<String>> {
+        let names = |slice: &[u8]| -> Vec<String> {
+            let Some((offset, size)) = export_trie_location(slice).unwrap() else {
+                return Vec::new();
+            };
+            read_export_trie(&slice[offset..offset + size])
+                .unwrap()
+                .into_iter()
+                .map(|(name, _)| String::from_utf8(name).unwrap())
+                .collect()
+        };
+        if peek(data, 0).unwrap() != FAT_MAGIC {
+            return vec![names(data)];
+        }
+        MultiArch::new(data)
+            .unwrap()
+            .iter_arches()
+            .map(|arch| names(arch.unwrap().slice(data)))
+            .collect()
+    }
+
+    /// The defined external symbol names of each slice, like `nm -gU`
+    fn external_names(data: &[u8]) -> Vec<Vec<String>> {
+        let names = |slice: &[u8]| -> Vec<String> {
+            let macho = MachO::parse(slice, 0).unwrap();
+            let mut names: Vec<_> = macho
+                .symbols()
+                .map(|sym| sym.unwrap())
+                .filter(|(_, nlist)| nlist.is_global() && !nlist.is_undefined())
+                .map(|(name, _)| name.to_string())
+                .collect();
+            names.sort();
+            names
+        };
+        if peek(data, 0).unwrap() != FAT_MAGIC {
+            return vec![names(data)];
+        }
+        MultiArch::new(data)
+            .unwrap()
+            .iter_arches()
+            .map(|arch| names(arch.unwrap().slice(data)))
+            .collect()
+    }
+
+    fn export(name: &str, address: u8) -> Export {
+        (name.as_bytes().to_vec(), vec![0, address])
+    }
+
+    #[test]
+    fn test_parse_exported_symbols_list() {
+        let symbols = parse_exported_symbols_list("_foo\n\n  _bar  \n# comment\n_baz # trailing\n");
+        let expected: HashSet<_> = ["_foo", "_bar", "_baz"].map(str::to_string).into();
+        assert_eq!(symbols, expected);
+    }
+
+    #[test]
+    fn test_export_trie_round_trip() {
+        let exports = vec![
+            export("_add", 1),
+            export("_add_assign", 2),
+            export("_addr", 3),
+            export("_main", 4),
+            export("__mh_execute_header", 5),
+        ];
+        let mut sorted = exports.clone();
+        sorted.sort();
+        let trie = build_export_trie(exports);
+        assert_eq!(read_export_trie(&trie).unwrap(), sorted);
+        // Root with a single `_` edge to the node at offset 5
+        assert_eq!(trie[..6], [0, 1, b'_', 0, 5, 0]);
+
+        assert_eq!(build_export_trie(Vec::new()), [0, 0]);
+        // Enough exports to need two byte ULEB128 child offsets
+        let many: Vec<_> = (0..100)
+            .map(|i| export(&format!("_sym{i:03}"), i))
+            .collect();
+        assert_eq!(
+            read_export_trie(&build_export_trie(many.clone())).unwrap(),
+            many
+        );
+    }
+
+    #[test]
+    fn test_restrict_exports() {
+        for name in ["test_x86_64.dylib", "test_universal2.dylib"] {
+            let data = fixture(name);
+            let slices = exported_names(&data);
+            assert!(
+                slices
+                    .iter()
+                    .all(|names| names.contains(&"_add".to_string()))
+            );
+
+            let mut kept = data.clone();
+            restrict_exports(&mut kept, &["_add".to_string()].into()).unwrap();
+            assert_eq!(kept.len(), data.len());
+            assert!(exported_names(&kept).iter().all(|names| names == &["_add"]));
+            assert!(external_names(&kept).iter().all(|names| names == &["_add"]));
+
+            let mut removed = data.clone();
+            restrict_exports(&mut removed, &HashSet::new()).unwrap();
+            assert!(exported_names(&removed).iter().all(Vec::is_empty));
+            assert!(external_names(&removed).iter().all(Vec::is_empty));
+        }
+    }
+}
@@ -3,10 +3,15 @@
 //! Supports modifying Mach-O load commands:
 //! - `-id name`: Change LC_ID_DYLIB
 //! - `-change old new`: Change LC_LOAD_DYLIB / LC_LOAD_WEAK_DYLIB / etc.
-//! - `-add_rpath new`: Add LC_RPATH
+//! - `-add_rpath new`: Add LC_RPATH after the existing ones
+//! - `-prepend_rpath new`: Add LC_RPATH before the existing ones
 //! - `-delete_rpath old`: Delete LC_RPATH
+//! - `-delete_all_rpaths`: Delete all LC_RPATH
 //! - `-rpath old new`: Change LC_RPATH
 //!
+//! Options can be repeated and applied to several input files. Slices that were
+//! signed and arm64 slices are ad-hoc signed again after editing.
+//!
 //! Based on the approach from [arwen-macho](https://github.com/nichmor/arwen).
 //!
 //! TODO: Replace this custom implementation with the `arwen-macho` crate
@@ -25,6 +30,8 @@ use goblin::mach::load_command::{
 use goblin::mach::{MachO, MultiArch, parse_magic_and_ctx, peek};
 use scroll::Pwrite;
 
+use super::codesign;
+
 /// Parsed command-line arguments for install_name_tool
 #[derive(Debug, Default)]
 struct Args {
@@ -32,8 +39,10 @@ struct Args {
     changes: Vec<(String, String)>,
     rpaths: Vec<(String, String)>,
     add_rpaths: Vec<String>,
+    prepend_rpaths: Vec<String>,
     delete_rpaths: Vec<String>,
-    input: Option<String>,
+    delete_all_rpaths: bool,
+    inputs: Vec<String>,
 }
 
 fn parse_args(args: &[String]) -> Result<Args> {
@@ -73,6 +82,13 @@ fn parse_args(args: &[String]) -> Result<Args> {
                 parsed.add_rpaths.push(args[i + 1].clone());
                 i += 2;
             }
+            "-prepend_rpath" => {
+                if i + 1 >= args.len() {
+                    bail!("-prepend_rpath requires an argument");
+                }
+                parsed.prepend_rpaths.push(args[i + 1].clone());
+                i += 2;
+            }
             "-delete_rpath" => {
                 if i + 1 >= args.len() {
                     bail!("-delete_rpath requires an argument");
@@ -80,27 +96,37 @@ fn parse_args(args: &[String]) -> Result<Args> {
                 parsed.delete_rpaths.push(args[i + 1].clone());
                 i += 2;
             }
+            "-delete_all_rpaths" => {
+                parsed.delete_all_rpaths = true;
+                i += 1;
+            }
             arg if arg.starts_with('-') => {
                 bail!("unknown option: {arg}");
             }
             _ => {
-                if parsed.input.is_some() {
-                    bail!("multiple input files not supported");
-                }
-                parsed.input = Some(args[i].clone());
+                parsed.inputs.push(args[i].clone());
                 i += 1;
             }
         }
     }
-    if parsed.input.is_none() {
+    if parsed.inputs.is_empty() {
         bail!("no input file specified");
     }
+    // Like Apple's install_name_tool, reject options that would add the same rpath twice
+    let mut new_rpaths: Vec<&str> = parsed.rpaths.iter().map(|(_, new)| new.as_str()).collect();
+    new_rpaths.extend(parsed.add_rpaths.iter().map(String::as_str));
+    new_rpaths.extend(parsed.prepend_rpaths.iter().map(String::as_str));
+    for (i, rpath) in new_rpaths.iter().enumerate() {
+        if new_rpaths[..i].contains(rpath) {
+            bail!("more than one option would add the rpath: {rpath}");
+        }
+    }
     Ok(parsed)
 }
 
 // -- Header helpers --
 
-fn header_size(ctx: container::Ctx) -> usize {
+pub(crate) fn header_size(ctx: container::Ctx) -> usize {
     if ctx.container.is_big() {
         SIZEOF_HEADER_64
     } else {
@@ -117,6 +143,26 @@ fn align_to_ctx(size: usize, ctx: container::Ctx) -> usize {
     }
 }
 
+/// File offset of the first section, the load commands must end before it
+///
+/// Falls back to the first non-empty segment for binaries without sections.
+fn load_commands_limit(data: &[u8]) -> Result<usize> {
+    let macho = MachO::parse(data, 0).context("failed to re-parse Mach-O")?;
+    let mut limit = data.len();
+    for segment in macho.segments.iter() {
+        for (section, _) in segment.sections()? {
+            // Zero-fill sections like `__bss` have no file contents
+            if section.offset != 0 && section.size != 0 {
+                limit = limit.min(section.offset as usize);
+            }
+        }
+        if segment.fileoff != 0 && segment.filesize != 0 {
+            limit = limit.min(segment.fileoff as usize);
+        }
+    }
+    Ok(limit)
+}
+
 // -- Load command manipulation --
 
 /// Remove a load command from the buffer and update the header.
@@ -144,7 +190,7 @@ fn remove_load_command(
 }
 
 /// Insert a new load command at the given offset and update the header.
-fn insert_load_command(
+pub(crate) fn insert_load_command(
     buffer: &mut Vec<u8>,
     header: &mut Header,
     ctx: container::Ctx,
@@ -152,6 +198,16 @@ fn insert_load_command(
     cmd_data: &[u8],
 ) -> Result<()> {
     let new_cmd_size = cmd_data.len() as u32;
+    let cmds_end = header_size(ctx) + header.sizeofcmds as usize;
+    let limit = load_commands_limit(buffer)?;
+    if cmds_end + cmd_data.len() > limit {
+        bail!(
+            "not enough header padding for the updated load commands ({} bytes needed, {} bytes available), \
+             relink with -headerpad_max_install_names",
+            cmd_data.len(),
+            limit.saturating_sub(cmds_end)
+        );
+    }
 
     header.ncmds += 1;
     header.sizeofcmds += new_cmd_size;
@@ -161,12 +217,9 @@ fn insert_load_command(
     buffer.extend_from_slice(cmd_data);
     buffer.extend(tail);
 
-    // Drain surplus padding to keep file size stable
+    // Drain the padding taken by the new command to keep file size stable
     let drain_start = header_size(ctx) + header.sizeofcmds as usize;
-    let drain_end = drain_start + new_cmd_size as usize;
-    if drain_end <= buffer.len() {
-        buffer.drain(drain_start..drain_end);
-    }
+    buffer.drain(drain_start..drain_start + new_cmd_size as usize);
 
     buffer.pwrite_with(*header, 0, ctx)?;
     Ok(())
@@ -247,6 +300,43 @@ fn read_rpath_path<'a>(data: &'a [u8], lc: &LoadCommand, rpath_cmd: &RpathComman
     std::str::from_utf8(&data[path_offset..path_end]).unwrap_or("")
 }
 
+/// Offsets, sizes and paths of the LC_RPATH commands in a single Mach-O slice
+fn rpath_commands(data: &[u8]) -> Result<Vec<(usize, usize, String)>> {
+    let macho = MachO::parse(data, 0).context("failed to re-parse Mach-O")?;
+    let mut rpaths = Vec::new();
+    for lc in &macho.load_commands {
+        if let CommandVariant::Rpath(ref rpath_cmd) = lc.command {
+            let path = read_rpath_path(data, lc, rpath_cmd);
+            rpaths.push((lc.offset, lc.command.cmdsize(), path.to_string()));
+        }
+    }
+    Ok(rpaths)
+}
+
+/// Insert LC_RPATH commands for `new_rpaths` at `offset`, or after the last load command
+fn insert_rpaths(data: &mut Vec<u8>, new_rpaths: &[String], offset: Option<usize>) -> Result<()> {
+    let mut offset = offset;
+    for new_rpath in new_rpaths {
+        if rpath_commands(data)?
+            .iter()
+            .any(|(_, _, path)| path == new_rpath)
+        {
+            bail!("option would duplicate path, file already has LC_RPATH for: {new_rpath}");
+        }
+        let macho = MachO::parse(data, 0).context("failed to re-parse Mach-O")?;
+        let (_, maybe_ctx) = parse_magic_and_ctx(data, 0)?;
+        let ctx = maybe_ctx.context("could not determine endianness")?;
+        let mut header = macho.header;
+
+        let insert_offset = offset.unwrap_or(header_size(ctx) + header.sizeofcmds as usize);
+        let (_, new_cmd_buf) = build_rpath_command(new_rpath, ctx)?;
+        insert_load_command(data, &mut header, ctx, insert_offset, &new_cmd_buf)?;
+        // Keep the inserted rpaths in command line order
+        offset = offset.map(|offset| offset + new_cmd_buf.len());
+    }
+    Ok(())
+}
+
 // -- Single Mach-O processing --
 
 /// Process a single Mach-O binary. The buffer must start at the Mach-O header (offset 0).
@@ -284,7 +374,6 @@ fn process_single_macho(data: &mut Vec<u8>, args: &Args) -> Result<()> {
         let ctx = maybe_ctx.context("could not determine endianness")?;
         let mut header = macho.header;
 
-        let mut found = false;
         for lc in &macho.load_commands {
             let dylib_cmd = match &lc.command {
                 CommandVariant::LoadDylib(cmd)
@@ -300,12 +389,19 @@ fn process_single_macho(data: &mut Vec<u8>, args: &Args) -> Result<()> {
                 let (_, new_cmd_buf) = build_dylib_command(new_name, dylib_cmd, ctx)?;
                 remove_load_command(data, &mut header, ctx, lc.offset, cmdsize)?;
                 insert_load_command(data, &mut header, ctx, lc.offset, &new_cmd_buf)?;
-                found = true;
                 break;
             }
         }
-        if !found {
-            bail!("no LC_LOAD_DYLIB with name '{old_name}' found");
+        // Like Apple's install_name_tool, dylibs that aren't loaded are ignored
+    }
+
+    // -delete_all_rpaths
+    if args.delete_all_rpaths {
+        // Remove from the last one so that the offsets of the others stay valid
+        for (offset, cmdsize, _) in rpath_commands(data)?.into_iter().rev() {
+            let macho = MachO::parse(data, 0).context("failed to re-parse Mach-O")?;
+            let mut header = macho.header;
+            remove_load_command(data, &mut header, ctx, offset, cmdsize)?;
         }
     }
 
@@ -316,6 +412,13 @@ fn process_single_macho(data: &mut Vec<u8>, args: &Args) -> Result<()> {
         let ctx = maybe_ctx.context("could not determine endianness")?;
         let mut header = macho.header;
 
+        if old_rpath != new_rpath
+            && rpath_commands(data)?
+                .iter()
+                .any(|(_, _, path)| path == new_rpath)
+        {
+            bail!("option would duplicate path, file already has LC_RPATH for: {new_rpath}");
+        }
         let mut found = false;
         for lc in &macho.load_commands {
             if let CommandVariant::Rpath(ref rpath_cmd) = lc.command {
@@ -360,16 +463,11 @@ fn process_single_macho(data: &mut Vec<u8>, args: &Args) -> Result<()> {
     }
 
     // -add_rpath
-    for new_rpath in &args.add_rpaths {
-        let macho = MachO::parse(data, 0).context("failed to re-parse Mach-O")?;
-        let (_, maybe_ctx) = parse_magic_and_ctx(data, 0)?;
-        let ctx = maybe_ctx.context("could not determine endianness")?;
-        let mut header = macho.header;
+    insert_rpaths(data, &args.add_rpaths, None)?;
 
-        let insert_offset = header_size(ctx) + header.sizeofcmds as usize;
-        let (_, new_cmd_buf) = build_rpath_command(new_rpath, ctx)?;
-        insert_load_command(data, &mut header, ctx, insert_offset, &new_cmd_buf)?;
-    }
+    // -prepend_rpath: insert before the first LC_RPATH so that they're searched first
+    let first_rpath = rpath_commands(data)?.first().map(|(offset, _, _)| *offset);
+    insert_rpaths(data, &args.prepend_rpaths, first_rpath)?;
 
     Ok(())
 }
@@ -377,8 +475,9 @@ fn process_single_macho(data: &mut Vec<u8>, args: &Args) -> Result<()> {
 // -- Top-level file processing --
 
 fn process_file(path: &Path, args: &Args) -> Result<()> {
-    let mut data =
+    let original =
         fs_err::read(path).with_context(|| format!("failed to read '{}'", path.display()))?;
+    let mut data = original.clone();
 
     let magic = peek(&data, 0)?;
 
@@ -403,6 +502,12 @@ fn process_file(path: &Path, args: &Args) -> Result<()> {
         }
     }
 
+    if data == original {
+        return Ok(());
+    }
+    // Editing load commands invalidates existing signatures, and arm64 slices must be signed
+    let data = codesign::adhoc_sign(&data, &codesign::default_identifier(path), false)?;
+
     fs_err::write(path, &data).with_context(|| format!("failed to write '{}'", path.display()))?;
 
     Ok(())
@@ -415,26 +520,17 @@ pub fn execute(args: impl IntoIterator<Item = impl Into<OsString>>) -> Result<()
         .map(|a| a.into().to_string_lossy().into_owned())
         .collect();
     let parsed = parse_args(&args)?;
-    let input = parsed.input.as_ref().unwrap();
-    process_file(Path::new(input), &parsed)
+    for input in &parsed.inputs {
+        process_file(Path::new(input), &parsed)
+            .with_context(|| format!("failed to modify '{input}'"))?;
+    }
+    Ok(())
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::path::PathBuf;
-
-    fn fixtures_dir() -> PathBuf {
-        PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures")
-    }
-
-    /// Copy a fixture to a temp file for modification
-    fn copy_fixture(name: &str) -> tempfile::NamedTempFile {
-        let src = fixtures_dir().join(name);
-        let mut tmp = tempfile::NamedTempFile::new().unwrap();
-        std::io::copy(&mut fs_err::File::open(src).unwrap(), &mut tmp).unwrap();
-        tmp
-    }
+    use crate::test_utils::{copy_fixture, fixtures_dir};
 
     /// Read the LC_ID_DYLIB name from a single Mach-O slice
     fn read_id(data: &[u8]) -> Option<String> {
@@ -593,6 +689,55 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_prepend_rpath_x86_64() {
+        let tmp = copy_fixture("test_x86_64.dylib");
+        execute([
+            "-prepend_rpath",
+            "/first/rpath",
+            "-prepend_rpath",
+            "/second/rpath",
+            tmp.path().to_str().unwrap(),
+        ])
+        .unwrap();
+
+        let data = fs_err::read(tmp.path()).unwrap();
+        assert_eq!(
+            read_rpaths(&data),
+            ["/first/rpath", "/second/rpath", "/old/rpath"]
+        );
+    }
+
+    #[test]
+    fn test_prepend_rpath_universal2() {
+        let tmp = copy_fixture("test_universal2.dylib");
+        execute([
+            "-prepend_rpath",
+            "/first/rpath",
+            tmp.path().to_str().unwrap(),
+        ])
+        .unwrap();
+
+        let data = fs_err::read(tmp.path()).unwrap();
+        for (offset, size) in fat_slices(&data) {
+            let slice = &data[offset..offset + size];
+            assert_eq!(read_rpaths(slice), ["/first/rpath", "/old/rpath"]);
+        }
+    }
+
+    #[test]
+    fn test_delete_all_rpaths_universal2() {
+        let tmp = copy_fixture("test_universal2.dylib");
+        execute(["-add_rpath", "/added/rpath", tmp.path().to_str().unwrap()]).unwrap();
+        execute(["-delete_all_rpaths", tmp.path().to_str().unwrap()]).unwrap();
+
+        let data = fs_err::read(tmp.path()).unwrap();
+        for (offset, size) in fat_slices(&data) {
+            let slice = &data[offset..offset + size];
+            assert!(read_rpaths(slice).is_empty());
+        }
+    }
+
     // -- Combined operations --
 
     #[test]
@@ -641,4 +786,107 @@ mod tests {
         ]);
         assert!(result.is_err());
     }
+
+    #[test]
+    fn test_multiple_input_files() {
+        let aarch64 = copy_fixture("test_aarch64.dylib");
+        let universal2 = copy_fixture("test_universal2.dylib");
+        execute([
+            "-delete_all_rpaths",
+            "-add_rpath",
+            "@loader_path",
+            aarch64.path().to_str().unwrap(),
+            universal2.path().to_str().unwrap(),
+        ])
+        .unwrap();
+
+        let data = fs_err::read(aarch64.path()).unwrap();
+        assert_eq!(read_rpaths(&data), ["@loader_path"]);
+        let data = fs_err::read(universal2.path()).unwrap();
+        for (offset, size) in fat_slices(&data) {
+            let slice = &data[offset..offset + size];
+            assert_eq!(read_rpaths(slice), ["@loader_path"]);
+        }
+    }
+
+    #[test]
+    fn test_change_nonexistent_dylib_is_ignored() {
+        let tmp = copy_fixture("test_aarch64.dylib");
+        let before = fs_err::read(tmp.path()).unwrap();
+        execute([
+            "-change",
+            "/nonexistent.dylib",
+            "/new.dylib",
+            tmp.path().to_str().unwrap(),
+        ])
+        .unwrap();
+        assert_eq!(fs_err::read(tmp.path()).unwrap(), before);
+    }
+
+    #[test]
+    fn test_duplicate_rpath_fails() {
+        for option in ["-add_rpath", "-prepend_rpath"] {
+            let tmp = copy_fixture("test_universal2.dylib");
+            let before = fs_err::read(tmp.path()).unwrap();
+            let err = execute([option, "/old/rpath", tmp.path().to_str().unwrap()]).unwrap_err();
+            assert!(
+                format!("{err:#}")
+                    .contains("would duplicate path, file already has LC_RPATH for: /old/rpath")
+            );
+            assert_eq!(fs_err::read(tmp.path()).unwrap(), before);
+        }
+
+        let tmp = copy_fixture("test_aarch64.dylib");
+        execute(["-add_rpath", "/added/rpath", tmp.path().to_str().unwrap()]).unwrap();
+        let result = execute([
+            "-rpath",
+            "/old/rpath",
+            "/added/rpath",
+            tmp.path().to_str().unwrap(),
+        ]);
+        assert!(result.is_err());
+
+        let err = execute([
+            "-add_rpath",
+            "/new/rpath",
+            "-prepend_rpath",
+            "/new/rpath",
+            tmp.path().to_str().unwrap(),
+        ])
+        .unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("more than one option would add the rpath: /new/rpath")
+        );
+    }
+
+    #[test]
+    fn test_not_enough_header_padding_fails() {
+        for fixture in ["test_aarch64.dylib", "test_universal2.dylib"] {
+            let tmp = copy_fixture(fixture);
+            let before = fs_err::read(tmp.path()).unwrap();
+            let long_rpath = format!("/{}", "x".repeat(64 * 1024));
+            let err =
+                execute(["-add_rpath", &long_rpath, tmp.path().to_str().unwrap()]).unwrap_err();
+            let err = format!("{err:#}");
+            assert!(err.contains("not enough header padding"));
+            assert!(err.contains("relink with -headerpad_max_install_names"));
+            assert_eq!(fs_err::read(tmp.path()).unwrap(), before);
+        }
+    }
+
+    #[test]
+    fn test_load_commands_limit() {
+        let data = fs_err::read(fixtures_dir().join("test_aarch64.dylib")).unwrap();
+        let macho = MachO::parse(&data, 0).unwrap();
+        let limit = load_commands_limit(&data).unwrap();
+        let text = macho
+            .segments
+            .iter()
+            .find(|segment| segment.name().unwrap() == "__TEXT")
+            .unwrap();
+        let (first_section, _) = &text.sections().unwrap()[0];
+        assert_eq!(limit, first_section.offset as usize);
+        assert!(limit > SIZEOF_HEADER_64 + macho.header.sizeofcmds as usize);
+    }
 }
@@ -0,0 +1,420 @@
+//! A minimal implementation of `lipo` for cross-compilation.
+//!
+//! Supports creating and inspecting fat Mach-O files and fat static libraries:
+//! - `-create input... -output file`: Create a fat file from thin or fat inputs
+//! - `-thin arch -output file`: Extract a single architecture as a thin file
+//! - `-extract arch... -output file`: Create a fat file with only the given architectures
+//! - `-remove arch... -output file`: Create a fat file without the given architectures
+//! - `-info`: Print whether the inputs are fat and their architectures
+//! - `-archs`: Print the architectures of the inputs
+
+use std::ffi::OsString;
+use std::path::Path;
+
+use anyhow::{Context, Result, bail};
+use fat_macho::FatWriter;
+use goblin::Object;
+use goblin::mach::constants::cputype::{
+    CPU_SUBTYPE_MASK, CpuSubType, CpuType, get_arch_from_flag, get_arch_name_from_types,
+};
+use goblin::mach::{Mach, MultiArch};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Operation {
+    Create,
+    Thin(String),
+    Extract(Vec<String>),
+    Remove(Vec<String>),
+    Info,
+    Archs,
+}
+
+/// Parsed command-line arguments for lipo
+#[derive(Debug, Default)]
+struct Args {
+    operation: Option<Operation>,
+    output: Option<String>,
+    inputs: Vec<String>,
+}
+
+impl Args {
+    fn set_operation(&mut self, operation: Operation) -> Result<()> {
+        match (&mut self.operation, operation) {
+            (None, operation) => self.operation = Some(operation),
+            (Some(Operation::Extract(archs)), Operation::Extract(more))
+            | (Some(Operation::Remove(archs)), Operation::Remove(more)) => archs.extend(more),
+            _ => {
+                bail!("only one of -create, -thin, -extract, -remove, -info or -archs can be used")
+            }
+        }
+        Ok(())
+    }
+}
+
+fn parse_args(args: &[String]) -> Result<Args> {
+    let mut parsed = Args::default();
+    let mut i = 0;
+    while i < args.len() {
+        let arg = args[i].as_str();
+        let value = || {
+            args.get(i + 1)
+                .cloned()
+                .with_context(|| format!("{arg} requires an argument"))
+        };
+        match arg {
+            "-create" => parsed.set_operation(Operation::Create)?,
+            "-info" => parsed.set_operation(Operation::Info)?,
+            "-archs" => parsed.set_operation(Operation::Archs)?,
+            "-thin" => {
+                parsed.set_operation(Operation::Thin(value()?))?;
+                i += 1;
+            }
+            "-extract" => {
+                parsed.set_operation(Operation::Extract(vec![value()?]))?;
+                i += 1;
+            }
+            "-remove" => {
+                parsed.set_operation(Operation::Remove(vec![value()?]))?;
+                i += 1;
+            }
+            "-output" | "-o" => {
+                parsed.output = Some(value()?);
+                i += 1;
+            }
+            arg if arg.starts_with('-') => {
+                bail!("unknown option: {arg}");
+            }
+            _ => parsed.inputs.push(args[i].clone()),
+        }
+        i += 1;
+    }
+    let Some(operation) = &parsed.operation else {
+        bail!("one of -create, -thin, -extract, -remove, -info or -archs must be specified");
+    };
+    if parsed.inputs.is_empty() {
+        bail!("no input files specified");
+    }
+    match operation {
+        Operation::Info | Operation::Archs => {}
+        Operation::Create => {
+            if parsed.output.is_none() {
+                bail!("no output file specified for -create");
+            }
+        }
+        Operation::Thin(_) | Operation::Extract(_) | Operation::Remove(_) => {
+            if parsed.output.is_none() {
+                bail!("no output file specified");
+            }
+            if parsed.inputs.len() != 1 {
+                bail!("only one input file can be specified with -thin, -extract or -remove");
+            }
+        }
+    }
+    Ok(parsed)
+}
+
+/// The name of an architecture, like `arm64` or `x86_64`
+fn arch_name(cputype: CpuType, cpusubtype: CpuSubType) -> String {
+    get_arch_name_from_types(cputype, cpusubtype & !CPU_SUBTYPE_MASK)
+        .map(str::to_string)
+        .unwrap_or_else(|| format!("unknown({cputype},{cpusubtype})"))
+}
+
+/// Check that `name` is an architecture lipo knows about
+fn check_arch_flag(name: &str) -> Result<()> {
+    if get_arch_from_flag(name).is_none() {
+        bail!("unknown architecture specification flag: {name}");
+    }
+    Ok(())
+}
+
+/// The architecture of a thin Mach-O file or static library
+fn thin_arch(data: &[u8]) -> Result<String> {
+    match Object::parse(data)? {
+        Object::Mach(Mach::Binary(macho)) => {
+            Ok(arch_name(macho.header.cputype, macho.header.cpusubtype))
+        }
+        Object::Archive(archive) => {
+            for member in archive.members() {
+                if let Ok(Object::Mach(Mach::Binary(macho))) =
+                    Object::parse(archive.extract(member, data)?)
+                {
+                    return Ok(arch_name(macho.header.cputype, macho.header.cpusubtype));
+                }
+            }
+            bail!("archive doesn't contain any Mach-O object files")
+        }
+        _ => bail!("not a Mach-O file or static library"),
+    }
+}
+
+/// A fat file slice with its architecture name
+type Slice<'a> = (String, &'a [u8]);
+
+/// The slices of a fat file, `None` for thin files
+fn fat_slices(data: &[u8]) -> Result<Option<Vec<Slice<'_>>>> {
+    let Ok(Mach::Fat(multi)) = Mach::parse(data) else {
+        return Ok(None);
+    };
+    let slices = fat_arches(&multi)?
+        .into_iter()
+        .map(|(name, arch)| (name, arch.slice(data)))
+        .collect();
+    Ok(Some(slices))
+}
+
+fn fat_arches(multi: &MultiArch) -> Result<Vec<(String, goblin::mach::fat::FatArch)>> {
+    multi
+        .iter_arches()
+        .map(|arch| {
+            let arch = arch?;
+            Ok((arch_name(arch.cputype, arch.cpusubtype), arch))
+        })
+        .collect()
+}
+
+fn write_fat<'a>(slices: impl IntoIterator<Item = &'a [u8]>, output: &str) -> Result<()> {
+    let mut fat = FatWriter::new();
+    for slice in slices {
+        fat.add(slice.to_vec())?;
+    }
+    fat.write_to_file(output)
+        .with_context(|| format!("failed to write '{output}'"))?;
+    Ok(())
+}
+
+fn lipo(args: &Args) -> Result<String> {
+    let inputs = args
+        .inputs
+        .iter()
+        .map(|input| fs_err::read(Path::new(input)).map(|data| (input.as_str(), data)))
+        .collect::<Result<Vec<_>, _>>()?;
+    let operation = args.operation.as_ref().expect("operation is required");
+    let output = args.output.as_deref().unwrap_or_default();
+    let mut stdout = String::new();
+    match operation {
+        Operation::Create => {
+            let mut seen: Vec<(String, &str)> = Vec::new();
+            for (input, data) in &inputs {
+                let archs = match fat_slices(data)? {
+                    Some(slices) => slices.into_iter().map(|(name, _)| name).collect(),
+                    None => vec![thin_arch(data).with_context(|| input.to_string())?],
+                };
+                for arch in archs {
+                    if let Some((_, other)) = seen.iter().find(|(seen, _)| *seen == arch) {
+                        bail!(
+                            "{input} and {other} have the same architectures ({arch}) and can't be in the same fat output file"
+                        );
+                    }
+                    seen.push((arch, input));
+                }
+            }
+            write_fat(inputs.iter().map(|(_, data)| data.as_slice()), output)?;
+        }
+        Operation::Thin(arch) => {
+            check_arch_flag(arch)?;
+            let (input, data) = &inputs[0];
+            let Some(slices) = fat_slices(data)? else {
+                bail!("input file ({input}) must be a fat file when the -thin option is specified");
+            };
+            let (_, slice) = slices
+                .iter()
+                .find(|(name, _)| name == arch)
+                .with_context(|| format!("fat input file ({input}) does not contain the specified architecture ({arch}) to thin it to"))?;
+            fs_err::write(output, slice)?;
+        }
+        Operation::Extract(archs) | Operation::Remove(archs) => {
+            let (input, data) = &inputs[0];
+            let Some(slices) = fat_slices(data)? else {
+                bail!(
+                    "input file ({input}) must be a fat file when the -extract or -remove option is specified"
+                );
+            };
+            for arch in archs {
+                check_arch_flag(arch)?;
+                if !slices.iter().any(|(name, _)| name == arch) {
+                    bail!(
+                        "-{} {arch} specified but fat file: {input} does not contain that architecture",
+                        if matches!(operation, Operation::Extract(_)) {
+                            "extract"
+                        } else {
+                            "remove"
+                        }
+                    );
+                }
+            }
+            let extract = matches!(operation, Operation::Extract(_));
+            let kept: Vec<&[u8]> = slices
+                .iter()
+                .filter(|(name, _)| archs.contains(name) == extract)
+                .map(|(_, slice)| *slice)
+                .collect();
+            if kept.is_empty() {
+                bail!("-remove's specified would result in an empty fat file");
+            }
+            write_fat(kept, output)?;
+        }
+        Operation::Info => {
+            for (input, data) in &inputs {
+                match fat_slices(data)? {
+                    Some(slices) => {
+                        let names: Vec<String> = slices.into_iter().map(|(name, _)| name).collect();
+                        stdout.push_str(&format!(
+                            "Architectures in the fat file: {input} are: {} \n",
+                            names.join(" ")
+                        ));
+                    }
+                    None => {
+                        let arch = thin_arch(data).with_context(|| input.to_string())?;
+                        stdout
+                            .push_str(&format!("Non-fat file: {input} is architecture: {arch}\n"));
+                    }
+                }
+            }
+        }
+        Operation::Archs => {
+            for (input, data) in &inputs {
+                let names = match fat_slices(data)? {
+                    Some(slices) => slices.into_iter().map(|(name, _)| name).collect(),
+                    None => vec![thin_arch(data).with_context(|| input.to_string())?],
+                };
+                stdout.push_str(&names.join(" "));
+                stdout.push('\n');
+            }
+        }
+    }
+    Ok(stdout)
+}
+
+/// Execute lipo with the given arguments
+pub fn execute(args: impl IntoIterator<Item = impl Into<OsString>>) -> Result<()> {
+    let args: Vec<String> = args
+        .into_iter()
+        .map(|a| a.into().to_string_lossy().into_owned())
+        .collect();
+    let parsed = parse_args(&args)?;
+    print!("{}", lipo(&parsed)?);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{archive, fixture, fixtures_dir};
+
+    fn run(args: &[&str]) -> Result<String> {
+        let args: Vec<String> = args.iter().map(|s| s.to_string()).collect();
+        lipo(&parse_args(&args)?)
+    }
+
+    #[test]
+    fn test_create_and_thin() {
+        let tmp = tempfile::tempdir().unwrap();
+        let fat = tmp.path().join("fat.dylib");
+        let fat = fat.to_str().unwrap();
+        let (aarch64, x86_64) = (
+            fixtures_dir().join("test_aarch64.dylib"),
+            fixtures_dir().join("test_x86_64.dylib"),
+        );
+        let (aarch64, x86_64) = (aarch64.to_str().unwrap(), x86_64.to_str().unwrap());
+        run(&["-create", aarch64, x86_64, "-output", fat]).unwrap();
+        assert_eq!(run(&["-archs", fat]).unwrap(), "x86_64 arm64\n");
+        assert_eq!(
+            run(&["-info", fat]).unwrap(),
+            format!("Architectures in the fat file: {fat} are: x86_64 arm64 \n")
+        );
+
+        let thin = tmp.path().join("thin.dylib");
+        let thin = thin.to_str().unwrap();
+        run(&[fat, "-thin", "arm64", "-output", thin]).unwrap();
+        assert_eq!(fs_err::read(thin).unwrap(), fixture("test_aarch64.dylib"));
+        assert_eq!(
+            run(&["-info", thin]).unwrap(),
+            format!("Non-fat file: {thin} is architecture: arm64\n")
+        );
+
+        let err = run(&[thin, "-thin", "arm64", "-output", fat]).unwrap_err();
+        assert!(err.to_string().contains("must be a fat file"));
+        let err = run(&[fat, "-thin", "armv7k", "-output", thin]).unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("does not contain the specified architecture")
+        );
+        let err = run(&["-create", aarch64, thin, "-output", fat]).unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("have the same architectures (arm64)")
+        );
+    }
+
+    #[test]
+    fn test_extract_and_remove() {
+        let tmp = tempfile::tempdir().unwrap();
+        let output = tmp.path().join("out.dylib");
+        let output = output.to_str().unwrap();
+        let universal2 = fixtures_dir().join("test_universal2.dylib");
+        let universal2 = universal2.to_str().unwrap();
+
+        run(&[universal2, "-extract", "x86_64", "-output", output]).unwrap();
+        assert_eq!(
+            run(&["-info", output]).unwrap(),
+            format!("Architectures in the fat file: {output} are: x86_64 \n")
+        );
+        run(&[universal2, "-remove", "x86_64", "-output", output]).unwrap();
+        assert_eq!(run(&["-archs", output]).unwrap(), "arm64\n");
+
+        let err = run(&[
+            universal2, "-remove", "x86_64", "-remove", "arm64", "-o", output,
+        ])
+        .unwrap_err();
+        assert!(err.to_string().contains("empty fat file"));
+        let err = run(&[universal2, "-extract", "i386", "-output", output]).unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("does not contain that architecture")
+        );
+    }
+
+    #[test]
+    fn test_static_libraries() {
+        let tmp = tempfile::tempdir().unwrap();
+        let aarch64 = tmp.path().join("libaarch64.a");
+        let x86_64 = tmp.path().join("libx86_64.a");
+        fs_err::write(&aarch64, archive(&fixture("test_aarch64.dylib"))).unwrap();
+        fs_err::write(&x86_64, archive(&fixture("test_x86_64.dylib"))).unwrap();
+        let (aarch64, x86_64) = (aarch64.to_str().unwrap(), x86_64.to_str().unwrap());
+        assert_eq!(
+            run(&["-archs", aarch64, x86_64]).unwrap(),
+            "arm64\nx86_64\n"
+        );
+
+        let fat = tmp.path().join("libfat.a");
+        let fat = fat.to_str().unwrap();
+        run(&["-create", "-output", fat, aarch64, x86_64]).unwrap();
+        assert_eq!(run(&["-archs", fat]).unwrap(), "x86_64 arm64\n");
+        let thin = tmp.path().join("libthin.a");
+        let thin = thin.to_str().unwrap();
+        run(&[fat, "-thin", "x86_64", "-output", thin]).unwrap();
+        assert_eq!(fs_err::read(thin).unwrap(), fs_err::read(x86_64).unwrap());
+    }
+
+    #[test]
+    fn test_parse_args() {
+        let args = |args: &[&str]| args.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        assert!(parse_args(&args(&["a"])).is_err());
+        assert!(parse_args(&args(&["-create", "a"])).is_err());
+        assert!(parse_args(&args(&["-info", "-archs", "a"])).is_err());
+        assert!(parse_args(&args(&["a", "b", "-thin", "arm64", "-output", "c"])).is_err());
+        let parsed = parse_args(&args(&[
+            "a", "-extract", "arm64", "-extract", "x86_64", "-o", "b",
+        ]))
+        .unwrap();
+        assert_eq!(
+            parsed.operation,
+            Some(Operation::Extract(vec![
+                "arm64".to_string(),
+                "x86_64".to_string()
+            ]))
+        );
+    }
+}
@@ -1,6 +1,27 @@
+pub(crate) mod build_version;
+pub mod codesign;
+pub(crate) mod exports;
 pub mod install_name_tool;
+#[cfg(feature = "universal2")]
+pub mod lipo;
+pub mod otool;
+pub mod sdk;
+pub mod tbd;
+
+use std::ffi::CStr;
+
+use anyhow::{Context, Result};
+use goblin::mach::load_command::LoadCommand;
 
 /// libiconv.tbd
 pub static LIBICONV_TBD: &str = include_str!("libiconv.tbd");
 /// libcharset.tbd
 pub static LIBCHARSET_TBD: &str = include_str!("libcharset.1.tbd");
+
+/// Read the NUL-terminated string at `offset` in a load command
+pub(crate) fn lc_str<'a>(data: &'a [u8], lc: &LoadCommand, offset: u32) -> Result<&'a str> {
+    data.get(lc.offset + offset as usize..)
+        .and_then(|bytes| CStr::from_bytes_until_nul(bytes).ok())
+        .and_then(|s| s.to_str().ok())
+        .context("invalid string in load command")
+}
@@ -0,0 +1,431 @@
+//! A minimal implementation of `otool` for cross-compilation.
+//!
+//! Supports printing Mach-O load commands in the same format as Apple's otool,
+//! which CMake's `GetPrerequisites` and `BundleUtilities` parse:
+//! - `-L`: Print the install name and the linked dylibs
+//! - `-D`: Print the install name
+//! - `-l`: Print the load commands
+//! - `-arch name`: Only print the given architecture of a fat file
+
+use std::ffi::OsString;
+use std::fmt::{self, Write as _};
+use std::path::Path;
+
+use anyhow::{Context, Result, bail};
+use goblin::mach::constants::cputype::get_arch_name_from_types;
+use goblin::mach::load_command::{CommandVariant, DylibCommand, LoadCommand, cmd_to_str};
+use goblin::mach::{Mach, MachO, SingleArch};
+
+/// Parsed command-line arguments for otool
+#[derive(Debug, Default)]
+struct Args {
+    install_name: bool,
+    dylibs: bool,
+    load_commands: bool,
+    arch: Option<String>,
+    inputs: Vec<String>,
+}
+
+fn parse_args(args: &[String]) -> Result<Args> {
+    let mut parsed = Args::default();
+    let mut i = 0;
+    while i < args.len() {
+        match args[i].as_str() {
+            "-D" => parsed.install_name = true,
+            "-L" => parsed.dylibs = true,
+            "-l" => parsed.load_commands = true,
+            "-arch" => {
+                if i + 1 >= args.len() {
+                    bail!("-arch requires an argument");
+                }
+                let arch = &args[i + 1];
+                if arch != "all" {
+                    parsed.arch = Some(arch.clone());
+                }
+                i += 1;
+            }
+            arg if arg.starts_with('-') => {
+                bail!("unknown option: {arg}");
+            }
+            _ => parsed.inputs.push(args[i].clone()),
+        }
+        i += 1;
+    }
+    if !parsed.install_name && !parsed.dylibs && !parsed.load_commands {
+        bail!("one of -L, -D or -l must be specified");
+    }
+    if parsed.inputs.is_empty() {
+        bail!("no input file specified");
+    }
+    Ok(parsed)
+}
+
+// -- Formatting helpers --
+
+/// Format a packed `xxxx.yy.zz` dylib version
+fn dylib_version(version: u32) -> String {
+    format!(
+        "{}.{}.{}",
+        version >> 16,
+        (version >> 8) & 0xff,
+        version & 0xff
+    )
+}
+
+/// Format a packed `xxxx.yy.zz` OS version, omitting a zero patch version
+fn os_version(version: u32) -> String {
+    let patch = version & 0xff;
+    if patch == 0 {
+        format!("{}.{}", version >> 16, (version >> 8) & 0xff)
+    } else {
+        format!("{}.{}.{patch}", version >> 16, (version >> 8) & 0xff)
+    }
+}
+
+/// Read the NUL-terminated string at `offset` in a load command, like otool for bad offsets
+fn lc_str<'a>(data: &'a [u8], lc: &LoadCommand, offset: u32) -> &'a str {
+    super::lc_str(data, lc, offset).unwrap_or("?(bad offset)")
+}
+
+fn fixed_str(bytes: &[u8; 16]) -> &str {
+    let len = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+    std::str::from_utf8(&bytes[..len]).unwrap_or("?")
+}
+
+fn dylib_command(command: &CommandVariant) -> Option<&DylibCommand> {
+    match command {
+        CommandVariant::IdDylib(cmd)
+        | CommandVariant::LoadDylib(cmd)
+        | CommandVariant::LoadWeakDylib(cmd)
+        | CommandVariant::ReexportDylib(cmd)
+        | CommandVariant::LazyLoadDylib(cmd)
+        | CommandVariant::LoadUpwardDylib(cmd) => Some(cmd),
+        _ => None,
+    }
+}
+
+// -- Printing --
+
+fn print_install_name(out: &mut String, data: &[u8], macho: &MachO) -> fmt::Result {
+    for lc in &macho.load_commands {
+        if let CommandVariant::IdDylib(cmd) = &lc.command {
+            writeln!(out, "{}", lc_str(data, lc, cmd.dylib.name))?;
+        }
+    }
+    Ok(())
+}
+
+fn print_dylibs(out: &mut String, data: &[u8], macho: &MachO) -> fmt::Result {
+    for lc in &macho.load_commands {
+        let Some(cmd) = dylib_command(&lc.command) else {
+            continue;
+        };
+        let kind = match lc.command {
+            CommandVariant::LoadWeakDylib(_) => ", weak",
+            CommandVariant::ReexportDylib(_) => ", reexport",
+            CommandVariant::LazyLoadDylib(_) => ", lazy",
+            CommandVariant::LoadUpwardDylib(_) => ", upward",
+            _ => "",
+        };
+        writeln!(
+            out,
+            "\t{} (compatibility version {}, current version {}{kind})",
+            lc_str(data, lc, cmd.dylib.name),
+            dylib_version(cmd.dylib.compatibility_version),
+            dylib_version(cmd.dylib.current_version),
+        )?;
+    }
+    Ok(())
+}
+
+fn print_load_commands(out: &mut String, data: &[u8], macho: &MachO) -> Result<()> {
+    let mut segments = macho.segments.iter();
+    for (index, lc) in macho.load_commands.iter().enumerate() {
+        writeln!(out, "Load command {index}")?;
+        let cmd = lc.command.cmd();
+        let cmdsize = lc.command.cmdsize();
+        match &lc.command {
+            CommandVariant::Segment32(_) | CommandVariant::Segment64(_) => {
+                let segment = segments.next().context("segment count mismatch")?;
+                // 32-bit addresses are printed with 8 hex digits
+                let width = if matches!(lc.command, CommandVariant::Segment64(_)) {
+                    18
+                } else {
+                    10
+                };
+                writeln!(out, "      cmd {}", cmd_to_str(cmd))?;
+                writeln!(out, "  cmdsize {cmdsize}")?;
+                writeln!(out, "  segname {}", fixed_str(&segment.segname))?;
+                writeln!(out, "   vmaddr {:#0width$x}", segment.vmaddr)?;
+                writeln!(out, "   vmsize {:#0width$x}", segment.vmsize)?;
+                writeln!(out, "  fileoff {}", segment.fileoff)?;
+                writeln!(out, " filesize {}", segment.filesize)?;
+                writeln!(out, "  maxprot {:#010x}", segment.maxprot)?;
+                writeln!(out, " initprot {:#010x}", segment.initprot)?;
+                writeln!(out, "   nsects {}", segment.nsects)?;
+                writeln!(out, "    flags {:#x}", segment.flags)?;
+                for (section, _) in segment.sections()? {
+                    writeln!(out, "Section")?;
+                    writeln!(out, "  sectname {}", fixed_str(&section.sectname))?;
+                    writeln!(out, "   segname {}", fixed_str(&section.segname))?;
+                    writeln!(out, "      addr {:#0width$x}", section.addr)?;
+                    writeln!(out, "      size {:#0width$x}", section.size)?;
+                    writeln!(out, "    offset {}", section.offset)?;
+                    writeln!(
+                        out,
+                        "     align 2^{} ({})",
+                        section.align,
+                        1u64.checked_shl(section.align).unwrap_or_default()
+                    )?;
+                    writeln!(out, "    reloff {}", section.reloff)?;
+                    writeln!(out, "    nreloc {}", section.nreloc)?;
+                    writeln!(out, "     flags {:#010x}", section.flags)?;
+                }
+            }
+            CommandVariant::IdDylib(dylib)
+            | CommandVariant::LoadDylib(dylib)
+            | CommandVariant::LoadWeakDylib(dylib)
+            | CommandVariant::ReexportDylib(dylib)
+            | CommandVariant::LazyLoadDylib(dylib)
+            | CommandVariant::LoadUpwardDylib(dylib) => {
+                writeln!(out, "          cmd {}", cmd_to_str(cmd))?;
+                writeln!(out, "      cmdsize {cmdsize}")?;
+                writeln!(
+                    out,
+                    "         name {} (offset {})",
+                    lc_str(data, lc, dylib.dylib.name),
+                    dylib.dylib.name
+                )?;
+                writeln!(out, "   time stamp {}", dylib.dylib.timestamp)?;
+                writeln!(
+                    out,
+                    "      current version {}",
+                    dylib_version(dylib.dylib.current_version)
+                )?;
+                writeln!(
+                    out,
+                    "compatibility version {}",
+                    dylib_version(dylib.dylib.compatibility_version)
+                )?;
+            }
+            CommandVariant::Rpath(rpath) => {
+                writeln!(out, "          cmd {}", cmd_to_str(cmd))?;
+                writeln!(out, "      cmdsize {cmdsize}")?;
+                writeln!(
+                    out,
+                    "         path {} (offset {})",
+                    lc_str(data, lc, rpath.path),
+                    rpath.path
+                )?;
+            }
+            CommandVariant::LoadDylinker(dylinker)
+            | CommandVariant::IdDylinker(dylinker)
+            | CommandVariant::DyldEnvironment(dylinker) => {
+                writeln!(out, "          cmd {}", cmd_to_str(cmd))?;
+                writeln!(out, "      cmdsize {cmdsize}")?;
+                writeln!(
+                    out,
+                    "         name {} (offset {})",
+                    lc_str(data, lc, dylinker.name),
+                    dylinker.name
+                )?;
+            }
+            CommandVariant::Uuid(uuid) => {
+                let u = &uuid.uuid;
+                writeln!(out, "     cmd {}", cmd_to_str(cmd))?;
+                writeln!(out, " cmdsize {cmdsize}")?;
+                writeln!(
+                    out,
+                    "    uuid {:02X}{:02X}{:02X}{:02X}-{:02X}{:02X}-{:02X}{:02X}-{:02X}{:02X}-{:02X}{:02X}{:02X}{:02X}{:02X}{:02X}",
+                    u[0],
+                    u[1],
+                    u[2],
+                    u[3],
+                    u[4],
+                    u[5],
+                    u[6],
+                    u[7],
+                    u[8],
+                    u[9],
+                    u[10],
+                    u[11],
+                    u[12],
+                    u[13],
+                    u[14],
+                    u[15]
+                )?;
+            }
+            CommandVariant::BuildVersion(build) => {
+                writeln!(out, "      cmd {}", cmd_to_str(cmd))?;
+                writeln!(out, "  cmdsize {cmdsize}")?;
+                writeln!(out, " platform {}", build.platform)?;
+                writeln!(out, "    minos {}", os_version(build.minos))?;
+                writeln!(out, "      sdk {}", os_version(build.sdk))?;
+                writeln!(out, "   ntools {}", build.ntools)?;
+            }
+            CommandVariant::VersionMinMacosx(version)
+            | CommandVariant::VersionMinIphoneos(version)
+            | CommandVariant::VersionMinTvos(version)
+            | CommandVariant::VersionMinWatchos(version) => {
+                writeln!(out, "      cmd {}", cmd_to_str(cmd))?;
+                writeln!(out, "  cmdsize {cmdsize}")?;
+                writeln!(out, "  version {}", os_version(version.version))?;
+                writeln!(out, "      sdk {}", os_version(version.sdk))?;
+            }
+            CommandVariant::Main(entry) => {
+                writeln!(out, "       cmd {}", cmd_to_str(cmd))?;
+                writeln!(out, "   cmdsize {cmdsize}")?;
+                writeln!(out, "  entryoff {}", entry.entryoff)?;
+                writeln!(out, " stacksize {}", entry.stacksize)?;
+            }
+            CommandVariant::Symtab(symtab) => {
+                writeln!(out, "     cmd {}", cmd_to_str(cmd))?;
+                writeln!(out, " cmdsize {cmdsize}")?;
+                writeln!(out, "  symoff {}", symtab.symoff)?;
+                writeln!(out, "   nsyms {}", symtab.nsyms)?;
+                writeln!(out, "  stroff {}", symtab.stroff)?;
+                writeln!(out, " strsize {}", symtab.strsize)?;
+            }
+            CommandVariant::CodeSignature(linkedit)
+            | CommandVariant::SegmentSplitInfo(linkedit)
+            | CommandVariant::FunctionStarts(linkedit)
+            | CommandVariant::DataInCode(linkedit)
+            | CommandVariant::DylibCodeSignDrs(linkedit)
+            | CommandVariant::LinkerOptimizationHint(linkedit)
+            | CommandVariant::DyldExportsTrie(linkedit)
+            | CommandVariant::DyldChainedFixups(linkedit) => {
+                writeln!(out, "      cmd {}", cmd_to_str(cmd))?;
+                writeln!(out, "  cmdsize {cmdsize}")?;
+                writeln!(out, "  dataoff {}", linkedit.dataoff)?;
+                writeln!(out, " datasize {}", linkedit.datasize)?;
+            }
+            _ => {
+                writeln!(out, "      cmd {}", cmd_to_str(cmd))?;
+                writeln!(out, "  cmdsize {cmdsize}")?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn print_macho(out: &mut String, data: &[u8], macho: &MachO, args: &Args) -> Result<()> {
+    if args.load_commands {
+        print_load_commands(out, data, macho)?;
+    }
+    if args.dylibs {
+        print_dylibs(out, data, macho)?;
+    }
+    if args.install_name {
+        print_install_name(out, data, macho)?;
+    }
+    Ok(())
+}
+
+fn otool(path: &str, data: &[u8], args: &Args) -> Result<String> {
+    let mut out = String::new();
+    match Mach::parse(data).with_context(|| format!("{path}: is not an object file"))? {
+        Mach::Binary(macho) => {
+            writeln!(out, "{path}:")?;
+            print_macho(&mut out, data, &macho, args)?;
+        }
+        Mach::Fat(multi) => {
+            for (arch, slice) in multi.iter_arches().zip(&multi) {
+                let arch = arch?;
+                let name = get_arch_name_from_types(arch.cputype(), arch.cpusubtype())
+                    .map(str::to_string)
+                    .unwrap_or_else(|| format!("cputype {}", arch.cputype()));
+                if args.arch.as_ref().is_some_and(|wanted| *wanted != name) {
+                    continue;
+                }
+                let SingleArch::MachO(macho) = slice? else {
+                    bail!("{path}: archives in fat files are not supported");
+                };
+                writeln!(out, "{path} (architecture {name}):")?;
+                print_macho(&mut out, arch.slice(data), &macho, args)?;
+            }
+        }
+    }
+    Ok(out)
+}
+
+/// Execute otool with the given arguments
+pub fn execute(args: impl IntoIterator<Item = impl Into<OsString>>) -> Result<()> {
+    let args: Vec<String> = args
+        .into_iter()
+        .map(|a| a.into().to_string_lossy().into_owned())
+        .collect();
+    let parsed = parse_args(&args)?;
+    for input in &parsed.inputs {
+        let data = fs_err::read(Path::new(input))?;
+        print!("{}", otool(input, &data, &parsed)?);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::fixture;
+
+    fn run(options: &[&str], name: &str) -> String {
+        let data = fixture(name);
+        let mut args: Vec<String> = options.iter().map(|s| s.to_string()).collect();
+        args.push(name.to_string());
+        otool(name, &data, &parse_args(&args).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_dylibs_aarch64() {
+        assert_eq!(
+            run(&["-L"], "test_aarch64.dylib"),
+            "test_aarch64.dylib:\n\
+             \t/usr/local/lib/test.dylib (compatibility version 0.0.0, current version 0.0.0)\n\
+             \t/usr/lib/libSystem.B.dylib (compatibility version 1.0.0, current version 1356.0.0)\n"
+        );
+    }
+
+    #[test]
+    fn test_install_name_universal2() {
+        assert_eq!(
+            run(&["-D"], "test_universal2.dylib"),
+            "test_universal2.dylib (architecture x86_64):\n\
+             /usr/local/lib/test.dylib\n\
+             test_universal2.dylib (architecture arm64):\n\
+             /usr/local/lib/test.dylib\n"
+        );
+        assert_eq!(
+            run(&["-D", "-arch", "arm64"], "test_universal2.dylib"),
+            "test_universal2.dylib (architecture arm64):\n/usr/local/lib/test.dylib\n"
+        );
+    }
+
+    #[test]
+    fn test_load_commands_x86_64() {
+        let output = run(&["-l"], "test_x86_64.dylib");
+        assert!(
+            output.starts_with("test_x86_64.dylib:\nLoad command 0\n      cmd LC_SEGMENT_64\n")
+        );
+        assert!(output.contains(
+            "          cmd LC_RPATH\n      cmdsize 24\n         path /old/rpath (offset 12)\n"
+        ));
+        assert!(output.contains("  sectname __text\n   segname __TEXT\n"));
+        assert!(output.contains(
+            "          cmd LC_ID_DYLIB\n      cmdsize 56\n         name /usr/local/lib/test.dylib (offset 24)\n"
+        ));
+    }
+
+    #[test]
+    fn test_errors() {
+        let args = |args: &[&str]| args.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        assert!(parse_args(&args(&["foo"])).is_err());
+        assert!(parse_args(&args(&["-L"])).is_err());
+        assert!(parse_args(&args(&["-x", "foo"])).is_err());
+        let err = otool(
+            "foo",
+            b"not a mach-o",
+            &parse_args(&args(&["-L", "foo"])).unwrap(),
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("is not an object file"));
+    }
+}
@@ -0,0 +1,478 @@
+//! Apple SDK directories
+
+use std::cmp::Ordering;
+use std::env;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result, bail};
+use fs_err as fs;
+use regex::Regex;
+
+use super::tbd;
+
+/// A directory of `.tbd` stubs standing in for the parts of an Apple SDK a project links against,
+/// laid out like a real SDK:
+///
+/// ```text
+/// usr/lib/libfoo.tbd
+/// System/Library/Frameworks/Foo.framework/Foo.tbd
+/// ```
+#[derive(Debug, Clone)]
+pub struct StubSdk {
+    root: PathBuf,
+}
+
+impl StubSdk {
+    pub fn new(root: impl Into<PathBuf>) -> Result<Self> {
+        let root = root.into();
+        if !root.is_dir() {
+            bail!("stub SDK directory '{}' does not exist", root.display());
+        }
+        Ok(Self { root })
+    }
+
+    /// Library search path, passed as `-L`
+    pub fn lib_dir(&self) -> PathBuf {
+        self.root.join("usr").join("lib")
+    }
+
+    /// Framework search path, passed as `-F`
+    pub fn frameworks_dir(&self) -> PathBuf {
+        self.root.join("System").join("Library").join("Frameworks")
+    }
+
+    /// Header search path for stubs that also ship headers
+    pub fn include_dir(&self) -> PathBuf {
+        self.root.join("usr").join("include")
+    }
+
+    /// All `.tbd` files of the stub SDK
+    pub fn tbd_files(&self) -> Result<Vec<PathBuf>> {
+        let mut files = tbd_files_in(&self.lib_dir())?;
+        let frameworks_dir = self.frameworks_dir();
+        if frameworks_dir.is_dir() {
+            for entry in fs::read_dir(&frameworks_dir)? {
+                let path = entry?.path();
+                if path.extension().is_some_and(|ext| ext == "framework") {
+                    files.extend(tbd_files_in(&path)?);
+                }
+            }
+        }
+        files.sort();
+        Ok(files)
+    }
+
+    /// Check that every stub provides symbols for `arch`, like `arm64`,
+    /// ld silently ignores stubs of other architectures and fails with undefined symbols instead
+    pub fn validate(&self, arch: &str) -> Result<()> {
+        for file in self.tbd_files()? {
+            let content = fs::read_to_string(&file)?;
+            let targets = tbd::parse_targets(&content)
+                .with_context(|| format!("failed to parse '{}'", file.display()))?;
+            if !targets
+                .iter()
+                .any(|target| tbd::target_arch(target) == arch)
+            {
+                bail!(
+                    "'{}' does not provide the {arch} architecture, it only has: {}",
+                    file.display(),
+                    targets.join(", ")
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+fn tbd_files_in(dir: &Path) -> Result<Vec<PathBuf>> {
+    if !dir.is_dir() {
+        return Ok(Vec::new());
+    }
+    let mut files = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_file() && path.extension().is_some_and(|ext| ext == "tbd") {
+            files.push(path);
+        }
+    }
+    Ok(files)
+}
+
+/// The architecture name Apple tools use for a Rust target, like `arm64` for `aarch64-apple-darwin`
+pub fn apple_arch(rust_target: &str) -> Option<&'static str> {
+    let arch = match rust_target.split('-').next()? {
+        "aarch64" => "arm64",
+        "arm64e" => "arm64e",
+        "arm64_32" => "arm64_32",
+        "x86_64" => "x86_64",
+        "x86_64h" => "x86_64h",
+        "i386" | "i686" => "i386",
+        "armv7k" => "armv7k",
+        "armv7s" => "armv7s",
+        _ => return None,
+    };
+    Some(arch)
+}
+
+/// A macOS SDK, validated by its `SDKSettings.json` or `SDKSettings.plist`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MacosSdk {
+    pub path: PathBuf,
+    /// SDK version, like `14.0`, `None` for an unvalidated `SDKROOT`
+    pub version: Option<String>,
+}
+
+impl MacosSdk {
+    /// Read the SDK settings at `path`, failing if it isn't a macOS SDK
+    pub fn new(path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        let (canonical_name, version) = read_sdk_settings(&path)?;
+        if !canonical_name.starts_with("macosx") {
+            bail!("{canonical_name} is not a macOS SDK");
+        }
+        Ok(Self {
+            path,
+            version: Some(version),
+        })
+    }
+
+    /// An SDK given by `SDKROOT`, which is used as is, the version is read if possible
+    pub fn from_sdkroot(path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        let version = read_sdk_settings(&path).ok().map(|(_, version)| version);
+        Self { path, version }
+    }
+}
+
+/// Whether the SDK at `path` is for the platform of an `xcrun` SDK name like `iphoneos`,
+/// according to its canonical name, like `iphoneos17.0`
+pub fn is_sdk_of(path: &Path, sdk_name: &str) -> bool {
+    read_sdk_settings(path).is_ok_and(|(canonical_name, _)| {
+        canonical_name
+            .strip_prefix(sdk_name)
+            .is_some_and(|version| !version.starts_with(|c: char| c.is_ascii_alphabetic()))
+    })
+}
+
+/// The canonical name, like `macosx14.0`, and version of an SDK
+fn read_sdk_settings(sdk: &Path) -> Result<(String, String)> {
+    let json = sdk.join("SDKSettings.json");
+    if json.is_file() {
+        let settings: serde_json::Value = serde_json::from_str(&fs::read_to_string(&json)?)
+            .with_context(|| format!("invalid {}", json.display()))?;
+        let field = |name: &str| {
+            settings[name]
+                .as_str()
+                .map(ToString::to_string)
+                .with_context(|| format!("{} doesn't have a `{name}`", json.display()))
+        };
+        return Ok((field("CanonicalName")?, field("Version")?));
+    }
+    let plist = sdk.join("SDKSettings.plist");
+    if plist.is_file() {
+        let content = fs::read(&plist)?;
+        if content.starts_with(b"bplist") {
+            bail!("binary {} is not supported", plist.display());
+        }
+        let content = String::from_utf8_lossy(&content);
+        let field = |name: &str| {
+            let re = Regex::new(&format!(r"<key>{name}</key>\s*<string>([^<]*)</string>")).unwrap();
+            re.captures(&content)
+                .map(|captures| captures[1].to_string())
+                .with_context(|| format!("{} doesn't have a `{name}`", plist.display()))
+        };
+        return Ok((field("CanonicalName")?, field("Version")?));
+    }
+    bail!("neither SDKSettings.json nor SDKSettings.plist found")
+}
+
+/// Conventional locations of macOS SDKs, directories containing `MacOSX*.sdk`
+/// or SDKs themselves
+pub fn default_search_paths() -> Vec<PathBuf> {
+    let mut paths = Vec::new();
+    // osxcross
+    if let Some(sdk) = env::var_os("OSXCROSS_SDK").filter(|v| !v.is_empty()) {
+        paths.push(PathBuf::from(sdk));
+    }
+    if let Some(target_dir) = env::var_os("OSXCROSS_TARGET_DIR").filter(|v| !v.is_empty()) {
+        paths.push(PathBuf::from(target_dir).join("SDK"));
+    }
+    if cfg!(target_os = "macos") {
+        paths.push("/Library/Developer/CommandLineTools/SDKs".into());
+        paths.push(
+            "/Applications/Xcode.app/Contents/Developer/Platforms/MacOSX.platform/Developer/SDKs"
+                .into(),
+        );
+    } else {
+        paths.push("/opt".into());
+        paths.push("/opt/osxcross/target/SDK".into());
+        paths.push("/usr/local/osxcross/target/SDK".into());
+    }
+    paths
+}
+
+/// Find the macOS SDK with the highest version matching `version` in `search_paths`
+///
+/// Returns `None` if no valid SDK was found and none was asked for, invalid SDKs are only
+/// warned about then, as the conventional locations may have broken ones.
+/// Otherwise fails listing the SDKs found.
+pub fn find_macos_sdk(
+    search_paths: &[PathBuf],
+    version: Option<&str>,
+    required: bool,
+) -> Result<Option<MacosSdk>> {
+    let mut candidates: Vec<PathBuf> = Vec::new();
+    for search_path in search_paths {
+        if search_path.join("SDKSettings.json").is_file()
+            || search_path.join("SDKSettings.plist").is_file()
+        {
+            candidates.push(search_path.clone());
+            continue;
+        }
+        let Ok(entries) = fs::read_dir(search_path) else {
+            continue;
+        };
+        let mut found: Vec<PathBuf> = entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| {
+                path.file_name()
+                    .and_then(|name| name.to_str())
+                    .is_some_and(|name| name.starts_with("MacOSX") && name.ends_with(".sdk"))
+            })
+            .collect();
+        found.sort();
+        candidates.extend(found);
+    }
+    // Xcode has `MacOSX.sdk` and a versioned symlink to it
+    let mut seen = Vec::new();
+    candidates.retain(|path| {
+        let canonical = fs::canonicalize(path).unwrap_or_else(|_| path.clone());
+        if seen.contains(&canonical) {
+            false
+        } else {
+            seen.push(canonical);
+            true
+        }
+    });
+
+    let mut valid = Vec::new();
+    let mut found = Vec::new();
+    let mut invalid = Vec::new();
+    for path in candidates {
+        match MacosSdk::new(&path) {
+            Ok(sdk) => {
+                let sdk_version = sdk.version.as_deref().unwrap_or_default();
+                found.push(format!("{} ({sdk_version})", path.display()));
+                if version.is_none_or(|version| version_matches(sdk_version, version)) {
+                    valid.push(sdk);
+                }
+            }
+            Err(err) => invalid.push(format!("{} (invalid: {err:#})", path.display())),
+        }
+    }
+    if let Some(sdk) = valid.into_iter().max_by(|a, b| {
+        compare_versions(
+            a.version.as_deref().unwrap_or_default(),
+            b.version.as_deref().unwrap_or_default(),
+        )
+    }) {
+        return Ok(Some(sdk));
+    }
+    if version.is_none() && !required {
+        for sdk in &invalid {
+            eprintln!("cargo-zigbuild: ignoring macOS SDK {sdk}");
+        }
+        return Ok(None);
+    }
+    found.extend(invalid);
+    let wanted = match version {
+        Some(version) => format!("macOS SDK {version}"),
+        None => "valid macOS SDK".to_string(),
+    };
+    if found.is_empty() {
+        bail!(
+            "no {wanted} found in {}",
+            search_paths
+                .iter()
+                .map(|path| path.display().to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+    }
+    bail!("no {wanted} found, found:\n  {}", found.join("\n  "))
+}
+
+fn version_components(version: &str) -> Vec<u32> {
+    version
+        .split('.')
+        .map(|component| component.parse().unwrap_or_default())
+        .collect()
+}
+
+/// Whether an SDK version matches a requested version, `14` matches `14.2`,
+/// but `14.2` doesn't match `14`
+fn version_matches(sdk_version: &str, requested: &str) -> bool {
+    let sdk_version = version_components(sdk_version);
+    version_components(requested)
+        .iter()
+        .enumerate()
+        .all(|(i, component)| sdk_version.get(i).copied().unwrap_or_default() == *component)
+}
+
+fn compare_versions(a: &str, b: &str) -> Ordering {
+    version_components(a).cmp(&version_components(b))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::macos::LIBICONV_TBD;
+
+    #[test]
+    fn test_stub_sdk() {
+        let tmp = tempfile::tempdir().unwrap();
+        let sdk = StubSdk::new(tmp.path()).unwrap();
+        assert!(sdk.tbd_files().unwrap().is_empty());
+
+        let framework = sdk.frameworks_dir().join("CoreFoundation.framework");
+        fs::create_dir_all(&framework).unwrap();
+        fs::create_dir_all(sdk.lib_dir()).unwrap();
+        fs::write(sdk.lib_dir().join("libiconv.tbd"), LIBICONV_TBD).unwrap();
+        fs::write(sdk.lib_dir().join("README"), "").unwrap();
+        fs::write(
+            framework.join("CoreFoundation.tbd"),
+            "--- !tapi-tbd\ntbd-version: 4\ntargets: [ x86_64-macos ]\n",
+        )
+        .unwrap();
+        assert_eq!(
+            sdk.tbd_files().unwrap(),
+            [
+                framework.join("CoreFoundation.tbd"),
+                sdk.lib_dir().join("libiconv.tbd")
+            ]
+        );
+        sdk.validate("x86_64").unwrap();
+        let err = sdk.validate("arm64").unwrap_err().to_string();
+        assert!(err.contains("CoreFoundation.tbd' does not provide the arm64 architecture"));
+        assert!(err.ends_with("it only has: x86_64-macos"));
+
+        assert!(StubSdk::new(tmp.path().join("missing")).is_err());
+    }
+
+    #[test]
+    fn test_apple_arch() {
+        assert_eq!(apple_arch("aarch64-apple-darwin"), Some("arm64"));
+        assert_eq!(apple_arch("x86_64h-apple-darwin"), Some("x86_64h"));
+        assert_eq!(apple_arch("arm64e-apple-darwin"), Some("arm64e"));
+        assert_eq!(apple_arch("riscv64gc-unknown-linux-gnu"), None);
+    }
+
+    fn write_sdk(dir: &Path, name: &str, canonical_name: &str, version: &str) -> PathBuf {
+        let sdk = dir.join(name);
+        fs::create_dir_all(&sdk).unwrap();
+        fs::write(
+            sdk.join("SDKSettings.json"),
+            format!(r#"{{"CanonicalName": "{canonical_name}", "Version": "{version}"}}"#),
+        )
+        .unwrap();
+        sdk
+    }
+
+    #[test]
+    fn test_find_macos_sdk() {
+        let tmp = tempfile::tempdir().unwrap();
+        let opt = tmp.path().join("opt");
+        let sdk11 = write_sdk(&opt, "MacOSX11.3.sdk", "macosx11.3", "11.3");
+        let sdk14 = write_sdk(&opt, "MacOSX14.sdk", "macosx14.2", "14.2");
+        // osxcross layout, with an XML plist
+        let osxcross = tmp.path().join("osxcross").join("SDK");
+        let sdk13 = osxcross.join("MacOSX13.0.sdk");
+        fs::create_dir_all(&sdk13).unwrap();
+        fs::write(
+            sdk13.join("SDKSettings.plist"),
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<plist version="1.0">
+<dict>
+	<key>CanonicalName</key>
+	<string>macosx13.0</string>
+	<key>Version</key>
+	<string>13.0</string>
+</dict>
+</plist>"#,
+        )
+        .unwrap();
+        write_sdk(&osxcross, "iPhoneOS17.sdk", "iphoneos17.0", "17.0");
+        let search_paths = [opt.clone(), osxcross.clone(), tmp.path().join("missing")];
+
+        let newest = find_macos_sdk(&search_paths, None, false).unwrap().unwrap();
+        assert_eq!(newest.path, sdk14);
+        assert_eq!(newest.version.as_deref(), Some("14.2"));
+        let sdk = find_macos_sdk(&search_paths, Some("11"), false)
+            .unwrap()
+            .unwrap();
+        assert_eq!(sdk.path, sdk11);
+        let sdk = find_macos_sdk(&search_paths, Some("13.0"), false)
+            .unwrap()
+            .unwrap();
+        assert_eq!(sdk.path, sdk13);
+        assert_eq!(sdk.version.as_deref(), Some("13.0"));
+        // An SDK directory can be a search path itself
+        let sdk = find_macos_sdk(std::slice::from_ref(&sdk11), None, true)
+            .unwrap()
+            .unwrap();
+        assert_eq!(sdk.path, sdk11);
+
+        let err = find_macos_sdk(&search_paths, Some("14.1"), false)
+            .unwrap_err()
+            .to_string();
+        assert!(err.starts_with("no macOS SDK 14.1 found, found:"), "{err}");
+        assert!(err.contains("MacOSX11.3.sdk (11.3)"));
+        assert!(err.contains("MacOSX13.0.sdk (13.0)"));
+
+        assert!(is_sdk_of(&osxcross.join("iPhoneOS17.sdk"), "iphoneos"));
+        assert!(!is_sdk_of(
+            &osxcross.join("iPhoneOS17.sdk"),
+            "iphonesimulator"
+        ));
+        assert!(!is_sdk_of(&sdk13, "iphoneos"));
+        assert!(is_sdk_of(&sdk13, "macosx"));
+
+        // Not a macOS SDK
+        let err = MacosSdk::new(osxcross.join("iPhoneOS17.sdk")).unwrap_err();
+        assert_eq!(err.to_string(), "iphoneos17.0 is not a macOS SDK");
+        let broken = tmp.path().join("broken");
+        fs::create_dir_all(broken.join("MacOSX15.sdk")).unwrap();
+        assert_eq!(
+            find_macos_sdk(std::slice::from_ref(&broken), None, false).unwrap(),
+            None
+        );
+        let err = find_macos_sdk(&[broken], None, true)
+            .unwrap_err()
+            .to_string();
+        assert!(
+            err.contains(
+                "MacOSX15.sdk (invalid: neither SDKSettings.json nor SDKSettings.plist found)"
+            ),
+            "{err}"
+        );
+
+        let empty = tmp.path().join("empty");
+        assert_eq!(
+            find_macos_sdk(std::slice::from_ref(&empty), None, false).unwrap(),
+            None
+        );
+        let err = find_macos_sdk(std::slice::from_ref(&empty), None, true).unwrap_err();
+        assert!(err.to_string().starts_with("no valid macOS SDK found in"));
+        let err = find_macos_sdk(&[empty], Some("14"), false).unwrap_err();
+        assert!(err.to_string().starts_with("no macOS SDK 14 found in"));
+    }
+
+    #[test]
+    fn test_version_matches() {
+        assert!(version_matches("14.2", "14"));
+        assert!(version_matches("14", "14.0"));
+        assert!(!version_matches("14", "14.2"));
+        assert!(!version_matches("11.3", "1"));
+        assert_eq!(compare_versions("10.15", "11.0"), Ordering::Less);
+    }
+}
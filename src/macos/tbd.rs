@@ -0,0 +1,687 @@
+//! Text-based stub (`.tbd`) files, which stand in for dylibs when linking
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write as _;
+
+use anyhow::{Context, Result, bail};
+use goblin::mach::constants::cputype::{CPU_SUBTYPE_MASK, get_arch_name_from_types};
+use goblin::mach::exports::{
+    EXPORT_SYMBOL_FLAGS_KIND_MASK, EXPORT_SYMBOL_FLAGS_KIND_THREAD_LOCAL,
+    EXPORT_SYMBOL_FLAGS_WEAK_DEFINITION, ExportInfo,
+};
+use goblin::mach::header::{MH_APP_EXTENSION_SAFE, MH_DYLIB, MH_TWOLEVEL};
+use goblin::mach::load_command::CommandVariant;
+use goblin::mach::{Mach, MachO, SingleArch};
+
+use super::lc_str;
+
+/// The targets a `.tbd` file provides symbols for, like `arm64-macos`,
+/// or bare architectures like `x86_64` for version 1 to 3 stubs
+pub fn parse_targets(content: &str) -> Result<Vec<String>> {
+    if content.trim_start().starts_with('{') {
+        // Version 5 stubs are JSON
+        let value: serde_json::Value =
+            serde_json::from_str(content).context("invalid JSON text-based stub")?;
+        let targets = value["target_info"]
+            .as_array()
+            .context("text-based stub doesn't have a `target_info` list")?
+            .iter()
+            .filter_map(|info| info["target"].as_str())
+            .map(ToString::to_string)
+            .collect();
+        return Ok(targets);
+    }
+    // Only the first document describes the library itself, the others are re-exports
+    let document = content
+        .split("\n---")
+        .find(|doc| !doc.trim().is_empty())
+        .unwrap_or_default();
+    let lines: Vec<&str> = document.lines().collect();
+    for key in ["targets:", "archs:"] {
+        let Some(index) = lines.iter().position(|line| line.starts_with(key)) else {
+            continue;
+        };
+        // The list may be wrapped over several lines
+        let rest = lines[index..].join("\n");
+        let value = rest[key.len()..].trim_start();
+        let Some(value) = value.strip_prefix('[') else {
+            bail!("expected a flow sequence after `{key}`");
+        };
+        let end = value
+            .find(']')
+            .with_context(|| format!("unterminated `{key}` list"))?;
+        return Ok(value[..end]
+            .split(',')
+            .map(|target| target.trim().to_string())
+            .filter(|target| !target.is_empty())
+            .collect());
+    }
+    bail!("text-based stub doesn't have `targets` or `archs`")
+}
+
+/// The install name of the library a `.tbd` file stands in for
+pub fn parse_install_name(content: &str) -> Result<String> {
+    if content.trim_start().starts_with('{') {
+        let value: serde_json::Value =
+            serde_json::from_str(content).context("invalid JSON text-based stub")?;
+        return value["install_names"][0]["name"]
+            .as_str()
+            .map(ToString::to_string)
+            .context("text-based stub doesn't have an install name");
+    }
+    let value = content
+        .lines()
+        .find_map(|line| line.strip_prefix("install-name:"))
+        .context("text-based stub doesn't have an `install-name`")?
+        .trim();
+    match value.strip_prefix('\'').and_then(|v| v.strip_suffix('\'')) {
+        Some(quoted) => Ok(quoted.replace("''", "'")),
+        None => Ok(value.trim_matches('"').to_string()),
+    }
+}
+
+/// The architecture part of a `.tbd` target, `arm64-macos` is `arm64`
+pub fn target_arch(target: &str) -> &str {
+    target.split_once('-').map_or(target, |(arch, _)| arch)
+}
+
+/// Column after which LLVM's YAML writer wraps flow sequences
+const WRAP_COLUMN: usize = 70;
+
+/// The kinds of exported symbols, in the order of their keys in an `exports` section
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum SymbolKind {
+    Symbol,
+    ObjcClass,
+    ObjcEhType,
+    ObjcIvar,
+    Weak,
+    ThreadLocal,
+}
+
+impl SymbolKind {
+    fn key(self) -> &'static str {
+        match self {
+            SymbolKind::Symbol => "symbols",
+            SymbolKind::ObjcClass => "objc-classes",
+            SymbolKind::ObjcEhType => "objc-eh-types",
+            SymbolKind::ObjcIvar => "objc-ivars",
+            SymbolKind::Weak => "weak-symbols",
+            SymbolKind::ThreadLocal => "thread-local-symbols",
+        }
+    }
+}
+
+/// Entries of a per-target list, with the indices of the targets they apply to
+type Targeted<T> = BTreeMap<T, BTreeSet<usize>>;
+
+/// Entries applying to the same targets, with the target names
+type Section<'a, K> = (Vec<String>, Vec<(K, &'a String)>);
+
+/// The interface of a dylib, everything a `.tbd` v4 file records about it
+#[derive(Debug, Default)]
+struct Interface {
+    targets: Vec<String>,
+    uuids: Vec<Option<String>>,
+    flat_namespace: bool,
+    app_extension_safe: bool,
+    install_name: String,
+    current_version: u32,
+    compatibility_version: u32,
+    swift_abi_version: u32,
+    parent_umbrellas: Targeted<String>,
+    allowable_clients: Targeted<String>,
+    reexported_libraries: Targeted<String>,
+    exports: Targeted<(SymbolKind, String)>,
+    reexports: Targeted<(SymbolKind, String)>,
+}
+
+/// Generate a `.tbd` v4 file from a thin or universal Mach-O dylib
+pub fn generate(data: &[u8]) -> Result<String> {
+    let mut interface = Interface::default();
+    match Mach::parse(data).context("not a Mach-O file")? {
+        Mach::Binary(macho) => interface.add_slice(data, &macho)?,
+        Mach::Fat(multi) => {
+            for (arch, slice) in multi.iter_arches().zip(&multi) {
+                let SingleArch::MachO(macho) = slice? else {
+                    bail!("archives in fat files are not supported");
+                };
+                interface.add_slice(arch?.slice(data), &macho)?;
+            }
+        }
+    }
+    Ok(interface.to_tbd())
+}
+
+/// The `.tbd` name of an `LC_BUILD_VERSION` platform
+fn platform_name(platform: u32) -> Result<&'static str> {
+    let name = match platform {
+        1 => "macos",
+        2 => "ios",
+        3 => "tvos",
+        4 => "watchos",
+        5 => "bridgeos",
+        6 => "maccatalyst",
+        7 => "ios-simulator",
+        8 => "tvos-simulator",
+        9 => "watchos-simulator",
+        10 => "driverkit",
+        11 => "xros",
+        12 => "xros-simulator",
+        _ => bail!("unknown platform {platform}"),
+    };
+    Ok(name)
+}
+
+/// Classify an exported symbol, Objective-C symbols are recorded without their prefixes
+fn classify_symbol(name: &str, flags: u64) -> (SymbolKind, String) {
+    for (prefix, kind) in [
+        ("_OBJC_CLASS_$_", SymbolKind::ObjcClass),
+        ("_OBJC_METACLASS_$_", SymbolKind::ObjcClass),
+        (".objc_class_name_", SymbolKind::ObjcClass),
+        ("_OBJC_EHTYPE_$_", SymbolKind::ObjcEhType),
+        ("_OBJC_IVAR_$_", SymbolKind::ObjcIvar),
+    ] {
+        if let Some(name) = name.strip_prefix(prefix) {
+            return (kind, name.to_string());
+        }
+    }
+    let kind = if flags & EXPORT_SYMBOL_FLAGS_KIND_MASK == EXPORT_SYMBOL_FLAGS_KIND_THREAD_LOCAL {
+        SymbolKind::ThreadLocal
+    } else if flags & EXPORT_SYMBOL_FLAGS_WEAK_DEFINITION != 0 {
+        SymbolKind::Weak
+    } else {
+        SymbolKind::Symbol
+    };
+    (kind, name.to_string())
+}
+
+impl Interface {
+    fn add_slice(&mut self, data: &[u8], macho: &MachO) -> Result<()> {
+        if macho.header.filetype != MH_DYLIB {
+            bail!("not a dynamic library");
+        }
+        let cputype = macho.header.cputype;
+        let arch = get_arch_name_from_types(cputype, macho.header.cpusubtype & !CPU_SUBTYPE_MASK)
+            .with_context(|| format!("unknown CPU type {cputype}"))?;
+
+        let mut platforms = Vec::new();
+        let mut uuid = None;
+        let mut id = None;
+        let mut umbrellas = Vec::new();
+        let mut clients = Vec::new();
+        let mut reexported = Vec::new();
+        for lc in &macho.load_commands {
+            match &lc.command {
+                CommandVariant::BuildVersion(cmd) => platforms.push(platform_name(cmd.platform)?),
+                CommandVariant::VersionMinMacosx(_) => platforms.push("macos"),
+                CommandVariant::VersionMinIphoneos(_) => platforms.push("ios"),
+                CommandVariant::VersionMinTvos(_) => platforms.push("tvos"),
+                CommandVariant::VersionMinWatchos(_) => platforms.push("watchos"),
+                CommandVariant::Uuid(cmd) => {
+                    let hex: String = cmd.uuid.iter().map(|b| format!("{b:02X}")).collect();
+                    uuid = Some(format!(
+                        "{}-{}-{}-{}-{}",
+                        &hex[..8],
+                        &hex[8..12],
+                        &hex[12..16],
+                        &hex[16..20],
+                        &hex[20..]
+                    ));
+                }
+                CommandVariant::IdDylib(cmd) => id = Some((lc_str(data, lc, cmd.dylib.name)?, cmd)),
+                CommandVariant::SubFramework(cmd) => {
+                    umbrellas.push(lc_str(data, lc, cmd.umbrella)?)
+                }
+                CommandVariant::SubClient(cmd) => clients.push(lc_str(data, lc, cmd.client)?),
+                CommandVariant::ReexportDylib(cmd) => {
+                    reexported.push(lc_str(data, lc, cmd.dylib.name)?)
+                }
+                _ => {}
+            }
+        }
+        if platforms.is_empty() {
+            bail!("{arch} slice doesn't have a platform load command");
+        }
+        let Some((install_name, id)) = id else {
+            bail!("{arch} slice doesn't have an LC_ID_DYLIB load command");
+        };
+        if self.targets.is_empty() {
+            self.install_name = install_name.to_string();
+            self.current_version = id.dylib.current_version;
+            self.compatibility_version = id.dylib.compatibility_version;
+            self.flat_namespace = macho.header.flags & MH_TWOLEVEL == 0;
+            self.app_extension_safe = macho.header.flags & MH_APP_EXTENSION_SAFE != 0;
+        } else if self.install_name != install_name {
+            bail!(
+                "{arch} slice has a different install name, {install_name} instead of {}",
+                self.install_name
+            );
+        }
+
+        let mut targets = BTreeSet::new();
+        for platform in platforms {
+            let target = format!("{arch}-{platform}");
+            if self.targets.contains(&target) {
+                bail!("more than one slice for {target}");
+            }
+            targets.insert(self.targets.len());
+            self.targets.push(target);
+            self.uuids.push(uuid.clone());
+        }
+        let add = |list: &mut Targeted<String>, entries: Vec<&str>| {
+            for entry in entries {
+                list.entry(entry.to_string()).or_default().extend(&targets);
+            }
+        };
+        add(&mut self.parent_umbrellas, umbrellas);
+        add(&mut self.allowable_clients, clients);
+        add(&mut self.reexported_libraries, reexported);
+
+        for export in macho.exports()? {
+            let (list, flags) = match export.info {
+                ExportInfo::Regular { flags, .. } | ExportInfo::Stub { flags, .. } => {
+                    (&mut self.exports, flags)
+                }
+                ExportInfo::Reexport { flags, .. } => (&mut self.reexports, flags),
+            };
+            list.entry(classify_symbol(&export.name, flags))
+                .or_default()
+                .extend(&targets);
+        }
+
+        for segment in &macho.segments {
+            for (section, section_data) in segment.sections()? {
+                if section.name()? == "__objc_imageinfo" && section_data.len() >= 8 {
+                    let flags = u32::from_le_bytes(section_data[4..8].try_into().unwrap());
+                    self.swift_abi_version = (flags >> 8) & 0xff;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Write the interface in the format of LLVM's YAML writer, which produces the SDK's stubs
+    fn to_tbd(&self) -> String {
+        let mut out = String::from("--- !tapi-tbd\n");
+        writeln!(out, "{}4", padded_key("tbd-version")).unwrap();
+        write_flow(
+            &mut out,
+            &padded_key("targets"),
+            self.targets.iter().cloned(),
+        );
+        if self.uuids.iter().any(Option::is_some) {
+            out.push_str("uuids:\n");
+            for (target, uuid) in self.targets.iter().zip(&self.uuids) {
+                if let Some(uuid) = uuid {
+                    writeln!(out, "  - {}{target}", padded_key("target")).unwrap();
+                    writeln!(out, "    {}{uuid}", padded_key("value")).unwrap();
+                }
+            }
+        }
+        let flags: Vec<String> = [
+            (self.flat_namespace, "flat_namespace"),
+            (!self.app_extension_safe, "not_app_extension_safe"),
+        ]
+        .into_iter()
+        .filter(|(set, _)| *set)
+        .map(|(_, flag)| flag.to_string())
+        .collect();
+        if !flags.is_empty() {
+            write_flow(&mut out, &padded_key("flags"), flags);
+        }
+        writeln!(
+            out,
+            "{}{}",
+            padded_key("install-name"),
+            scalar(&self.install_name)
+        )
+        .unwrap();
+        // Versions default to 1, the Swift ABI version to 0
+        for (key, version) in [
+            ("current-version", self.current_version),
+            ("compatibility-version", self.compatibility_version),
+        ] {
+            if version != 0x10000 {
+                writeln!(out, "{}{}", padded_key(key), packed_version(version)).unwrap();
+            }
+        }
+        if self.swift_abi_version != 0 {
+            writeln!(
+                out,
+                "{}{}",
+                padded_key("swift-abi-version"),
+                self.swift_abi_version
+            )
+            .unwrap();
+        }
+
+        // Each target has a single parent umbrella, the other entries are lists
+        for (key, list, item_key, is_list) in [
+            ("parent-umbrella", &self.parent_umbrellas, "umbrella", false),
+            (
+                "allowable-clients",
+                &self.allowable_clients,
+                "clients",
+                true,
+            ),
+            (
+                "reexported-libraries",
+                &self.reexported_libraries,
+                "libraries",
+                true,
+            ),
+        ] {
+            let sections = self.sections(list.iter().map(|(entry, targets)| ((), entry, targets)));
+            if sections.is_empty() {
+                continue;
+            }
+            writeln!(out, "{key}:").unwrap();
+            let targets_key = format!("  - {}", padded_key("targets"));
+            let item_key = format!("    {}", padded_key(item_key));
+            for (targets, entries) in sections {
+                let entries = entries.into_iter().map(|(_, entry)| scalar(entry));
+                if is_list {
+                    write_flow(&mut out, &targets_key, targets);
+                    write_flow(&mut out, &item_key, entries);
+                } else {
+                    for entry in entries {
+                        write_flow(&mut out, &targets_key, targets.iter().cloned());
+                        writeln!(out, "{item_key}{entry}").unwrap();
+                    }
+                }
+            }
+        }
+
+        for (key, list) in [("exports", &self.exports), ("reexports", &self.reexports)] {
+            let sections = self.sections(
+                list.iter()
+                    .map(|((kind, symbol), targets)| (*kind, symbol, targets)),
+            );
+            if sections.is_empty() {
+                continue;
+            }
+            writeln!(out, "{key}:").unwrap();
+            for (targets, symbols) in sections {
+                write_flow(&mut out, &format!("  - {}", padded_key("targets")), targets);
+                let mut by_kind: BTreeMap<SymbolKind, Vec<String>> = BTreeMap::new();
+                for (kind, symbol) in symbols {
+                    by_kind.entry(kind).or_default().push(scalar(symbol));
+                }
+                for (kind, symbols) in by_kind {
+                    write_flow(
+                        &mut out,
+                        &format!("    {}", padded_key(kind.key())),
+                        symbols,
+                    );
+                }
+            }
+        }
+        out.push_str("...\n");
+        out
+    }
+
+    /// Group per-target entries into sections by the targets they apply to,
+    /// in the order of the targets
+    fn sections<'a, K: Ord + 'a>(
+        &self,
+        entries: impl Iterator<Item = (K, &'a String, &'a BTreeSet<usize>)>,
+    ) -> Vec<Section<'a, K>> {
+        let mut sections: BTreeMap<Vec<usize>, Vec<(K, &String)>> = BTreeMap::new();
+        for (kind, entry, targets) in entries {
+            sections
+                .entry(targets.iter().copied().collect())
+                .or_default()
+                .push((kind, entry));
+        }
+        sections
+            .into_iter()
+            .map(|(targets, entries)| {
+                let targets = targets.iter().map(|&i| self.targets[i].clone()).collect();
+                (targets, entries)
+            })
+            .collect()
+    }
+}
+
+/// A mapping key padded to align its value, like LLVM's YAML writer does
+fn padded_key(key: &str) -> String {
+    format!(
+        "{key}:{}",
+        " ".repeat(16usize.saturating_sub(key.len()).max(1))
+    )
+}
+
+/// Quote a YAML scalar if needed, LLVM's YAML writer always quotes paths
+fn scalar(value: &str) -> String {
+    let plain = !value.is_empty()
+        && !value.starts_with(|c| "-?:,[]{}#&*!|>'\"%@`".contains(c))
+        && value
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "_-^., ".contains(c));
+    if plain {
+        value.to_string()
+    } else {
+        format!("'{}'", value.replace('\'', "''"))
+    }
+}
+
+/// Write a flow sequence, wrapped at the same column as LLVM's YAML writer
+fn write_flow(out: &mut String, prefix: &str, items: impl IntoIterator<Item = String>) {
+    out.push_str(prefix);
+    out.push_str("[ ");
+    let indent = prefix.len() + 2;
+    let mut column = indent;
+    for (i, item) in items.into_iter().enumerate() {
+        if i > 0 {
+            out.push_str(", ");
+            column += 2;
+        }
+        if column > WRAP_COLUMN {
+            out.push('\n');
+            out.push_str(&" ".repeat(indent));
+            column = indent;
+        }
+        out.push_str(&item);
+        column += item.len();
+    }
+    out.push_str(" ]\n");
+}
+
+/// Format a packed `xxxx.yy.zz` version, omitting trailing zero components
+fn packed_version(version: u32) -> String {
+    let (major, minor, patch) = (version >> 16, (version >> 8) & 0xff, version & 0xff);
+    if patch != 0 {
+        format!("{major}.{minor}.{patch}")
+    } else if minor != 0 {
+        format!("{major}.{minor}")
+    } else {
+        major.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::macos::{LIBCHARSET_TBD, LIBICONV_TBD};
+    use crate::test_utils::fixture;
+
+    #[test]
+    fn test_parse_targets() {
+        let targets = parse_targets(LIBICONV_TBD).unwrap();
+        assert_eq!(
+            targets,
+            [
+                "x86_64-macos",
+                "x86_64-maccatalyst",
+                "arm64-macos",
+                "arm64-maccatalyst",
+                "arm64e-macos",
+                "arm64e-maccatalyst"
+            ]
+        );
+        assert!(
+            parse_targets(LIBCHARSET_TBD)
+                .unwrap()
+                .contains(&"arm64-macos".to_string())
+        );
+
+        let v3 = "--- !tapi-tbd-v3\narchs:           [ i386, x86_64 ]\nplatform:        macosx\n";
+        assert_eq!(parse_targets(v3).unwrap(), ["i386", "x86_64"]);
+
+        let v5 = r#"{"tapi_tbd_version": 5, "main_library": {"target_info": []},
+            "target_info": [{"target": "x86_64-macos"}, {"target": "arm64-macos", "min_deployment": "11"}]}"#;
+        assert_eq!(parse_targets(v5).unwrap(), ["x86_64-macos", "arm64-macos"]);
+
+        assert!(parse_targets("--- !tapi-tbd\ntbd-version: 4\n").is_err());
+        assert_eq!(target_arch("arm64e-maccatalyst"), "arm64e");
+        assert_eq!(target_arch("x86_64"), "x86_64");
+    }
+
+    #[test]
+    fn test_generate() {
+        let stub = generate(&fixture("test_universal2.dylib")).unwrap();
+        assert_eq!(
+            stub,
+            "--- !tapi-tbd
+tbd-version:     4
+targets:         [ x86_64-macos, arm64-macos ]
+uuids:
+  - target:          x86_64-macos
+    value:           B5B6E327-4A7E-3804-96FC-5D69815DC4FD
+  - target:          arm64-macos
+    value:           7E7B97E7-2E3B-3444-BFF3-8A022AE38A9B
+flags:           [ not_app_extension_safe ]
+install-name:    '/usr/local/lib/test.dylib'
+current-version: 0
+compatibility-version: 0
+exports:
+  - targets:         [ x86_64-macos, arm64-macos ]
+    symbols:         [ _add ]
+...
+"
+        );
+        assert_eq!(
+            parse_targets(&stub).unwrap(),
+            ["x86_64-macos", "arm64-macos"]
+        );
+        assert_eq!(
+            parse_install_name(&stub).unwrap(),
+            "/usr/local/lib/test.dylib"
+        );
+
+        let thin = generate(&fixture("test_aarch64.dylib")).unwrap();
+        assert!(thin.contains("targets:         [ arm64-macos ]\n"));
+        assert!(
+            generate(b"not a dylib")
+                .unwrap_err()
+                .to_string()
+                .contains("not a Mach-O file")
+        );
+    }
+
+    #[test]
+    fn test_sdk_format() {
+        // The bundled stubs come from the SDK, only the order of the export targets differs
+        let targets = [
+            "x86_64-macos",
+            "x86_64-maccatalyst",
+            "arm64-macos",
+            "arm64-maccatalyst",
+            "arm64e-macos",
+            "arm64e-maccatalyst",
+        ];
+        let uuids = [
+            "5B967C5A-B6AC-322F-B731-4D653B8462B8",
+            "00000000-0000-0000-0000-000000000000",
+            "A83762DF-A611-3148-9926-C0874266E926",
+        ];
+        let all: BTreeSet<usize> = (0..targets.len()).collect();
+        let interface = Interface {
+            targets: targets.iter().map(|t| t.to_string()).collect(),
+            uuids: uuids
+                .iter()
+                .flat_map(|uuid| [Some(uuid.to_string()), Some(uuid.to_string())])
+                .collect(),
+            app_extension_safe: true,
+            install_name: "/usr/lib/libcharset.1.dylib".to_string(),
+            current_version: 2 << 16,
+            compatibility_version: 2 << 16,
+            exports: ["_libcharset_set_relocation_prefix", "_locale_charset"]
+                .into_iter()
+                .map(|symbol| ((SymbolKind::Symbol, symbol.to_string()), all.clone()))
+                .collect(),
+            ..Default::default()
+        };
+        let expected = LIBCHARSET_TBD.replace(
+            "[ arm64e-macos, x86_64-macos, x86_64-maccatalyst, arm64e-maccatalyst, \n                       arm64-macos, arm64-maccatalyst ]",
+            "[ x86_64-macos, x86_64-maccatalyst, arm64-macos, arm64-maccatalyst, \n                       arm64e-macos, arm64e-maccatalyst ]",
+        );
+        assert_ne!(expected, LIBCHARSET_TBD);
+        assert_eq!(interface.to_tbd(), expected);
+    }
+
+    #[test]
+    fn test_sections() {
+        let mut interface = Interface {
+            targets: vec!["x86_64-macos".to_string(), "arm64-macos".to_string()],
+            uuids: vec![None, None],
+            flat_namespace: true,
+            app_extension_safe: true,
+            install_name: "/System/Library/Frameworks/Foo.framework/Versions/A/Foo".to_string(),
+            current_version: 0x10000,
+            compatibility_version: 0x10000,
+            swift_abi_version: 7,
+            ..Default::default()
+        };
+        let both = BTreeSet::from([0, 1]);
+        let arm64 = BTreeSet::from([1]);
+        interface
+            .parent_umbrellas
+            .insert("System".to_string(), both.clone());
+        interface
+            .reexported_libraries
+            .insert("/usr/lib/libbar.dylib".to_string(), arm64.clone());
+        for (name, flags, targets) in [
+            ("_OBJC_CLASS_$_FooObject", 0, &both),
+            ("_OBJC_METACLASS_$_FooObject", 0, &both),
+            ("_OBJC_IVAR_$_FooObject._bar", 0, &both),
+            ("_foo_weak", EXPORT_SYMBOL_FLAGS_WEAK_DEFINITION, &both),
+            ("_foo_tlv", EXPORT_SYMBOL_FLAGS_KIND_THREAD_LOCAL, &arm64),
+            ("$ld$hide$os10.4$_foo", 0, &arm64),
+        ] {
+            interface
+                .exports
+                .insert(classify_symbol(name, flags), targets.clone());
+        }
+        assert_eq!(
+            interface.to_tbd(),
+            "--- !tapi-tbd
+tbd-version:     4
+targets:         [ x86_64-macos, arm64-macos ]
+flags:           [ flat_namespace ]
+install-name:    '/System/Library/Frameworks/Foo.framework/Versions/A/Foo'
+swift-abi-version: 7
+parent-umbrella:
+  - targets:         [ x86_64-macos, arm64-macos ]
+    umbrella:        System
+reexported-libraries:
+  - targets:         [ arm64-macos ]
+    libraries:       [ '/usr/lib/libbar.dylib' ]
+exports:
+  - targets:         [ x86_64-macos, arm64-macos ]
+    objc-classes:    [ FooObject ]
+    objc-ivars:      [ FooObject._bar ]
+    weak-symbols:    [ _foo_weak ]
+  - targets:         [ arm64-macos ]
+    symbols:         [ '$ld$hide$os10.4$_foo' ]
+    thread-local-symbols: [ _foo_tlv ]
+...
+"
+        );
+        assert_eq!(
+            parse_install_name(&interface.to_tbd()).unwrap(),
+            "/System/Library/Frameworks/Foo.framework/Versions/A/Foo"
+        );
+    }
+}
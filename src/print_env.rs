@@ -0,0 +1,147 @@
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+use anyhow::{Result, bail};
+use clap::Parser;
+
+use crate::zig::{CommandEnv, Zig};
+
+/// Print the environment variables cargo-zigbuild sets for the given targets,
+/// for use by other build systems
+#[derive(Clone, Debug, Default, Parser)]
+#[command(display_order = 1)]
+pub struct PrintEnv {
+    /// Target triple, may have a glibc version suffix like `.2.17`
+    #[arg(long, value_name = "TRIPLE", required = true)]
+    pub target: Vec<String>,
+
+    /// Path to Cargo.toml
+    #[arg(long, value_name = "PATH")]
+    pub manifest_path: Option<PathBuf>,
+
+    /// Use the release profile
+    #[arg(short = 'r', long)]
+    pub release: bool,
+
+    /// Use the given profile
+    #[arg(long, value_name = "PROFILE-NAME")]
+    pub profile: Option<String>,
+
+    /// Directory for all generated artifacts
+    #[arg(long, value_name = "DIRECTORY")]
+    pub target_dir: Option<PathBuf>,
+
+    /// Also set up `AR_<target>` using zig ar
+    #[arg(long)]
+    pub zig_ar: bool,
+
+    /// Output format
+    #[arg(long, value_enum, default_value_t = EnvFormat::Shell)]
+    pub format: EnvFormat,
+}
+
+/// Output format of `cargo zigbuild print-env`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum EnvFormat {
+    /// POSIX shell `export` lines
+    #[default]
+    Shell,
+    /// dotenv file
+    Dotenv,
+    /// JSON object
+    Json,
+}
+
+impl PrintEnv {
+    /// Execute `cargo zigbuild print-env` command
+    pub fn execute(&self) -> Result<()> {
+        let cargo = cargo_options::CommonOptions {
+            target: self.target.clone(),
+            profile: self.profile.clone(),
+            target_dir: self.target_dir.clone(),
+            ..Default::default()
+        };
+        let command_env = Zig::command_env(
+            self.manifest_path.as_deref(),
+            self.release,
+            &cargo,
+            self.zig_ar,
+        )?;
+        print!("{}", format_env(&command_env, self.format)?);
+        Ok(())
+    }
+}
+
+fn format_env(command_env: &CommandEnv, format: EnvFormat) -> Result<String> {
+    let mut vars = Vec::new();
+    for (name, value) in command_env.iter() {
+        let Some(value) = value.to_str() else {
+            bail!("Value of environment variable `{name}` is not valid UTF-8");
+        };
+        vars.push((name, value));
+    }
+    if format != EnvFormat::Json {
+        // Variables like `BINDGEN_EXTRA_CLANG_ARGS_<triple>` are also set with the
+        // underscored triple, so it's fine to leave out names shells can't handle
+        vars.retain(|(name, _)| name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_'));
+    }
+    let output = match format {
+        EnvFormat::Shell => vars
+            .iter()
+            .map(|(name, value)| format!("export {name}={}\n", shell_words::quote(value)))
+            .collect(),
+        EnvFormat::Dotenv => vars
+            .iter()
+            .map(|(name, value)| {
+                let escaped = value
+                    .replace('\\', "\\\\")
+                    .replace('"', "\\\"")
+                    .replace('\n', "\\n")
+                    .replace('$', "\\$");
+                format!("{name}=\"{escaped}\"\n")
+            })
+            .collect(),
+        EnvFormat::Json => {
+            let map: BTreeMap<_, _> = vars.into_iter().collect();
+            format!("{}\n", serde_json::to_string_pretty(&map)?)
+        }
+    };
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_env() {
+        let mut command_env = CommandEnv::default();
+        command_env.set("CC_x86_64_unknown_linux_gnu", "/path with space/zigcc.sh");
+        command_env.set("BINDGEN_EXTRA_CLANG_ARGS", r#"-DFOO="$BAR" -I C:\include"#);
+        command_env.set(
+            "BINDGEN_EXTRA_CLANG_ARGS_x86_64-unknown-linux-gnu",
+            "-nostdinc",
+        );
+
+        assert_eq!(
+            format_env(&command_env, EnvFormat::Shell).unwrap(),
+            "export CC_x86_64_unknown_linux_gnu='/path with space/zigcc.sh'\n\
+             export BINDGEN_EXTRA_CLANG_ARGS='-DFOO=\"$BAR\" -I C:\\include'\n"
+        );
+        assert_eq!(
+            format_env(&command_env, EnvFormat::Dotenv).unwrap(),
+            "CC_x86_64_unknown_linux_gnu=\"/path with space/zigcc.sh\"\n\
+             BINDGEN_EXTRA_CLANG_ARGS=\"-DFOO=\\\"\\$BAR\\\" -I C:\\\\include\"\n"
+        );
+        let json: BTreeMap<String, String> =
+            serde_json::from_str(&format_env(&command_env, EnvFormat::Json).unwrap()).unwrap();
+        assert_eq!(
+            json["CC_x86_64_unknown_linux_gnu"],
+            "/path with space/zigcc.sh"
+        );
+        assert_eq!(
+            json["BINDGEN_EXTRA_CLANG_ARGS_x86_64-unknown-linux-gnu"],
+            "-nostdinc"
+        );
+    }
+}
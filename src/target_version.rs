@@ -0,0 +1,373 @@
+//! Target version suffix, like the `.2.17` in `x86_64-unknown-linux-gnu.2.17`
+//!
+//! The meaning of the suffix depends on the target:
+//!
+//! * Linux gnu targets: the minimum glibc version, passed as zig's abi version
+//! * macOS: the minimum macOS version, passed as zig's OS version,
+//!   `MACOSX_DEPLOYMENT_TARGET` and friends are used without a suffix
+//! * FreeBSD: the FreeBSD release whose ABI to target, passed as zig's OS version
+
+use std::env;
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+
+use anyhow::{Result, bail};
+use fs_err as fs;
+
+/// Version used when a Linux gnu target has no version suffix
+pub(crate) const DEFAULT_GLIBC_VERSION: TargetVersion = TargetVersion {
+    major: 2,
+    minor: Some(17),
+    patch: None,
+};
+
+/// A `major[.minor[.patch]]` version suffix
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct TargetVersion {
+    pub major: u32,
+    pub minor: Option<u32>,
+    pub patch: Option<u32>,
+}
+
+impl TargetVersion {
+    pub(crate) const fn new(major: u32, minor: u32, patch: u32) -> Self {
+        Self {
+            major,
+            minor: Some(minor),
+            patch: Some(patch),
+        }
+    }
+
+    /// Version triple with missing components as zero, for comparisons
+    pub(crate) fn triple(&self) -> (u32, u32, u32) {
+        (
+            self.major,
+            self.minor.unwrap_or_default(),
+            self.patch.unwrap_or_default(),
+        )
+    }
+
+    /// Version with at least `major.minor`, as zig requires for OS versions
+    pub(crate) fn with_minor(self) -> Self {
+        Self {
+            minor: Some(self.minor.unwrap_or_default()),
+            ..self
+        }
+    }
+}
+
+impl fmt::Display for TargetVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.major)?;
+        if let Some(minor) = self.minor {
+            write!(f, ".{minor}")?;
+            if let Some(patch) = self.patch {
+                write!(f, ".{patch}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for TargetVersion {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let parts: Vec<&str> = s.split('.').collect();
+        if parts.len() > 3
+            || parts
+                .iter()
+                .any(|part| part.is_empty() || !part.chars().all(|c| c.is_ascii_digit()))
+        {
+            bail!(
+                "Malformed target version suffix `.{s}`, expected `.major[.minor[.patch]]` like `.2.17`"
+            );
+        }
+        let numbers = parts
+            .iter()
+            .map(|part| part.parse::<u32>())
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Self {
+            major: numbers[0],
+            minor: numbers.get(1).copied(),
+            patch: numbers.get(2).copied(),
+        })
+    }
+}
+
+/// Split a target like `x86_64-unknown-linux-gnu.2.17` into the Rust target and its version suffix
+pub(crate) fn split_target_version(target: &str) -> Result<(&str, Option<TargetVersion>)> {
+    match target.split_once('.') {
+        // A trailing `.` without a version is accepted and ignored
+        Some((rust_target, "")) => Ok((rust_target, None)),
+        Some((rust_target, suffix)) => Ok((rust_target, Some(suffix.parse()?))),
+        None => Ok((target, None)),
+    }
+}
+
+/// glibc versions shipped with zig, read from its `lib_dir`
+///
+/// Returns `None` if the metadata file isn't found or can't be understood.
+pub(crate) fn zig_glibc_versions(lib_dir: &Path) -> Option<Vec<TargetVersion>> {
+    let glibc_dir = lib_dir.join("libc").join("glibc");
+    // zig >= 0.10 ships a binary `abilist`: the library names, then the versions
+    if let Ok(abilist) = fs::read(glibc_dir.join("abilist")) {
+        return parse_abilist_versions(&abilist);
+    }
+    // Older zig versions ship a `vers.txt` with one `GLIBC_x.y[.z]` per line
+    let vers = fs::read_to_string(glibc_dir.join("vers.txt")).ok()?;
+    let versions: Vec<TargetVersion> = vers
+        .lines()
+        .filter_map(|line| line.trim().strip_prefix("GLIBC_")?.parse().ok())
+        .collect();
+    (!versions.is_empty()).then_some(versions)
+}
+
+fn parse_abilist_versions(abilist: &[u8]) -> Option<Vec<TargetVersion>> {
+    let libs_len = *abilist.first()?;
+    let mut index = 1;
+    for _ in 0..libs_len {
+        let name_len = abilist.get(index..)?.iter().position(|&b| b == 0)?;
+        index += name_len + 1;
+    }
+    let versions_len = usize::from(*abilist.get(index)?);
+    index += 1;
+    let versions = abilist
+        .get(index..index + versions_len * 3)?
+        .chunks_exact(3)
+        .map(|v| TargetVersion::new(v[0].into(), v[1].into(), v[2].into()))
+        .collect();
+    Some(versions)
+}
+
+/// Check that zig ships a glibc at least as new as the requested version
+pub(crate) fn check_glibc_version(
+    target: &str,
+    version: TargetVersion,
+    shipped: &[TargetVersion],
+) -> Result<()> {
+    let Some(newest) = shipped.iter().max_by_key(|v| v.triple()) else {
+        return Ok(());
+    };
+    if version.major != 2 || version.triple() > newest.triple() {
+        let releases: Vec<String> = shipped
+            .iter()
+            .filter(|v| v.patch.unwrap_or_default() == 0)
+            .map(|v| format!("{}.{}", v.major, v.minor.unwrap_or_default()))
+            .collect();
+        bail!(
+            "glibc {version} requested by `{target}` is not supported by the installed zig, \
+             available glibc versions: {}",
+            releases.join(", ")
+        );
+    }
+    Ok(())
+}
+
+/// Check the minimum macOS version requested by a target suffix
+pub(crate) fn check_macos_version(target: &str, arch: &str, version: TargetVersion) -> Result<()> {
+    if version.major < 10 || (version.major == 10 && version.minor.is_none()) {
+        bail!(
+            "Invalid macOS version {version} in `{target}`, expected something like `.10.13` or `.11.0`"
+        );
+    }
+    if arch == "aarch64" && version.major < 11 {
+        bail!(
+            "macOS {version} requested by `{target}` is too old, aarch64 macOS requires 11.0 or newer"
+        );
+    }
+    Ok(())
+}
+
+/// The environment variable rustc reads the minimum OS version of an Apple target from
+pub(crate) fn deployment_target_env(rust_target: &str) -> Option<&'static str> {
+    let os = rust_target.split('-').nth(2)?;
+    let var = match os {
+        "darwin" => "MACOSX_DEPLOYMENT_TARGET",
+        // Mac Catalyst uses iOS versions
+        "ios" => "IPHONEOS_DEPLOYMENT_TARGET",
+        "tvos" => "TVOS_DEPLOYMENT_TARGET",
+        "watchos" => "WATCHOS_DEPLOYMENT_TARGET",
+        "visionos" => "XROS_DEPLOYMENT_TARGET",
+        _ => return None,
+    };
+    Some(var)
+}
+
+/// The oldest OS version rustc supports for an Apple target,
+/// older deployment targets are raised to it
+fn min_deployment_target(rust_target: &str) -> Option<TargetVersion> {
+    let arch = rust_target.split('-').next()?;
+    let aarch64_sim = arch == "aarch64" && rust_target.ends_with("-sim");
+    let (major, minor) =
+        match deployment_target_env(rust_target)? {
+            "MACOSX_DEPLOYMENT_TARGET" if matches!(arch, "aarch64" | "arm64e") => (11, 0),
+            "MACOSX_DEPLOYMENT_TARGET" => (10, 12),
+            "IPHONEOS_DEPLOYMENT_TARGET" if rust_target.ends_with("-macabi") => {
+                if arch == "aarch64" { (14, 0) } else { (13, 1) }
+            }
+            "IPHONEOS_DEPLOYMENT_TARGET" | "TVOS_DEPLOYMENT_TARGET" if aarch64_sim => (14, 0),
+            "IPHONEOS_DEPLOYMENT_TARGET" | "TVOS_DEPLOYMENT_TARGET" => (10, 0),
+            "WATCHOS_DEPLOYMENT_TARGET" if aarch64_sim => (7, 0),
+            "WATCHOS_DEPLOYMENT_TARGET" => (5, 0),
+            _ => (1, 0),
+        };
+    Some(TargetVersion {
+        major,
+        minor: Some(minor),
+        patch: None,
+    })
+}
+
+/// The minimum OS version of an Apple target from its deployment target variable,
+/// like `MACOSX_DEPLOYMENT_TARGET`, raised to the oldest version rustc supports like rustc does
+///
+/// Returns `None` for other targets or if the variable isn't set.
+pub(crate) fn deployment_target(rust_target: &str) -> Result<Option<TargetVersion>> {
+    let Some(var) = deployment_target_env(rust_target) else {
+        return Ok(None);
+    };
+    let Some(value) = env::var(var).ok().filter(|value| !value.is_empty()) else {
+        return Ok(None);
+    };
+    parse_deployment_target(rust_target, var, &value).map(Some)
+}
+
+fn parse_deployment_target(rust_target: &str, var: &str, value: &str) -> Result<TargetVersion> {
+    let Ok(version) = value.trim().parse::<TargetVersion>() else {
+        bail!("Invalid `{var}` value `{value}`, expected a version like `11.0`");
+    };
+    let version = match min_deployment_target(rust_target) {
+        Some(min) if version.triple() < min.triple() => min,
+        _ => version,
+    };
+    Ok(version.with_minor())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_target_version() {
+        let (target, version) = split_target_version("x86_64-unknown-linux-gnu.2.17").unwrap();
+        assert_eq!(target, "x86_64-unknown-linux-gnu");
+        assert_eq!(version.unwrap().to_string(), "2.17");
+
+        let (_, version) = split_target_version("x86_64-unknown-linux-gnu.2.17.0").unwrap();
+        assert_eq!(version.unwrap(), TargetVersion::new(2, 17, 0));
+
+        let (target, version) = split_target_version("x86_64-unknown-freebsd.13").unwrap();
+        assert_eq!(target, "x86_64-unknown-freebsd");
+        let version = version.unwrap();
+        assert_eq!(version.to_string(), "13");
+        assert_eq!(version.with_minor().to_string(), "13.0");
+
+        assert_eq!(
+            split_target_version("x86_64-apple-darwin").unwrap(),
+            ("x86_64-apple-darwin", None)
+        );
+        assert_eq!(
+            split_target_version("x86_64-unknown-linux-gnu.").unwrap(),
+            ("x86_64-unknown-linux-gnu", None)
+        );
+        for malformed in [
+            "x86_64-unknown-linux-gnu..",
+            "x86_64-unknown-linux-gnu.2.",
+            "x86_64-unknown-linux-gnu.2.x",
+            "x86_64-unknown-linux-gnu.2.17.0.1",
+        ] {
+            let err = split_target_version(malformed).unwrap_err();
+            assert!(err.to_string().contains("Malformed target version suffix"));
+        }
+    }
+
+    #[test]
+    fn test_zig_glibc_versions() {
+        let tmp = tempfile::tempdir().unwrap();
+        let glibc_dir = tmp.path().join("libc").join("glibc");
+        fs::create_dir_all(&glibc_dir).unwrap();
+        assert!(zig_glibc_versions(tmp.path()).is_none());
+
+        fs::write(
+            glibc_dir.join("vers.txt"),
+            "GLIBC_2.0\nGLIBC_2.1.3\nGLIBC_2.17\n",
+        )
+        .unwrap();
+        let versions = zig_glibc_versions(tmp.path()).unwrap();
+        assert_eq!(versions[1], TargetVersion::new(2, 1, 3));
+        assert_eq!(versions[2].to_string(), "2.17");
+
+        let mut abilist = vec![2];
+        abilist.extend(b"c\0m\0");
+        abilist.extend([3, 2, 0, 0, 2, 17, 0, 2, 28, 0]);
+        fs::write(glibc_dir.join("abilist"), &abilist).unwrap();
+        let versions = zig_glibc_versions(tmp.path()).unwrap();
+        assert_eq!(
+            versions,
+            [
+                TargetVersion::new(2, 0, 0),
+                TargetVersion::new(2, 17, 0),
+                TargetVersion::new(2, 28, 0)
+            ]
+        );
+
+        let target = "x86_64-unknown-linux-gnu.2.31";
+        assert!(check_glibc_version(target, "2.17.0".parse().unwrap(), &versions).is_ok());
+        assert!(check_glibc_version(target, "2.20".parse().unwrap(), &versions).is_ok());
+        let err = check_glibc_version(target, "2.31".parse().unwrap(), &versions).unwrap_err();
+        assert!(
+            err.to_string()
+                .ends_with("available glibc versions: 2.0, 2.17, 2.28")
+        );
+        assert!(check_glibc_version(target, "3".parse().unwrap(), &versions).is_err());
+    }
+
+    #[test]
+    fn test_check_macos_version() {
+        let target = "x86_64-apple-darwin.10.13";
+        assert!(check_macos_version(target, "x86_64", "10.13".parse().unwrap()).is_ok());
+        assert!(check_macos_version(target, "x86_64", "10".parse().unwrap()).is_err());
+        assert!(check_macos_version(target, "x86_64", "9.0".parse().unwrap()).is_err());
+        assert!(check_macos_version(target, "aarch64", "10.15".parse().unwrap()).is_err());
+        assert!(check_macos_version(target, "aarch64", "11".parse().unwrap()).is_ok());
+    }
+
+    #[test]
+    fn test_deployment_target() {
+        assert_eq!(
+            deployment_target_env("x86_64-apple-darwin"),
+            Some("MACOSX_DEPLOYMENT_TARGET")
+        );
+        assert_eq!(
+            deployment_target_env("aarch64-apple-ios-macabi"),
+            Some("IPHONEOS_DEPLOYMENT_TARGET")
+        );
+        assert_eq!(
+            deployment_target_env("aarch64-apple-visionos-sim"),
+            Some("XROS_DEPLOYMENT_TARGET")
+        );
+        assert_eq!(deployment_target_env("x86_64-unknown-linux-gnu"), None);
+
+        let var = "MACOSX_DEPLOYMENT_TARGET";
+        let version = parse_deployment_target("x86_64-apple-darwin", var, "10.13").unwrap();
+        assert_eq!(version.to_string(), "10.13");
+        let version = parse_deployment_target("x86_64-apple-darwin", var, "14").unwrap();
+        assert_eq!(version.to_string(), "14.0");
+        // Raised to the oldest supported version like rustc does
+        let version = parse_deployment_target("aarch64-apple-darwin", var, "10.13").unwrap();
+        assert_eq!(version.to_string(), "11.0");
+        let var = "IPHONEOS_DEPLOYMENT_TARGET";
+        let version = parse_deployment_target("x86_64-apple-ios-macabi", var, "13.0").unwrap();
+        assert_eq!(version.to_string(), "13.1");
+        let version = parse_deployment_target("aarch64-apple-ios-macabi", var, "15.2").unwrap();
+        assert_eq!(version.to_string(), "15.2");
+
+        let err = parse_deployment_target("x86_64-apple-darwin", var, "latest").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Invalid `IPHONEOS_DEPLOYMENT_TARGET` value `latest`, expected a version like `11.0`"
+        );
+    }
+}
@@ -0,0 +1,143 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result, bail};
+use clap::Parser;
+use fs_err as fs;
+
+use crate::macos::tbd;
+
+/// Generate text-based stubs (`.tbd` files) from Mach-O dylibs and frameworks,
+/// for use as a stub SDK
+#[derive(Clone, Debug, Default, Parser)]
+#[command(display_order = 1)]
+pub struct Tbd {
+    /// Thin or universal dylibs, framework binaries or `.framework` directories
+    #[arg(required = true, value_name = "DYLIB")]
+    pub inputs: Vec<PathBuf>,
+
+    /// Write the stub to this file instead of stdout, only for a single input
+    #[arg(short, long, value_name = "PATH")]
+    pub output: Option<PathBuf>,
+
+    /// Write each stub into this stub SDK directory, at the location of its install name,
+    /// like `usr/lib/libfoo.tbd` or `System/Library/Frameworks/Foo.framework/Foo.tbd`
+    #[arg(long, value_name = "DIRECTORY", conflicts_with = "output")]
+    pub sdk_dir: Option<PathBuf>,
+}
+
+impl Tbd {
+    /// Execute `cargo zigbuild tbd` command
+    pub fn execute(&self) -> Result<()> {
+        if self.inputs.len() > 1 && self.sdk_dir.is_none() {
+            bail!("more than one input requires `--sdk-dir`");
+        }
+        let mut stubs: Vec<(&Path, String)> = Vec::new();
+        for input in &self.inputs {
+            let binary = framework_binary(input);
+            let data = fs::read(&binary)?;
+            let stub = tbd::generate(&data)
+                .with_context(|| format!("failed to generate a stub for '{}'", binary.display()))?;
+            stubs.push((input, stub));
+        }
+        let Some(sdk_dir) = &self.sdk_dir else {
+            let (_, stub) = &stubs[0];
+            match &self.output {
+                Some(output) => fs::write(output, stub)?,
+                None => print!("{stub}"),
+            }
+            return Ok(());
+        };
+        // Check for conflicts before writing anything
+        let mut paths: Vec<(PathBuf, &Path)> = Vec::new();
+        for (input, stub) in &stubs {
+            let install_name = tbd::parse_install_name(stub)?;
+            let path = sdk_dir.join(sdk_path(&install_name)?);
+            if let Some((_, other)) = paths.iter().find(|(existing, _)| *existing == path) {
+                bail!(
+                    "'{}' and '{}' have the same install name {install_name}, \
+                     combine them into a universal binary with lipo first",
+                    other.display(),
+                    input.display()
+                );
+            }
+            paths.push((path, input));
+        }
+        for ((path, _), (_, stub)) in paths.iter().zip(&stubs) {
+            fs::create_dir_all(path.parent().unwrap())?;
+            fs::write(path, stub)?;
+            eprintln!("cargo-zigbuild: wrote `{}`", path.display());
+        }
+        Ok(())
+    }
+}
+
+/// The binary of a `.framework` directory, other paths are returned as is
+fn framework_binary(path: &Path) -> PathBuf {
+    if path.is_dir()
+        && path.extension().is_some_and(|ext| ext == "framework")
+        && let Some(name) = path.file_stem()
+    {
+        path.join(name)
+    } else {
+        path.to_path_buf()
+    }
+}
+
+/// The location of a stub in an SDK for an install name: framework versions are dropped,
+/// and dylibs are named after the library, so that `-lfoo` finds `libfoo.1.dylib`
+fn sdk_path(install_name: &str) -> Result<PathBuf> {
+    let Some(relative) = install_name.strip_prefix('/') else {
+        bail!("install name {install_name} is not an absolute path, use `--output` instead");
+    };
+    let mut path = PathBuf::new();
+    let mut components = relative.split('/').peekable();
+    while let Some(component) = components.next() {
+        if component == "Versions" && components.peek().is_some() {
+            components.next();
+            continue;
+        }
+        if components.peek().is_none() {
+            let name = if path.extension().is_some_and(|ext| ext == "framework") {
+                component
+            } else {
+                component.split('.').next().unwrap_or(component)
+            };
+            path.push(format!("{name}.tbd"));
+        } else {
+            path.push(component);
+        }
+    }
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sdk_path() {
+        assert_eq!(
+            sdk_path("/usr/lib/libiconv.2.dylib").unwrap(),
+            Path::new("usr/lib/libiconv.tbd")
+        );
+        assert_eq!(
+            sdk_path(
+                "/System/Library/Frameworks/CoreFoundation.framework/Versions/A/CoreFoundation"
+            )
+            .unwrap(),
+            Path::new("System/Library/Frameworks/CoreFoundation.framework/CoreFoundation.tbd")
+        );
+        assert_eq!(
+            sdk_path(
+                "/System/Library/Frameworks/ApplicationServices.framework/Versions/A/Frameworks/\
+                 ATS.framework/Versions/A/ATS"
+            )
+            .unwrap(),
+            Path::new(
+                "System/Library/Frameworks/ApplicationServices.framework/Frameworks/\
+                 ATS.framework/ATS.tbd"
+            )
+        );
+        assert!(sdk_path("@rpath/libfoo.dylib").is_err());
+    }
+}
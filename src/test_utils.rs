@@ -0,0 +1,42 @@
+//! Helpers shared by the unit tests
+
+use std::path::PathBuf;
+
+/// The `tests/fixtures` directory
+pub(crate) fn fixtures_dir() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures")
+}
+
+/// Read a fixture
+pub(crate) fn fixture(name: &str) -> Vec<u8> {
+    fs_err::read(fixtures_dir().join(name)).unwrap()
+}
+
+/// Copy a fixture to a temp file for modification
+pub(crate) fn copy_fixture(name: &str) -> tempfile::NamedTempFile {
+    let src = fixtures_dir().join(name);
+    let mut tmp = tempfile::NamedTempFile::new().unwrap();
+    std::io::copy(&mut fs_err::File::open(src).unwrap(), &mut tmp).unwrap();
+    tmp
+}
+
+/// A static archive with a single member
+#[cfg(feature = "universal2")]
+pub(crate) fn archive(member: &[u8]) -> Vec<u8> {
+    let mut ar = b"!<arch>\n".to_vec();
+    let header = format!(
+        "{:<16}{:<12}{:<6}{:<6}{:<8}{:<10}`\n",
+        "lib.o/",
+        0,
+        0,
+        0,
+        644,
+        member.len()
+    );
+    ar.extend(header.as_bytes());
+    ar.extend(member);
+    if member.len() % 2 == 1 {
+        ar.push(b'\n');
+    }
+    ar
+}
@@ -0,0 +1,416 @@
+//! Module-definition (`.def`) files generated from the export table of a DLL, like mingw's `gendef`
+
+use std::fmt::Write as _;
+
+use anyhow::{Context, Result, bail};
+use goblin::pe::PE;
+use goblin::pe::export::ExportAddressTableEntry;
+use goblin::pe::header::{
+    COFF_MACHINE_ARM64, COFF_MACHINE_ARMNT, COFF_MACHINE_X86, COFF_MACHINE_X86_64,
+};
+use goblin::pe::section_table::{IMAGE_SCN_MEM_EXECUTE, SectionTable};
+use scroll::Pread;
+
+/// Give up looking for the `ret` of a function after this many instructions
+const MAX_INSTRUCTIONS: usize = 4096;
+
+/// The exports of a DLL
+#[derive(Debug, Clone)]
+pub struct ModuleDefinition {
+    /// The DLL name recorded in its export directory
+    pub library: String,
+    /// The `dlltool` machine of the DLL, like `i386:x86-64`
+    pub machine: &'static str,
+    pub exports: Vec<DefExport>,
+    /// Ordinals exported without a name, which can't be imported by name
+    pub unnamed_ordinals: Vec<u32>,
+    /// Whether the import library must be built with `dlltool --kill-at`, for x86 DLLs exporting
+    /// stdcall functions with undecorated names
+    pub kill_at: bool,
+    /// x86 functions whose stdcall argument size couldn't be determined, including forwarded
+    /// ones whose code is in another DLL, left undecorated
+    pub undetermined: Vec<String>,
+}
+
+/// A named export of a DLL
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DefExport {
+    /// The name in the `.def` file, with the stdcall decoration for x86 functions
+    pub name: String,
+    pub data: bool,
+    /// `dll.function` when the export is forwarded to another DLL
+    pub forwarded_to: Option<String>,
+}
+
+impl ModuleDefinition {
+    /// Read the export table of a DLL
+    ///
+    /// The export table of x86 DLLs built with `--kill-at` or by MSVC has no stdcall decorations,
+    /// the argument size is read from the `ret` instruction of each function instead. Functions
+    /// returning with a plain `ret` are assumed to be cdecl, as stdcall functions without
+    /// arguments can't be told apart.
+    pub fn from_dll(data: &[u8], file_name: &str) -> Result<Self> {
+        let pe = PE::parse(data).context("failed to parse PE file")?;
+        let machine = match pe.header.coff_header.machine {
+            COFF_MACHINE_X86 => "i386",
+            COFF_MACHINE_X86_64 => "i386:x86-64",
+            COFF_MACHINE_ARM64 => "arm64",
+            COFF_MACHINE_ARMNT => "arm",
+            machine => bail!("unsupported PE machine {machine:#x}"),
+        };
+        let Some(export_data) = &pe.export_data else {
+            bail!("'{file_name}' has no export table");
+        };
+        let library = export_data
+            .name
+            .filter(|name| !name.is_empty())
+            .unwrap_or(file_name)
+            .to_string();
+        let addresses = &export_data.export_address_table;
+        let ordinal_base = export_data.export_directory_table.ordinal_base;
+
+        let mut named = vec![false; addresses.len()];
+        let mut exports = Vec::new();
+        let mut rvas = Vec::new();
+        for (&name_rva, &index) in export_data
+            .export_name_pointer_table
+            .iter()
+            .zip(&export_data.export_ordinal_table)
+        {
+            let name = read_str(data, &pe.sections, name_rva)?;
+            let entry = addresses
+                .get(index as usize)
+                .with_context(|| format!("export `{name}` has an invalid ordinal"))?;
+            named[index as usize] = true;
+            let (rva, forwarded_to) = match *entry {
+                ExportAddressTableEntry::ExportRVA(rva) => (rva, None),
+                ExportAddressTableEntry::ForwarderRVA(rva) => {
+                    (rva, Some(read_str(data, &pe.sections, rva)?.to_string()))
+                }
+            };
+            let data = forwarded_to.is_none()
+                && section(&pe.sections, rva)
+                    .is_some_and(|section| section.characteristics & IMAGE_SCN_MEM_EXECUTE == 0);
+            exports.push(DefExport {
+                name: name.to_string(),
+                data,
+                forwarded_to,
+            });
+            rvas.push(rva);
+        }
+        let unnamed_ordinals = addresses
+            .iter()
+            .enumerate()
+            .filter(|(index, entry)| {
+                !named[*index] && !matches!(entry, ExportAddressTableEntry::ExportRVA(0))
+            })
+            .map(|(index, _)| ordinal_base + index as u32)
+            .collect();
+
+        // Decorated names already carry their argument size
+        let kill_at = machine == "i386"
+            && !exports
+                .iter()
+                .any(|export| is_stdcall_decorated(&export.name));
+        let mut undetermined = Vec::new();
+        if kill_at {
+            for (export, &rva) in exports.iter_mut().zip(&rvas) {
+                if export.data || export.name.starts_with('?') {
+                    continue;
+                }
+                if export.forwarded_to.is_some() {
+                    undetermined.push(export.name.clone());
+                    continue;
+                }
+                let arg_size = section(&pe.sections, rva).and_then(|section| {
+                    let code = section_data(data, section)?;
+                    x86_stdcall_arg_size(code, (rva - section.virtual_address) as usize)
+                });
+                match arg_size {
+                    Some(0) => {}
+                    Some(size) => export.name = format!("{}@{size}", export.name),
+                    None => undetermined.push(export.name.clone()),
+                }
+            }
+        }
+
+        Ok(Self {
+            library,
+            machine,
+            exports,
+            unnamed_ordinals,
+            kill_at,
+            undetermined,
+        })
+    }
+
+    /// The `.def` file content
+    pub fn to_def(&self) -> String {
+        let mut def = String::new();
+        let _ = writeln!(def, "; Generated by cargo-zigbuild gendef");
+        let _ = writeln!(def, "LIBRARY \"{}\"", self.library);
+        let _ = writeln!(def, "EXPORTS");
+        for export in &self.exports {
+            def.push_str(&export.name);
+            if export.data {
+                def.push_str(" DATA");
+            }
+            if let Some(forwarded_to) = &export.forwarded_to {
+                let _ = write!(def, " ; forwarded to {forwarded_to}");
+            }
+            def.push('\n');
+        }
+        for ordinal in &self.unnamed_ordinals {
+            let _ = writeln!(def, "; ordinal {ordinal} is exported without a name");
+        }
+        def
+    }
+
+    /// `zig dlltool` arguments generating an import library from the `.def` file
+    pub fn dlltool_args(&self, def: &str, import_lib: &str) -> Vec<String> {
+        let mut args = vec![
+            "-d".to_string(),
+            def.to_string(),
+            "-l".to_string(),
+            import_lib.to_string(),
+            "-m".to_string(),
+            self.machine.to_string(),
+        ];
+        if self.kill_at {
+            args.push("-k".to_string());
+        }
+        args
+    }
+}
+
+/// Whether an export name has a stdcall or fastcall decoration like `foo@8`
+fn is_stdcall_decorated(name: &str) -> bool {
+    name.rsplit_once('@').is_some_and(|(base, size)| {
+        !base.is_empty() && !size.is_empty() && size.bytes().all(|b| b.is_ascii_digit())
+    })
+}
+
+/// The section containing an RVA
+fn section(sections: &[SectionTable], rva: u32) -> Option<&SectionTable> {
+    sections.iter().find(|section| {
+        let size = section.virtual_size.max(section.size_of_raw_data);
+        rva >= section.virtual_address && rva - section.virtual_address < size
+    })
+}
+
+/// The raw data of a section
+fn section_data<'a>(data: &'a [u8], section: &SectionTable) -> Option<&'a [u8]> {
+    let start = section.pointer_to_raw_data as usize;
+    data.get(start..start + section.size_of_raw_data as usize)
+}
+
+/// Read a NUL-terminated string at an RVA
+fn read_str<'a>(data: &'a [u8], sections: &[SectionTable], rva: u32) -> Result<&'a str> {
+    let section = section(sections, rva).with_context(|| format!("RVA {rva:#x} isn't mapped"))?;
+    let offset = (section.pointer_to_raw_data + (rva - section.virtual_address)) as usize;
+    data.pread::<&str>(offset)
+        .with_context(|| format!("invalid string at RVA {rva:#x}"))
+}
+
+/// The number of bytes a 32-bit x86 function pops off the stack, found by decoding its
+/// instructions from `pos` until the first `ret`
+///
+/// Unconditional jumps are followed, conditional ones are assumed not taken. Returns `None`
+/// for indirect jumps, like import thunks, and for instructions we don't know.
+fn x86_stdcall_arg_size(code: &[u8], mut pos: usize) -> Option<u16> {
+    for _ in 0..MAX_INSTRUCTIONS {
+        let mut operand_16 = false;
+        loop {
+            match *code.get(pos)? {
+                0x66 => operand_16 = true,
+                // 16-bit addressing isn't used by 32-bit code
+                0x67 => return None,
+                0xf0 | 0xf2 | 0xf3 | 0x26 | 0x2e | 0x36 | 0x3e | 0x64 | 0x65 => {}
+                _ => break,
+            }
+            pos += 1;
+        }
+        let imm = if operand_16 { 2 } else { 4 };
+        let opcode = *code.get(pos)?;
+        pos += 1;
+        let len = match opcode {
+            0xc3 => return Some(0),
+            0xc2 => {
+                return code
+                    .get(pos..pos + 2)
+                    .map(|b| u16::from_le_bytes([b[0], b[1]]));
+            }
+            0xeb => {
+                let rel = *code.get(pos)? as i8;
+                pos = (pos + 1).checked_add_signed(rel as isize)?;
+                continue;
+            }
+            0xe9 => {
+                let rel = code.get(pos..pos + 4)?;
+                let rel = i32::from_le_bytes([rel[0], rel[1], rel[2], rel[3]]);
+                pos = (pos + 4).checked_add_signed(rel as isize)?;
+                continue;
+            }
+            0x0f => {
+                let opcode = *code.get(pos)?;
+                pos += 1;
+                match opcode {
+                    0x80..=0x8f => imm,
+                    0x06 | 0x08 | 0x09 | 0x30..=0x33 | 0x77 | 0xa0..=0xa2 | 0xa8..=0xaa => 0,
+                    0xc8..=0xcf => 0,
+                    0x70..=0x73 | 0xa4 | 0xac | 0xba | 0xc2 | 0xc4..=0xc6 => {
+                        modrm_len(code, pos)? + 1
+                    }
+                    0x38 => modrm_len(code, pos + 1)? + 1,
+                    0x3a => modrm_len(code, pos + 1)? + 2,
+                    0x00..=0x03 | 0x0d | 0x10..=0x2f | 0x40..=0x7f | 0x90..=0xff => {
+                        modrm_len(code, pos)?
+                    }
+                    _ => return None,
+                }
+            }
+            0x00..=0x3f => match opcode & 7 {
+                0..=3 => modrm_len(code, pos)?,
+                4 => 1,
+                5 => imm,
+                _ => 0,
+            },
+            0x40..=0x61 | 0x6c..=0x6f | 0x90..=0x99 | 0x9b..=0x9f | 0xa4..=0xa7 => 0,
+            0xaa..=0xaf | 0xc9 | 0xd7 | 0xec..=0xef | 0xf5 | 0xf8..=0xfd => 0,
+            0x62 | 0x63 | 0x84..=0x8f | 0xc4 | 0xc5 | 0xd0..=0xd3 | 0xd8..=0xdf | 0xfe => {
+                modrm_len(code, pos)?
+            }
+            0x6a | 0x70..=0x7f | 0xa8 | 0xb0..=0xb7 | 0xcd | 0xd4 | 0xd5 | 0xe0..=0xe7 => 1,
+            0x68 | 0xa9 | 0xb8..=0xbf | 0xe8 => imm,
+            0xa0..=0xa3 => 4,
+            0x9a => imm + 2,
+            0xc8 => 3,
+            0x69 | 0x81 | 0xc7 => modrm_len(code, pos)? + imm,
+            0x6b | 0x80 | 0x82 | 0x83 | 0xc0 | 0xc1 | 0xc6 => modrm_len(code, pos)? + 1,
+            0xf6 | 0xf7 => {
+                // Only `test` has an immediate
+                let reg = (*code.get(pos)? >> 3) & 7;
+                let imm = match (opcode, reg) {
+                    (0xf6, 0 | 1) => 1,
+                    (0xf7, 0 | 1) => imm,
+                    _ => 0,
+                };
+                modrm_len(code, pos)? + imm
+            }
+            0xff => {
+                let reg = (*code.get(pos)? >> 3) & 7;
+                if matches!(reg, 4 | 5) {
+                    return None;
+                }
+                modrm_len(code, pos)?
+            }
+            _ => return None,
+        };
+        pos += len;
+    }
+    None
+}
+
+/// The length of a ModRM byte with its SIB byte and displacement, for 32-bit addressing
+fn modrm_len(code: &[u8], pos: usize) -> Option<usize> {
+    let modrm = *code.get(pos)?;
+    let (mode, rm) = (modrm >> 6, modrm & 7);
+    if mode == 3 {
+        return Some(1);
+    }
+    let mut len = 1;
+    let mut base = rm;
+    if rm == 4 {
+        base = *code.get(pos + 1)? & 7;
+        len += 1;
+    }
+    len += match mode {
+        0 if base == 5 => 4,
+        0 => 0,
+        1 => 1,
+        _ => 4,
+    };
+    Some(len)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::fixture;
+
+    fn module_definition(name: &str) -> ModuleDefinition {
+        ModuleDefinition::from_dll(&fixture(name), name).unwrap()
+    }
+
+    #[test]
+    fn test_gendef() {
+        for (name, machine) in [
+            ("test_x86_64.dll", "i386:x86-64"),
+            ("test_aarch64.dll", "arm64"),
+        ] {
+            let def = module_definition(name);
+            assert_eq!(def.machine, machine);
+            assert!(!def.kill_at);
+            assert_eq!(
+                def.to_def(),
+                format!(
+                    "; Generated by cargo-zigbuild gendef\n\
+                     LIBRARY \"{name}\"\nEXPORTS\nadd\ncounter DATA\n"
+                )
+            );
+        }
+
+        // `add` is stdcall, `cdecl_fn` has `ret` bytes in an immediate before its own `ret`
+        let def = module_definition("test_i686.dll");
+        assert_eq!(def.machine, "i386");
+        assert!(def.kill_at);
+        // `fwd` is forwarded to the stdcall `kernel32.Sleep`, its argument size is unknown
+        assert_eq!(def.undetermined, ["fwd"]);
+        assert_eq!(
+            def.to_def(),
+            "; Generated by cargo-zigbuild gendef\n\
+             LIBRARY \"test_i686.dll\"\nEXPORTS\n\
+             add@8\n\
+             cdecl_fn\n\
+             counter DATA\n\
+             fwd ; forwarded to kernel32.Sleep\n\
+             ; ordinal 10 is exported without a name\n"
+        );
+        assert_eq!(
+            def.dlltool_args("test.def", "libtest.dll.a"),
+            ["-d", "test.def", "-l", "libtest.dll.a", "-m", "i386", "-k"]
+        );
+    }
+
+    #[test]
+    fn test_x86_stdcall_arg_size() {
+        // mov eax, [esp+4]; add eax, [esp+8]; ret 8
+        let code = [
+            0x8b, 0x44, 0x24, 0x04, 0x03, 0x44, 0x24, 0x08, 0xc2, 0x08, 0x00,
+        ];
+        assert_eq!(x86_stdcall_arg_size(&code, 0), Some(8));
+        // jmp +2; int3; int3; ret 4
+        let code = [0xeb, 0x02, 0xcc, 0xcc, 0xc2, 0x04, 0x00];
+        assert_eq!(x86_stdcall_arg_size(&code, 0), Some(4));
+        // push ebp; mov ebp, esp; sub esp, 0x100; mov dword [ebp-4], 0xc3; leave; ret
+        let code = [
+            0x55, 0x89, 0xe5, 0x81, 0xec, 0x00, 0x01, 0x00, 0x00, 0xc7, 0x45, 0xfc, 0xc3, 0x00,
+            0x00, 0x00, 0xc9, 0xc3,
+        ];
+        assert_eq!(x86_stdcall_arg_size(&code, 0), Some(0));
+        // jmp [0x1000], an import thunk
+        let code = [0xff, 0x25, 0x00, 0x10, 0x00, 0x00];
+        assert_eq!(x86_stdcall_arg_size(&code, 0), None);
+        assert_eq!(x86_stdcall_arg_size(&[0xcc], 0), None);
+        assert_eq!(x86_stdcall_arg_size(&[0x90, 0x90], 0), None);
+    }
+
+    #[test]
+    fn test_is_stdcall_decorated() {
+        assert!(is_stdcall_decorated("foo@8"));
+        assert!(is_stdcall_decorated("_foo@0"));
+        assert!(!is_stdcall_decorated("foo"));
+        assert!(!is_stdcall_decorated("@foo"));
+        assert!(!is_stdcall_decorated("foo@bar"));
+    }
+}
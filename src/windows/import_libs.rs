@@ -0,0 +1,356 @@
+//! Import libraries zig's mingw doesn't ship, generated from module-definition files
+//!
+//! rustc and `-sys` crates link Windows system libraries like `-lsynchronization` that
+//! not every zig version ships a `.def` file for. A registry of bundled and user-provided
+//! `.def` files fills the gaps, the import libraries are generated with `zig dlltool`
+//! into the cache directory and added to the library search path.
+
+use std::env;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use anyhow::{Context, Result, bail};
+use fs_err as fs;
+use regex::Regex;
+
+use crate::config::ZigbuildConfig;
+use crate::zig::{Zig, cache_dir};
+
+/// Environment variable with extra directories of `.def` files, searched before
+/// the `def-dirs` setting
+pub(crate) const DEF_DIRS_ENV: &str = "CARGO_ZIGBUILD_DEF_DIRS";
+
+/// Libraries the built-in linker argument rules drop, as zig's mingw doesn't have them
+const DROPPED_LIBS: &[&str] = &["windows"];
+
+/// `.def` files shipped with cargo-zigbuild
+static BUNDLED_DEFS: &[(&str, &str)] =
+    &[("synchronization", include_str!("defs/synchronization.def"))];
+
+/// Bundled and user-provided `.def` files, looked up by library name
+#[derive(Debug, Clone, Default)]
+pub(crate) struct DefRegistry {
+    dirs: Vec<PathBuf>,
+}
+
+impl DefRegistry {
+    pub(crate) fn from_config(config: &ZigbuildConfig) -> Self {
+        let mut dirs: Vec<PathBuf> = env::var_os(DEF_DIRS_ENV)
+            .map(|dirs| env::split_paths(&dirs).collect())
+            .unwrap_or_default();
+        dirs.extend(config.def_dirs.iter().cloned());
+        Self { dirs }
+    }
+
+    /// The `.def` file content for a library, user-provided files take precedence
+    ///
+    /// Windows library names are case-insensitive.
+    pub(crate) fn get(&self, name: &str) -> Result<Option<String>> {
+        let file_names = [
+            format!("{name}.def"),
+            format!("{}.def", name.to_lowercase()),
+        ];
+        for dir in &self.dirs {
+            if let Some(path) = file_names
+                .iter()
+                .map(|file_name| dir.join(file_name))
+                .find(|path| path.is_file())
+            {
+                return Ok(Some(fs::read_to_string(path)?));
+            }
+        }
+        Ok(BUNDLED_DEFS
+            .iter()
+            .find(|(bundled, _)| bundled.eq_ignore_ascii_case(name))
+            .map(|(_, content)| content.to_string()))
+    }
+}
+
+/// Generate the import libraries of the libraries linked by `args` that neither zig's mingw
+/// nor the library search path provide, returning the directories to add to the search path
+///
+/// `arch` is the zig architecture, like `x86_64` or `x86`.
+pub(crate) fn synthesize_missing(
+    args: &[String],
+    arch: &str,
+    registry: &DefRegistry,
+) -> Result<Vec<PathBuf>> {
+    // zig < 0.11 calls x86 `i386`
+    let arch = if arch == "i386" { "x86" } else { arch };
+    let search_dirs = search_dirs(args);
+    let mut zig_mingw_dir = None;
+    let mut lib_dirs = Vec::new();
+    for name in linked_libs(args) {
+        let Some(def) = registry.get(&name)? else {
+            continue;
+        };
+        if search_dirs.iter().any(|dir| has_library(dir, &name)) {
+            continue;
+        }
+        let mingw_dir = match &zig_mingw_dir {
+            Some(dir) => dir,
+            None => zig_mingw_dir.insert(Zig::lib_dir()?.join("libc").join("mingw")),
+        };
+        if zig_ships_def(mingw_dir, arch, &name) {
+            continue;
+        }
+        lib_dirs.push(
+            generate_import_lib(&name, &def, arch)
+                .with_context(|| format!("Failed to generate the import library of -l{name}"))?,
+        );
+    }
+    Ok(lib_dirs)
+}
+
+/// `-l` arguments of the libraries linked by `args` that the built-in rules drop, but that the
+/// library search path or the registry provides, to be passed to the linker after all
+pub(crate) fn provided_dropped_libs(
+    args: &[String],
+    registry: &DefRegistry,
+) -> Result<Vec<String>> {
+    let search_dirs = search_dirs(args);
+    let mut libs = Vec::new();
+    for name in linked_libs(args) {
+        if !DROPPED_LIBS.contains(&name.as_str()) {
+            continue;
+        }
+        if search_dirs.iter().any(|dir| has_library(dir, &name)) || registry.get(&name)?.is_some() {
+            libs.push(format!("-l{name}"));
+        }
+    }
+    Ok(libs)
+}
+
+/// Library names linked with `-lname` or `-l name`, `-l:file` links a file and is ignored
+fn linked_libs(args: &[String]) -> Vec<String> {
+    let mut libs = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let name = match arg.strip_prefix("-l") {
+            Some("") => args.next().map(String::as_str),
+            Some(name) => Some(name),
+            None => None,
+        };
+        if let Some(name) = name
+            && !name.starts_with(':')
+            && !libs.iter().any(|lib| lib == name)
+        {
+            libs.push(name.to_string());
+        }
+    }
+    libs
+}
+
+/// Library search directories passed with `-Ldir` or `-L dir`
+fn search_dirs(args: &[String]) -> Vec<PathBuf> {
+    let mut dirs = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.strip_prefix("-L") {
+            Some("") => dirs.extend(args.next().map(PathBuf::from)),
+            Some(dir) => dirs.push(PathBuf::from(dir)),
+            None => {}
+        }
+    }
+    dirs
+}
+
+/// Whether a search directory has a library the linker would pick for `-lname`
+fn has_library(dir: &Path, name: &str) -> bool {
+    [
+        format!("lib{name}.dll.a"),
+        format!("lib{name}.a"),
+        format!("{name}.lib"),
+    ]
+    .iter()
+    .any(|file_name| dir.join(file_name).is_file())
+}
+
+/// Whether zig's mingw has a `.def` file for the library, zig builds those import libraries itself
+fn zig_ships_def(mingw_dir: &Path, arch: &str, name: &str) -> bool {
+    let name = name.to_lowercase();
+    let arch_dir = match arch {
+        "x86" => "lib32",
+        "x86_64" => "lib64",
+        "aarch64" => "libarm64",
+        _ => "libarm32",
+    };
+    ["lib-common", arch_dir].iter().any(|dir| {
+        [format!("{name}.def"), format!("{name}.def.in")]
+            .iter()
+            .any(|file_name| mingw_dir.join(dir).join(file_name).is_file())
+    })
+}
+
+/// Generate `lib{name}.a` in the cache, returning its directory
+fn generate_import_lib(name: &str, def: &str, arch: &str) -> Result<PathBuf> {
+    let machine = match arch {
+        "x86" => "i386",
+        "x86_64" => "i386:x86-64",
+        "aarch64" => "arm64",
+        "arm" | "thumb" => "arm",
+        _ => bail!("unsupported Windows architecture '{arch}'"),
+    };
+    let def = def_for_arch(def, arch);
+    // Keyed by the content, so that edited `.def` files are picked up
+    let hash = crc::Crc::<u16>::new(&crc::CRC_16_IBM_SDLC).checksum(def.as_bytes());
+    let dir = cache_dir()
+        .join("import-libs")
+        .join(arch)
+        .join(format!("{name}-{hash:x}"));
+    let lib = dir.join(format!("lib{name}.a"));
+    if lib.is_file() {
+        return Ok(dir);
+    }
+    fs::create_dir_all(&dir)?;
+    // Generate under temporary names, parallel links may need the same library
+    let pid = std::process::id();
+    let def_path = dir.join(format!("{name}.def.{pid}"));
+    fs::write(&def_path, def)?;
+    let tmp_lib = dir.join(format!("lib{name}.a.{pid}"));
+    let mut command = Zig::command()?;
+    command
+        .arg("dlltool")
+        .arg("-d")
+        .arg(&def_path)
+        .arg("-l")
+        .arg(&tmp_lib)
+        .args(["-m", machine]);
+    if arch == "x86" {
+        // Import by the undecorated names of stdcall functions
+        command.arg("-k");
+    }
+    let output = command.output().context("Failed to run `zig dlltool`")?;
+    if !output.status.success() {
+        bail!(
+            "`zig dlltool` failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    fs::rename(&tmp_lib, &lib)?;
+    fs::rename(&def_path, dir.join(format!("{name}.def")))?;
+    Ok(dir)
+}
+
+/// Strip the `@N` stdcall decorations of exports for architectures other than x86
+fn def_for_arch(def: &str, arch: &str) -> String {
+    static DECORATION: OnceLock<Regex> = OnceLock::new();
+    if arch == "x86" {
+        return def.to_string();
+    }
+    let decoration = DECORATION.get_or_init(|| Regex::new(r"(?m)^(\s*[^\s;@=]+)@\d+").unwrap());
+    decoration.replace_all(def, "$1").into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_linked_libs() {
+        let args = args(&[
+            "-lkernel32",
+            "-l",
+            "synchronization",
+            "-l:libpthread.a",
+            "-lkernel32",
+            "-Lfoo",
+            "-L",
+            "bar",
+        ]);
+        assert_eq!(linked_libs(&args), ["kernel32", "synchronization"]);
+        assert_eq!(
+            search_dirs(&args),
+            [PathBuf::from("foo"), PathBuf::from("bar")]
+        );
+    }
+
+    #[test]
+    fn test_def_registry() {
+        let tmp = tempfile::tempdir().unwrap();
+        fs::write(tmp.path().join("foo.def"), "LIBRARY foo.dll\n").unwrap();
+        fs::write(
+            tmp.path().join("synchronization.def"),
+            "LIBRARY custom.dll\n",
+        )
+        .unwrap();
+        let registry = DefRegistry::default();
+        assert!(registry.get("foo").unwrap().is_none());
+        assert!(
+            registry
+                .get("Synchronization")
+                .unwrap()
+                .unwrap()
+                .contains("WaitOnAddress@16")
+        );
+
+        let registry = DefRegistry {
+            dirs: vec![tmp.path().to_path_buf()],
+        };
+        assert_eq!(registry.get("FOO").unwrap().unwrap(), "LIBRARY foo.dll\n");
+        assert_eq!(
+            registry.get("synchronization").unwrap().unwrap(),
+            "LIBRARY custom.dll\n"
+        );
+    }
+
+    #[test]
+    fn test_provided_dropped_libs() {
+        let tmp = tempfile::tempdir().unwrap();
+        let lib_dir = tmp.path().join("lib");
+        let def_dir = tmp.path().join("defs");
+        fs::create_dir_all(&lib_dir).unwrap();
+        fs::create_dir_all(&def_dir).unwrap();
+        let link_args = args(&["-lwindows", "-lkernel32"]);
+        let registry = DefRegistry {
+            dirs: vec![def_dir.clone()],
+        };
+        assert!(
+            provided_dropped_libs(&link_args, &registry)
+                .unwrap()
+                .is_empty()
+        );
+
+        fs::write(def_dir.join("windows.def"), "LIBRARY windows.dll\n").unwrap();
+        assert_eq!(
+            provided_dropped_libs(&link_args, &registry).unwrap(),
+            ["-lwindows"]
+        );
+
+        fs::write(lib_dir.join("libwindows.a"), "").unwrap();
+        let mut link_args = link_args;
+        link_args.push(format!("-L{}", lib_dir.display()));
+        assert_eq!(
+            provided_dropped_libs(&link_args, &DefRegistry::default()).unwrap(),
+            ["-lwindows"]
+        );
+    }
+
+    #[test]
+    fn test_def_for_arch() {
+        let def = "LIBRARY \"a.dll\"\nEXPORTS\nWaitOnAddress@16\n  Foo@4 @ 3\nBar @2\nBaz=Qux@8\n";
+        assert_eq!(def_for_arch(def, "x86"), def);
+        assert_eq!(
+            def_for_arch(def, "x86_64"),
+            "LIBRARY \"a.dll\"\nEXPORTS\nWaitOnAddress\n  Foo @ 3\nBar @2\nBaz=Qux@8\n"
+        );
+    }
+
+    #[test]
+    fn test_zig_ships_def() {
+        let tmp = tempfile::tempdir().unwrap();
+        let mingw = tmp.path();
+        fs::create_dir_all(mingw.join("lib-common")).unwrap();
+        fs::create_dir_all(mingw.join("lib32")).unwrap();
+        fs::write(mingw.join("lib-common").join("kernel32.def.in"), "").unwrap();
+        fs::write(mingw.join("lib32").join("ws2_32.def"), "").unwrap();
+        assert!(zig_ships_def(mingw, "x86_64", "Kernel32"));
+        assert!(zig_ships_def(mingw, "x86", "ws2_32"));
+        assert!(!zig_ships_def(mingw, "x86_64", "ws2_32"));
+        assert!(!zig_ships_def(mingw, "x86_64", "synchronization"));
+    }
+}
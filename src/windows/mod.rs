@@ -0,0 +1,5 @@
+pub mod gendef;
+pub(crate) mod import_libs;
+pub(crate) mod pe_security;
+pub mod windres;
+pub mod xwin;
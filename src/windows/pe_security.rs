@@ -0,0 +1,320 @@
+//! Security characteristics of linked PE files
+//!
+//! rustc asks for `-Wl,--dynamicbase` and, for i686, `-Wl,--large-address-aware` when linking
+//! windows-gnu targets, which zig's linker rejects, see `linker-arg-rules.toml`. We set the
+//! equivalent PE header bits after linking instead.
+
+use std::path::Path;
+
+use anyhow::{Context, Result, bail};
+use fs_err as fs;
+use goblin::pe::characteristic::{IMAGE_FILE_LARGE_ADDRESS_AWARE, IMAGE_FILE_RELOCS_STRIPPED};
+use goblin::pe::dll_characteristic::{
+    IMAGE_DLLCHARACTERISTICS_DYNAMIC_BASE, IMAGE_DLLCHARACTERISTICS_HIGH_ENTROPY_VA,
+    IMAGE_DLLCHARACTERISTICS_NX_COMPAT,
+};
+use scroll::{Pread, Pwrite};
+
+const PE32_PLUS_MAGIC: u16 = 0x20b;
+
+/// Security characteristics requested with GNU ld options
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct RequestedCharacteristics {
+    /// `--dynamicbase`, which also implies `--nxcompat` and `--high-entropy-va` since binutils 2.36
+    pub dynamic_base: bool,
+    /// `--large-address-aware`
+    pub large_address_aware: bool,
+}
+
+impl RequestedCharacteristics {
+    /// The characteristics requested by linker arguments
+    pub(crate) fn from_args<'a>(args: impl IntoIterator<Item = &'a str>) -> Self {
+        let mut requested = Self::default();
+        for arg in args {
+            let Some(options) = arg.strip_prefix("-Wl,") else {
+                continue;
+            };
+            for option in options.split(',') {
+                match option {
+                    "--dynamicbase" => requested.dynamic_base = true,
+                    "--large-address-aware" => requested.large_address_aware = true,
+                    _ => {}
+                }
+            }
+        }
+        requested
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// Offsets of the header fields we read and write
+struct PeHeader {
+    characteristics: usize,
+    check_sum: usize,
+    dll_characteristics: usize,
+    pe32_plus: bool,
+}
+
+impl PeHeader {
+    /// Locate the headers, returns `None` if `data` is not a PE file
+    fn parse(data: &[u8]) -> Result<Option<Self>> {
+        if !data.starts_with(b"MZ") {
+            return Ok(None);
+        }
+        let pe = data.pread_with::<u32>(0x3c, scroll::LE)? as usize;
+        if data.get(pe..pe + 4) != Some(&b"PE\0\0"[..]) {
+            return Ok(None);
+        }
+        let characteristics = pe + 22;
+        let optional_header = pe + 24;
+        let pe32_plus = match data.pread_with::<u16>(optional_header, scroll::LE)? {
+            0x10b => false,
+            PE32_PLUS_MAGIC => true,
+            magic => bail!("unknown PE optional header magic {magic:#x}"),
+        };
+        let header = Self {
+            characteristics,
+            check_sum: optional_header + 64,
+            dll_characteristics: optional_header + 70,
+            pe32_plus,
+        };
+        // Both PE32 and PE32+ optional headers are longer than this
+        if data.len() < header.dll_characteristics + 2 {
+            bail!("PE optional header is truncated");
+        }
+        Ok(Some(header))
+    }
+}
+
+/// Set the requested characteristics on the PE file at `path`
+///
+/// DYNAMIC_BASE isn't set if the relocations were stripped, as the image can't be moved.
+/// Files that aren't PE files are ignored.
+pub(crate) fn apply_requested_characteristics(
+    path: &Path,
+    requested: RequestedCharacteristics,
+) -> Result<()> {
+    let mut data = fs::read(path)?;
+    let changed = apply(&mut data, requested)
+        .with_context(|| format!("Failed to set PE characteristics of `{}`", path.display()))?;
+    if changed {
+        fs::write(path, data)?;
+    }
+    Ok(())
+}
+
+/// Set the requested characteristics, returns whether the file changed
+fn apply(data: &mut [u8], requested: RequestedCharacteristics) -> Result<bool> {
+    let Some(header) = PeHeader::parse(data)? else {
+        return Ok(false);
+    };
+    let characteristics: u16 = data.pread_with(header.characteristics, scroll::LE)?;
+    let dll_characteristics: u16 = data.pread_with(header.dll_characteristics, scroll::LE)?;
+    let mut new_characteristics = characteristics;
+    let mut new_dll_characteristics = dll_characteristics;
+    if requested.large_address_aware {
+        new_characteristics |= IMAGE_FILE_LARGE_ADDRESS_AWARE;
+    }
+    if requested.dynamic_base && characteristics & IMAGE_FILE_RELOCS_STRIPPED == 0 {
+        new_dll_characteristics |=
+            IMAGE_DLLCHARACTERISTICS_DYNAMIC_BASE | IMAGE_DLLCHARACTERISTICS_NX_COMPAT;
+        if header.pe32_plus {
+            new_dll_characteristics |= IMAGE_DLLCHARACTERISTICS_HIGH_ENTROPY_VA;
+        }
+    }
+    if new_characteristics == characteristics && new_dll_characteristics == dll_characteristics {
+        return Ok(false);
+    }
+    data.pwrite_with(new_characteristics, header.characteristics, scroll::LE)?;
+    data.pwrite_with(
+        new_dll_characteristics,
+        header.dll_characteristics,
+        scroll::LE,
+    )?;
+    // Only drivers and some system DLLs must have a valid checksum, keep it valid if there is one
+    let check_sum: u32 = data.pread_with(header.check_sum, scroll::LE)?;
+    if check_sum != 0 {
+        let check_sum = pe_check_sum(data, header.check_sum);
+        data.pwrite_with(check_sum, header.check_sum, scroll::LE)?;
+    }
+    Ok(true)
+}
+
+/// The PE checksum, like `CheckSumMappedFile` computes it
+fn pe_check_sum(data: &[u8], check_sum_offset: usize) -> u32 {
+    let mut sum: u32 = 0;
+    for (index, word) in data.chunks(2).enumerate() {
+        let offset = index * 2;
+        if offset == check_sum_offset || offset == check_sum_offset + 2 {
+            continue;
+        }
+        let word = u16::from_le_bytes([word[0], word.get(1).copied().unwrap_or(0)]);
+        sum += u32::from(word);
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    sum = (sum & 0xffff) + (sum >> 16);
+    sum.wrapping_add(data.len() as u32)
+}
+
+/// The security characteristics a PE file has, returns `None` if `data` is not a PE file
+pub(crate) fn security_characteristics(data: &[u8]) -> Result<Option<Vec<&'static str>>> {
+    let Some(header) = PeHeader::parse(data)? else {
+        return Ok(None);
+    };
+    let characteristics: u16 = data.pread_with(header.characteristics, scroll::LE)?;
+    let dll_characteristics: u16 = data.pread_with(header.dll_characteristics, scroll::LE)?;
+    let mut names = Vec::new();
+    for (flag, name) in [
+        (IMAGE_DLLCHARACTERISTICS_DYNAMIC_BASE, "DYNAMIC_BASE"),
+        (IMAGE_DLLCHARACTERISTICS_HIGH_ENTROPY_VA, "HIGH_ENTROPY_VA"),
+        (IMAGE_DLLCHARACTERISTICS_NX_COMPAT, "NX_COMPAT"),
+    ] {
+        if dll_characteristics & flag != 0 {
+            names.push(name);
+        }
+    }
+    if characteristics & IMAGE_FILE_LARGE_ADDRESS_AWARE != 0 {
+        names.push("LARGE_ADDRESS_AWARE");
+    }
+    Ok(Some(names))
+}
+
+/// Print the security characteristics of the PE artifact at `path`, other files are ignored
+pub(crate) fn report_pe_artifact(path: &Path) -> Result<()> {
+    let data = fs::read(path)?;
+    let Some(names) = security_characteristics(&data)
+        .with_context(|| format!("Failed to read PE characteristics of `{}`", path.display()))?
+    else {
+        return Ok(());
+    };
+    let names = if names.is_empty() {
+        "no security characteristics".to_string()
+    } else {
+        names.join(", ")
+    };
+    eprintln!("cargo-zigbuild: `{}` has {names}", path.display());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::fixture;
+
+    /// Clear the bits the fixtures were linked with
+    fn clear(data: &mut [u8], characteristics: u16, dll_characteristics: u16) {
+        let header = PeHeader::parse(data).unwrap().unwrap();
+        let value: u16 = data.pread_with(header.characteristics, scroll::LE).unwrap();
+        data.pwrite_with(value & !characteristics, header.characteristics, scroll::LE)
+            .unwrap();
+        let value: u16 = data
+            .pread_with(header.dll_characteristics, scroll::LE)
+            .unwrap();
+        data.pwrite_with(
+            value & !dll_characteristics,
+            header.dll_characteristics,
+            scroll::LE,
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn test_requested_characteristics() {
+        let requested = RequestedCharacteristics::from_args([
+            "-Wl,--dynamicbase",
+            "-Wl,--disable-auto-image-base",
+            "-lkernel32",
+        ]);
+        assert!(requested.dynamic_base);
+        assert!(!requested.large_address_aware);
+        let requested =
+            RequestedCharacteristics::from_args(["-Wl,--nxcompat,--large-address-aware"]);
+        assert!(requested.large_address_aware);
+        assert!(RequestedCharacteristics::from_args(["-o", "foo.exe"]).is_empty());
+    }
+
+    #[test]
+    fn test_apply_requested_characteristics() {
+        let all = IMAGE_DLLCHARACTERISTICS_DYNAMIC_BASE
+            | IMAGE_DLLCHARACTERISTICS_HIGH_ENTROPY_VA
+            | IMAGE_DLLCHARACTERISTICS_NX_COMPAT;
+
+        let mut data = fixture("test_x86_64.dll");
+        assert_eq!(
+            security_characteristics(&data).unwrap().unwrap(),
+            [
+                "DYNAMIC_BASE",
+                "HIGH_ENTROPY_VA",
+                "NX_COMPAT",
+                "LARGE_ADDRESS_AWARE"
+            ]
+        );
+        clear(&mut data, 0, all);
+        assert_eq!(
+            security_characteristics(&data).unwrap().unwrap(),
+            ["LARGE_ADDRESS_AWARE"]
+        );
+        let requested = RequestedCharacteristics {
+            dynamic_base: true,
+            large_address_aware: false,
+        };
+        assert!(apply(&mut data, requested).unwrap());
+        assert_eq!(data, fixture("test_x86_64.dll"));
+        assert!(!apply(&mut data, requested).unwrap());
+
+        let mut data = fixture("test_i686.dll");
+        clear(&mut data, 0, all);
+        let requested = RequestedCharacteristics {
+            dynamic_base: true,
+            large_address_aware: true,
+        };
+        assert!(apply(&mut data, requested).unwrap());
+        assert_eq!(
+            security_characteristics(&data).unwrap().unwrap(),
+            ["DYNAMIC_BASE", "NX_COMPAT", "LARGE_ADDRESS_AWARE"]
+        );
+
+        // An image without relocations can't be moved
+        let mut data = fixture("test_i686.dll");
+        clear(&mut data, 0, all);
+        let header = PeHeader::parse(&data).unwrap().unwrap();
+        let value: u16 = data.pread_with(header.characteristics, scroll::LE).unwrap();
+        data.pwrite_with(
+            value | IMAGE_FILE_RELOCS_STRIPPED,
+            header.characteristics,
+            scroll::LE,
+        )
+        .unwrap();
+        let requested = RequestedCharacteristics {
+            dynamic_base: true,
+            large_address_aware: false,
+        };
+        assert!(!apply(&mut data, requested).unwrap());
+
+        assert!(security_characteristics(b"\x7fELF").unwrap().is_none());
+    }
+
+    #[test]
+    fn test_pe_check_sum() {
+        let mut data = fixture("test_x86_64.dll");
+        let header = PeHeader::parse(&data).unwrap().unwrap();
+        data.pwrite_with(1u32, header.check_sum, scroll::LE)
+            .unwrap();
+        clear(&mut data, 0, IMAGE_DLLCHARACTERISTICS_NX_COMPAT);
+        let requested = RequestedCharacteristics {
+            dynamic_base: true,
+            large_address_aware: false,
+        };
+        assert!(apply(&mut data, requested).unwrap());
+        let check_sum: u32 = data.pread_with(header.check_sum, scroll::LE).unwrap();
+        assert_eq!(check_sum, pe_check_sum(&data, header.check_sum));
+        assert_ne!(check_sum, 1);
+        // The checksum field itself isn't part of the sum
+        data.pwrite_with(0u32, header.check_sum, scroll::LE)
+            .unwrap();
+        assert_eq!(pe_check_sum(&data, header.check_sum), check_sum);
+    }
+}
@@ -0,0 +1,256 @@
+//! GNU `windres` on top of `zig rc`
+//!
+//! Build scripts like `embed-resource` and `winres`, and CMake, compile Windows resource
+//! scripts to COFF objects with `windres`, `zig rc` can do the same since zig 0.14.
+
+use std::path::Path;
+
+use anyhow::{Context, Result, bail};
+
+/// Translate `windres` arguments to `zig rc` arguments
+pub(crate) fn rc_args(args: &[String]) -> Result<Vec<String>> {
+    let mut input = None;
+    let mut output = None;
+    let mut input_format = None;
+    let mut output_format = None;
+    let mut target = None;
+    let mut options = Vec::new();
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let (name, attached) = if let Some(long) = arg.strip_prefix("--") {
+            match long.split_once('=') {
+                Some((name, value)) => (name, Some(value)),
+                None => (long, None),
+            }
+        } else if arg.len() > 1 && arg.starts_with('-') {
+            // Short options may have their value attached, like `-DNAME=1`
+            let split = arg.char_indices().nth(2).map_or(arg.len(), |(i, _)| i);
+            let (flag, rest) = arg.split_at(split);
+            (&flag[1..], (!rest.is_empty()).then_some(rest))
+        } else {
+            if input.is_none() {
+                input = Some(arg.clone());
+            } else if output.is_none() {
+                output = Some(arg.clone());
+            } else {
+                bail!("unexpected windres argument `{arg}`");
+            }
+            continue;
+        };
+        if matches!(name, "v" | "verbose") {
+            options.push("/v".to_string());
+            continue;
+        }
+        if matches!(name, "r" | "use-temp-file" | "no-use-temp-file") {
+            continue;
+        }
+        let mut value = || -> Result<String> {
+            match attached {
+                Some(value) => Ok(value.to_string()),
+                None => args
+                    .next()
+                    .cloned()
+                    .with_context(|| format!("windres option `{arg}` requires a value")),
+            }
+        };
+        match name {
+            "i" | "input" => input = Some(value()?),
+            "o" | "output" => output = Some(value()?),
+            "J" | "input-format" => input_format = Some(value()?),
+            "O" | "output-format" => output_format = Some(value()?),
+            "F" | "target" => target = Some(value()?),
+            "I" | "include-dir" => options.extend(["/i".to_string(), value()?]),
+            "D" | "define" => options.extend(["/d".to_string(), value()?]),
+            "U" | "undefine" => options.extend(["/u".to_string(), value()?]),
+            "l" | "language" => {
+                let language = value()?;
+                let language = language.trim_start_matches("0x").to_string();
+                options.extend(["/l".to_string(), language]);
+            }
+            "c" | "codepage" => options.extend(["/c".to_string(), value()?]),
+            // zig rc has its own preprocessor
+            "preprocessor" | "preprocessor-arg" => {
+                value()?;
+            }
+            _ => bail!("unsupported windres option `{arg}`"),
+        }
+    }
+
+    let input = input.context("windres reading from stdin is not supported, pass an input file")?;
+    let output =
+        output.context("windres writing to stdout is not supported, pass an output file")?;
+    let output_format = match output_format {
+        Some(format) => format,
+        // Like windres, guess the format from the extension and default to COFF
+        None => match Path::new(&output).extension().and_then(|ext| ext.to_str()) {
+            Some(ext) if ext.eq_ignore_ascii_case("res") => "res".to_string(),
+            Some(ext) if ext.eq_ignore_ascii_case("rc") => "rc".to_string(),
+            _ => "coff".to_string(),
+        },
+    };
+
+    let mut rc_args = Vec::new();
+    match input_format.as_deref() {
+        None | Some("rc") => {}
+        Some("res") => rc_args.extend(["/:input-format".to_string(), "res".to_string()]),
+        Some(format) => bail!("unsupported windres input format `{format}`"),
+    }
+    match output_format.as_str() {
+        "res" => rc_args.extend(["/:output-format".to_string(), "res".to_string()]),
+        "coff" => {
+            rc_args.extend(["/:output-format".to_string(), "coff".to_string()]);
+            if let Some(target) = &target {
+                let arch = coff_arch(target)
+                    .with_context(|| format!("unsupported windres target `{target}`"))?;
+                rc_args.extend(["/:target".to_string(), arch.to_string()]);
+            }
+        }
+        format => bail!("unsupported windres output format `{format}`"),
+    }
+    rc_args.extend(options);
+    rc_args.extend(["/fo".to_string(), output, "--".to_string(), input]);
+    Ok(rc_args)
+}
+
+/// Whether `zig rc` arguments produce a COFF object
+pub(crate) fn outputs_coff(rc_args: &[String]) -> bool {
+    rc_args
+        .windows(2)
+        .any(|pair| pair[0] == "/:output-format" && pair[1] == "coff")
+}
+
+/// The zig architecture of a BFD target like `pe-x86-64`
+fn coff_arch(bfd_target: &str) -> Option<&'static str> {
+    let target = bfd_target
+        .strip_prefix("pei-")
+        .or_else(|| bfd_target.strip_prefix("pe-"))?;
+    match target {
+        "x86-64" => Some("x86_64"),
+        "i386" => Some("x86"),
+        "aarch64-little" => Some("aarch64"),
+        _ => None,
+    }
+}
+
+/// The `--target` of a prefixed `windres` like `x86_64-w64-mingw32-windres`
+pub fn program_target(program_name: &str) -> Option<String> {
+    let prefix = program_name.strip_suffix("-windres")?;
+    let bfd_target = match prefix.split('-').next()? {
+        "x86_64" => "pe-x86-64",
+        "i686" | "i586" | "i386" => "pe-i386",
+        "aarch64" => "pe-aarch64-little",
+        _ => return None,
+    };
+    Some(format!("--target={bfd_target}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rc_args(args: &[&str]) -> Result<Vec<String>> {
+        super::rc_args(&args.iter().map(|s| s.to_string()).collect::<Vec<_>>())
+    }
+
+    #[test]
+    fn test_rc_args() {
+        // embed-resource
+        let args = rc_args(&[
+            "--input",
+            "app.rc",
+            "--output-format=coff",
+            "--target",
+            "pe-x86-64",
+            "--output",
+            "out/app.o",
+            "--include-dir",
+            "res",
+            "-D",
+            "VERSION=1",
+        ])
+        .unwrap();
+        assert_eq!(
+            args,
+            [
+                "/:output-format",
+                "coff",
+                "/:target",
+                "x86_64",
+                "/i",
+                "res",
+                "/d",
+                "VERSION=1",
+                "/fo",
+                "out/app.o",
+                "--",
+                "app.rc"
+            ]
+        );
+        assert!(outputs_coff(&args));
+
+        // CMake
+        let args = rc_args(&[
+            "-O",
+            "coff",
+            "-DNDEBUG",
+            "-Iinclude",
+            "-I",
+            "gen",
+            "app.rc",
+            "app.rc.obj",
+        ])
+        .unwrap();
+        assert_eq!(
+            args,
+            [
+                "/:output-format",
+                "coff",
+                "/d",
+                "NDEBUG",
+                "/i",
+                "include",
+                "/i",
+                "gen",
+                "/fo",
+                "app.rc.obj",
+                "--",
+                "app.rc"
+            ]
+        );
+
+        let args = rc_args(&["-i", "app.rc", "-o", "app.res", "-l", "0x409"]).unwrap();
+        assert_eq!(
+            args,
+            [
+                "/:output-format",
+                "res",
+                "/l",
+                "409",
+                "/fo",
+                "app.res",
+                "--",
+                "app.rc"
+            ]
+        );
+        assert!(!outputs_coff(&args));
+
+        assert!(rc_args(&["app.rc"]).is_err());
+        assert!(rc_args(&["-i", "app.rc", "-o", "app.o", "--bogus"]).is_err());
+        assert!(rc_args(&["-i", "app.rc", "-o", "app.o", "-F", "elf64-x86-64"]).is_err());
+        assert!(rc_args(&["-i", "app.rc", "-o", "app.o", "-I"]).is_err());
+    }
+
+    #[test]
+    fn test_program_target() {
+        assert_eq!(
+            program_target("x86_64-w64-mingw32-windres").as_deref(),
+            Some("--target=pe-x86-64")
+        );
+        assert_eq!(
+            program_target("i686-w64-mingw32-windres").as_deref(),
+            Some("--target=pe-i386")
+        );
+        assert_eq!(program_target("windres"), None);
+    }
+}
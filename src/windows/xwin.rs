@@ -0,0 +1,174 @@
+//! Windows SDK and CRT directories splatted by [xwin](https://github.com/Jake-Shadle/xwin)
+
+use std::env;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Result, anyhow, bail};
+
+use crate::config::ZigbuildConfig;
+
+/// Environment variable overriding the `xwin-dir` setting
+pub(crate) const XWIN_DIR_ENV: &str = "CARGO_ZIGBUILD_XWIN_DIR";
+
+/// A Windows SDK and MSVC CRT created by `xwin splat`, which zig needs to link
+/// `*-windows-msvc` targets on non-Windows hosts:
+///
+/// ```text
+/// crt/include
+/// crt/lib/x86_64
+/// sdk/include/{ucrt,um,shared}
+/// sdk/lib/{ucrt,um}/x86_64
+/// ```
+///
+/// Library directories may also use Microsoft's architecture names, like `x64` and `arm64`.
+#[derive(Debug, Clone)]
+pub struct XwinSdk {
+    root: PathBuf,
+}
+
+impl XwinSdk {
+    pub fn new(root: impl Into<PathBuf>) -> Result<Self> {
+        let root = root.into();
+        for dir in ["crt", "sdk"] {
+            if !root.join(dir).is_dir() {
+                bail!(
+                    "'{}' is not an xwin splat directory, '{dir}' is missing",
+                    root.display()
+                );
+            }
+        }
+        Ok(Self { root })
+    }
+
+    /// The SDK set with `CARGO_ZIGBUILD_XWIN_DIR` or the `xwin-dir` setting
+    pub(crate) fn from_config(config: &ZigbuildConfig) -> Result<Option<Self>> {
+        env::var_os(XWIN_DIR_ENV)
+            .map(PathBuf::from)
+            .or_else(|| config.xwin_dir.clone())
+            .map(Self::new)
+            .transpose()
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Header search paths of the CRT and the SDK
+    pub fn include_dirs(&self) -> Vec<PathBuf> {
+        let sdk_include = self.root.join("sdk").join("include");
+        vec![
+            self.root.join("crt").join("include"),
+            sdk_include.join("ucrt"),
+            sdk_include.join("um"),
+            sdk_include.join("shared"),
+        ]
+    }
+
+    /// Library search paths of the CRT, the ucrt and the SDK for an architecture
+    /// like `x86_64` or `i686`
+    pub fn lib_dirs(&self, arch: &str) -> Result<Vec<PathBuf>> {
+        Ok(self.arch_lib_dirs(arch)?.to_vec())
+    }
+
+    /// The CRT, ucrt and SDK library directories
+    fn arch_lib_dirs(&self, arch: &str) -> Result<[PathBuf; 3]> {
+        let sdk_lib = self.root.join("sdk").join("lib");
+        Ok([
+            arch_dir(&self.root.join("crt").join("lib"), arch)?,
+            arch_dir(&sdk_lib.join("ucrt"), arch)?,
+            arch_dir(&sdk_lib.join("um"), arch)?,
+        ])
+    }
+
+    /// A zig libc installation file, passed to zig with `ZIG_LIBC`
+    ///
+    /// zig adds the `um` and `shared` headers next to `include_dir` itself.
+    pub(crate) fn libc_file(&self, arch: &str) -> Result<String> {
+        let [crt_lib, ucrt_lib, um_lib] = self.arch_lib_dirs(arch)?;
+        let sdk_include = self.root.join("sdk").join("include");
+        Ok(format!(
+            "include_dir={}\nsys_include_dir={}\ncrt_dir={}\nmsvc_lib_dir={}\nkernel32_lib_dir={}\ngcc_dir=\n",
+            sdk_include.join("ucrt").display(),
+            self.root.join("crt").join("include").display(),
+            ucrt_lib.display(),
+            crt_lib.display(),
+            um_lib.display(),
+        ))
+    }
+}
+
+/// The architecture subdirectory of a library directory, named by xwin or by Microsoft
+fn arch_dir(dir: &Path, arch: &str) -> Result<PathBuf> {
+    let names: &[&str] = match arch {
+        "x86_64" => &["x86_64", "x64"],
+        "i686" | "i586" | "i386" | "x86" => &["x86"],
+        "aarch64" => &["aarch64", "arm64"],
+        "thumbv7a" | "arm" => &["aarch", "arm"],
+        _ => bail!("unsupported xwin architecture '{arch}'"),
+    };
+    names
+        .iter()
+        .map(|name| dir.join(name))
+        .find(|dir| dir.is_dir())
+        .ok_or_else(|| {
+            anyhow!(
+                "'{}' has no libraries for {arch}, expected one of {}",
+                dir.display(),
+                names.join(", ")
+            )
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fs_err as fs;
+
+    #[test]
+    fn test_xwin_sdk() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        assert!(XwinSdk::new(root).is_err());
+        for dir in [
+            "crt/include",
+            "crt/lib/x86_64",
+            "crt/lib/arm64",
+            "sdk/include/ucrt",
+            "sdk/lib/ucrt/x86_64",
+            "sdk/lib/ucrt/arm64",
+            "sdk/lib/um/x86_64",
+            "sdk/lib/um/arm64",
+        ] {
+            fs::create_dir_all(root.join(dir)).unwrap();
+        }
+        let sdk = XwinSdk::new(root).unwrap();
+        assert_eq!(sdk.include_dirs()[1], root.join("sdk/include/ucrt"));
+        assert_eq!(
+            sdk.lib_dirs("aarch64").unwrap(),
+            [
+                root.join("crt/lib/arm64"),
+                root.join("sdk/lib/ucrt/arm64"),
+                root.join("sdk/lib/um/arm64"),
+            ]
+        );
+        let err = sdk.lib_dirs("i686").unwrap_err();
+        assert!(
+            err.to_string()
+                .ends_with("has no libraries for i686, expected one of x86")
+        );
+
+        let libc = sdk.libc_file("x86_64").unwrap();
+        let root = root.display();
+        assert_eq!(
+            libc,
+            format!(
+                "include_dir={root}/sdk/include/ucrt\n\
+                 sys_include_dir={root}/crt/include\n\
+                 crt_dir={root}/sdk/lib/ucrt/x86_64\n\
+                 msvc_lib_dir={root}/crt/lib/x86_64\n\
+                 kernel32_lib_dir={root}/sdk/lib/um/x86_64\n\
+                 gcc_dir=\n"
+            )
+        );
+    }
+}
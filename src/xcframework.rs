@@ -0,0 +1,477 @@
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::path::PathBuf;
+
+use anyhow::{Context, Result, bail};
+use cargo_metadata::TargetKind;
+use cargo_metadata::camino::{Utf8Path, Utf8PathBuf};
+use clap::Parser;
+use fs_err as fs;
+
+use crate::Build;
+use crate::fat::{copy_dir_all, replace_target_dir};
+use crate::macos::sdk::apple_arch;
+use crate::target_version::split_target_version;
+
+/// Build static libraries or dylibs for Apple targets and package them into `.xcframework` bundles
+#[derive(Clone, Debug, Default, Parser)]
+#[command(
+    after_help = "Run `cargo help build` for more detailed information.",
+    display_order = 1
+)]
+pub struct Xcframework {
+    #[command(flatten)]
+    pub cargo: cargo_options::Build,
+
+    /// Package dynamic libraries instead of static libraries
+    #[arg(long)]
+    pub dylib: bool,
+
+    /// Directory of headers to ship with each library
+    #[arg(long, value_name = "DIRECTORY")]
+    pub headers: Option<PathBuf>,
+
+    /// Directory to write the `.xcframework` bundles to,
+    /// defaults to `xcframework/<profile>` in the target directory
+    #[arg(long, value_name = "DIRECTORY")]
+    pub output_dir: Option<PathBuf>,
+
+    /// Disable zig linker
+    #[arg(skip)]
+    pub disable_zig_linker: bool,
+
+    /// Enable zig ar
+    #[arg(skip)]
+    pub enable_zig_ar: bool,
+}
+
+/// An architecture and the library built for it
+type Slice<'a> = (&'static str, &'a Utf8PathBuf);
+/// Slices by platform and variant
+type Slices<'a> = BTreeMap<(&'static str, Option<&'static str>), Vec<Slice<'a>>>;
+
+/// A library of an xcframework, the slices of one platform and variant
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Library {
+    platform: &'static str,
+    /// `simulator` or `maccatalyst`
+    variant: Option<&'static str>,
+    /// Apple architecture names, like `arm64`
+    archs: Vec<&'static str>,
+    /// File name of the library
+    file_name: String,
+    has_headers: bool,
+}
+
+impl Library {
+    /// The directory of the library in the xcframework, like `ios-arm64_x86_64-simulator`
+    fn identifier(&self) -> String {
+        let mut identifier = format!("{}-{}", self.platform, self.archs.join("_"));
+        if let Some(variant) = self.variant {
+            identifier.push('-');
+            identifier.push_str(variant);
+        }
+        identifier
+    }
+}
+
+impl Xcframework {
+    /// Execute `cargo zigbuild xcframework` command
+    pub fn execute(&self) -> Result<()> {
+        if self.cargo.target.is_empty() {
+            bail!("`--target` is required, pass each Apple target to include");
+        }
+        let mut targets = Vec::new();
+        for target in &self.cargo.target {
+            let (rust_target, _) = split_target_version(target)?;
+            let (platform, variant) = apple_platform(rust_target)?;
+            let arch = apple_arch(rust_target)
+                .with_context(|| format!("unsupported architecture of `{rust_target}`"))?;
+            targets.push((rust_target, platform, variant, arch));
+        }
+        if let Some(headers) = &self.headers
+            && !headers.is_dir()
+        {
+            bail!("headers directory '{}' does not exist", headers.display());
+        }
+
+        let mut build = Build::from(self.cargo.clone());
+        build.disable_zig_linker = self.disable_zig_linker;
+        build.enable_zig_ar = self.enable_zig_ar;
+        let artifacts = build.build_artifacts()?;
+
+        let (kind, extension) = if self.dylib {
+            (TargetKind::CDyLib, "dylib")
+        } else {
+            (TargetKind::StaticLib, "a")
+        };
+        // Libraries by file name, then by platform and variant
+        let mut libraries: BTreeMap<&str, Slices> = BTreeMap::new();
+        for path in artifacts
+            .iter()
+            .filter(|artifact| artifact.target.kind.contains(&kind))
+            .flat_map(|artifact| &artifact.filenames)
+            .filter(|path| path.extension() == Some(extension))
+        {
+            let Some(&(_, platform, variant, arch)) =
+                targets.iter().find(|(target, ..)| built_for(path, target))
+            else {
+                continue;
+            };
+            let file_name = path.file_name().unwrap_or_default();
+            libraries
+                .entry(file_name)
+                .or_default()
+                .entry((platform, variant))
+                .or_default()
+                .push((arch, path));
+        }
+        if libraries.is_empty() {
+            bail!(
+                "no {} libraries were built, add `{}` to `crate-type` in Cargo.toml",
+                if self.dylib { "dynamic" } else { "static" },
+                if self.dylib { "cdylib" } else { "staticlib" }
+            );
+        }
+
+        for (file_name, slices) in libraries {
+            let paths: Vec<&Utf8PathBuf> =
+                slices.values().flatten().map(|(_, path)| *path).collect();
+            let missing: Vec<&str> = targets
+                .iter()
+                .map(|(target, ..)| *target)
+                .filter(|target| !paths.iter().any(|path| built_for(path, target)))
+                .collect();
+            if !missing.is_empty() {
+                bail!(
+                    "`{file_name}` wasn't built for {}",
+                    missing
+                        .iter()
+                        .map(|target| format!("`{target}`"))
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                );
+            }
+            let name = file_name
+                .strip_suffix(&format!(".{extension}"))
+                .unwrap_or(file_name);
+            let name = name.strip_prefix("lib").unwrap_or(name);
+            let output_dir = match &self.output_dir {
+                Some(dir) => Utf8PathBuf::try_from(dir.clone())?,
+                None => {
+                    // Next to the target directories, like `target/xcframework/release`
+                    let target = targets[0].0;
+                    let path = paths.iter().find(|path| built_for(path, target)).unwrap();
+                    replace_target_dir(path.parent().unwrap(), target, "xcframework")
+                        .with_context(|| format!("`{path}` is not in the `{target}` directory"))?
+                }
+            };
+            let xcframework = output_dir.join(format!("{name}.xcframework"));
+            self.write_xcframework(&xcframework, file_name, &slices)?;
+            eprintln!("cargo-zigbuild: wrote `{xcframework}`");
+        }
+        Ok(())
+    }
+
+    fn write_xcframework(
+        &self,
+        xcframework: &Utf8Path,
+        file_name: &str,
+        slices: &Slices,
+    ) -> Result<()> {
+        if xcframework.exists() {
+            fs::remove_dir_all(xcframework)?;
+        }
+        let mut libraries = Vec::new();
+        for (&(platform, variant), inputs) in slices {
+            let mut inputs = inputs.clone();
+            inputs.sort();
+            let library = Library {
+                platform,
+                variant,
+                archs: inputs.iter().map(|(arch, _)| *arch).collect(),
+                file_name: file_name.to_string(),
+                has_headers: self.headers.is_some(),
+            };
+            let library_dir = xcframework.join(library.identifier());
+            fs::create_dir_all(&library_dir)?;
+            let paths: Vec<&Utf8PathBuf> = inputs.iter().map(|(_, path)| *path).collect();
+            write_library(&paths, &library_dir.join(file_name))?;
+            if let Some(headers) = &self.headers {
+                let headers = Utf8Path::from_path(headers).context("non UTF-8 headers path")?;
+                copy_dir_all(headers, &library_dir.join("Headers"))?;
+            }
+            libraries.push(library);
+        }
+        fs::write(xcframework.join("Info.plist"), info_plist(&libraries))?;
+        Ok(())
+    }
+}
+
+/// Whether an artifact is in the directory of `target`
+fn built_for(path: &Utf8Path, target: &str) -> bool {
+    path.components()
+        .any(|component| component.as_str() == target)
+}
+
+/// Copy a single slice, or merge the slices of several architectures into a fat file
+fn write_library(inputs: &[&Utf8PathBuf], output: &Utf8Path) -> Result<()> {
+    if let [input] = inputs {
+        fs::copy(input, output)?;
+        return Ok(());
+    }
+    #[cfg(feature = "universal2")]
+    return crate::fat::merge_binaries(inputs, output);
+    #[cfg(not(feature = "universal2"))]
+    bail!(
+        "merging several architectures into `{output}` requires cargo-zigbuild \
+         to be built with the `universal2` feature"
+    )
+}
+
+/// The xcframework platform and variant of an Apple target, like `ios` and `simulator`
+/// for `aarch64-apple-ios-sim`
+fn apple_platform(rust_target: &str) -> Result<(&'static str, Option<&'static str>)> {
+    let mut parts = rust_target.split('-');
+    let arch = parts.next().unwrap_or_default();
+    let (vendor, os, env) = (parts.next(), parts.next(), parts.next());
+    if vendor != Some("apple") {
+        bail!("`{rust_target}` is not an Apple target");
+    }
+    let platform = match os {
+        Some("darwin") => "macos",
+        Some("ios") => "ios",
+        Some("tvos") => "tvos",
+        Some("watchos") => "watchos",
+        Some("visionos") => "xros",
+        _ => bail!("`{rust_target}` is not supported by xcframeworks"),
+    };
+    let variant = match env {
+        Some("macabi") => Some("maccatalyst"),
+        Some("sim") => Some("simulator"),
+        // x86 iOS targets only exist as simulators
+        None if platform != "macos" && matches!(arch, "x86_64" | "i386") => Some("simulator"),
+        None => None,
+        Some(_) => bail!("`{rust_target}` is not supported by xcframeworks"),
+    };
+    Ok((platform, variant))
+}
+
+fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+/// The `Info.plist` of an xcframework
+fn info_plist(libraries: &[Library]) -> String {
+    let mut plist = String::from(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+	<key>AvailableLibraries</key>
+	<array>
+"#,
+    );
+    for library in libraries {
+        let file_name = xml_escape(&library.file_name);
+        plist.push_str("\t\t<dict>\n");
+        let _ = writeln!(
+            plist,
+            "\t\t\t<key>BinaryPath</key>\n\t\t\t<string>{file_name}</string>"
+        );
+        if library.has_headers {
+            plist.push_str("\t\t\t<key>HeadersPath</key>\n\t\t\t<string>Headers</string>\n");
+        }
+        let _ = writeln!(
+            plist,
+            "\t\t\t<key>LibraryIdentifier</key>\n\t\t\t<string>{}</string>",
+            library.identifier()
+        );
+        let _ = writeln!(
+            plist,
+            "\t\t\t<key>LibraryPath</key>\n\t\t\t<string>{file_name}</string>"
+        );
+        plist.push_str("\t\t\t<key>SupportedArchitectures</key>\n\t\t\t<array>\n");
+        for arch in &library.archs {
+            let _ = writeln!(plist, "\t\t\t\t<string>{arch}</string>");
+        }
+        plist.push_str("\t\t\t</array>\n");
+        let _ = writeln!(
+            plist,
+            "\t\t\t<key>SupportedPlatform</key>\n\t\t\t<string>{}</string>",
+            library.platform
+        );
+        if let Some(variant) = library.variant {
+            let _ = writeln!(
+                plist,
+                "\t\t\t<key>SupportedPlatformVariant</key>\n\t\t\t<string>{variant}</string>"
+            );
+        }
+        plist.push_str("\t\t</dict>\n");
+    }
+    plist.push_str(
+        "\t</array>
+\t<key>CFBundlePackageType</key>
+\t<string>XFWK</string>
+\t<key>XCFrameworkFormatVersion</key>
+\t<string>1.0</string>
+</dict>
+</plist>
+",
+    );
+    plist
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(feature = "universal2")]
+    use crate::test_utils::fixtures_dir;
+
+    #[test]
+    fn test_apple_platform() {
+        assert_eq!(
+            apple_platform("aarch64-apple-darwin").unwrap(),
+            ("macos", None)
+        );
+        assert_eq!(apple_platform("aarch64-apple-ios").unwrap(), ("ios", None));
+        assert_eq!(
+            apple_platform("aarch64-apple-ios-sim").unwrap(),
+            ("ios", Some("simulator"))
+        );
+        assert_eq!(
+            apple_platform("x86_64-apple-ios").unwrap(),
+            ("ios", Some("simulator"))
+        );
+        assert_eq!(
+            apple_platform("x86_64-apple-ios-macabi").unwrap(),
+            ("ios", Some("maccatalyst"))
+        );
+        assert_eq!(
+            apple_platform("aarch64-apple-visionos-sim").unwrap(),
+            ("xros", Some("simulator"))
+        );
+        assert!(apple_platform("x86_64-unknown-linux-gnu").is_err());
+        assert!(apple_platform("aarch64-apple-ios-foo").is_err());
+    }
+
+    #[test]
+    fn test_info_plist() {
+        let libraries = [
+            Library {
+                platform: "ios",
+                variant: None,
+                archs: vec!["arm64"],
+                file_name: "libfoo.a".to_string(),
+                has_headers: true,
+            },
+            Library {
+                platform: "ios",
+                variant: Some("simulator"),
+                archs: vec!["arm64", "x86_64"],
+                file_name: "libfoo.a".to_string(),
+                has_headers: false,
+            },
+        ];
+        assert_eq!(libraries[1].identifier(), "ios-arm64_x86_64-simulator");
+        assert_eq!(
+            info_plist(&libraries),
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+	<key>AvailableLibraries</key>
+	<array>
+		<dict>
+			<key>BinaryPath</key>
+			<string>libfoo.a</string>
+			<key>HeadersPath</key>
+			<string>Headers</string>
+			<key>LibraryIdentifier</key>
+			<string>ios-arm64</string>
+			<key>LibraryPath</key>
+			<string>libfoo.a</string>
+			<key>SupportedArchitectures</key>
+			<array>
+				<string>arm64</string>
+			</array>
+			<key>SupportedPlatform</key>
+			<string>ios</string>
+		</dict>
+		<dict>
+			<key>BinaryPath</key>
+			<string>libfoo.a</string>
+			<key>LibraryIdentifier</key>
+			<string>ios-arm64_x86_64-simulator</string>
+			<key>LibraryPath</key>
+			<string>libfoo.a</string>
+			<key>SupportedArchitectures</key>
+			<array>
+				<string>arm64</string>
+				<string>x86_64</string>
+			</array>
+			<key>SupportedPlatform</key>
+			<string>ios</string>
+			<key>SupportedPlatformVariant</key>
+			<string>simulator</string>
+		</dict>
+	</array>
+	<key>CFBundlePackageType</key>
+	<string>XFWK</string>
+	<key>XCFrameworkFormatVersion</key>
+	<string>1.0</string>
+</dict>
+</plist>
+"#
+        );
+    }
+
+    #[cfg(feature = "universal2")]
+    #[test]
+    fn test_write_xcframework() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = Utf8Path::from_path(tmp.path()).unwrap();
+        let headers = root.join("include");
+        fs::create_dir_all(headers.join("foo")).unwrap();
+        fs::write(headers.join("foo").join("foo.h"), "int add(int, int);").unwrap();
+        let fixtures = Utf8PathBuf::try_from(fixtures_dir()).unwrap();
+        let x86_64 = fixtures.join("test_x86_64.dylib");
+        let aarch64 = fixtures.join("test_aarch64.dylib");
+        let mut slices = Slices::new();
+        slices.insert(
+            ("macos", None),
+            vec![("x86_64", &x86_64), ("arm64", &aarch64)],
+        );
+        slices.insert(("ios", Some("maccatalyst")), vec![("arm64", &aarch64)]);
+
+        let xcframework = Xcframework {
+            headers: Some(headers.into()),
+            ..Default::default()
+        };
+        let output = root.join("foo.xcframework");
+        fs::create_dir_all(output.join("stale")).unwrap();
+        xcframework
+            .write_xcframework(&output, "libfoo.dylib", &slices)
+            .unwrap();
+        assert!(!output.join("stale").exists());
+        let universal = fs::read(output.join("macos-arm64_x86_64/libfoo.dylib")).unwrap();
+        assert!(matches!(
+            goblin::mach::Mach::parse(&universal).unwrap(),
+            goblin::mach::Mach::Fat(multi) if multi.narches == 2
+        ));
+        assert_eq!(
+            fs::read(output.join("ios-arm64-maccatalyst/libfoo.dylib")).unwrap(),
+            fs::read(&aarch64).unwrap()
+        );
+        assert!(
+            output
+                .join("ios-arm64-maccatalyst/Headers/foo/foo.h")
+                .is_file()
+        );
+        let plist = fs::read_to_string(output.join("Info.plist")).unwrap();
+        assert!(plist.contains("<string>macos-arm64_x86_64</string>"));
+        assert!(plist.contains("<string>maccatalyst</string>"));
+    }
+}
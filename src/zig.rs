@@ -1,5 +1,6 @@
+use std::collections::BTreeMap;
 use std::env;
-use std::ffi::OsStr;
+use std::ffi::{OsStr, OsString};
 #[cfg(target_family = "unix")]
 use std::fs::OpenOptions;
 use std::io::Write;
@@ -14,10 +15,21 @@ use anyhow::{Context, Result, anyhow, bail};
 use fs_err as fs;
 use path_slash::PathBufExt;
 use serde::Deserialize;
-use target_lexicon::{Architecture, Environment, OperatingSystem, Triple};
-
-use crate::linux::ARM_FEATURES_H;
-use crate::macos::{LIBCHARSET_TBD, LIBICONV_TBD};
+use target_lexicon::{Architecture, Environment, OperatingSystem, Triple, Vendor};
+
+use crate::config::{RESOLVED_CONFIG_ENV, ZigbuildConfig};
+use crate::linker_rules::{FilteredArg, LinkerArgRules};
+use crate::linux::{ARM_FEATURES_H, dynamic_list};
+use crate::macos::sdk::{self, MacosSdk, StubSdk};
+use crate::macos::{LIBCHARSET_TBD, LIBICONV_TBD, build_version, codesign, exports};
+use crate::target_version::{
+    DEFAULT_GLIBC_VERSION, TargetVersion, check_glibc_version, check_macos_version,
+    deployment_target, deployment_target_env, split_target_version, zig_glibc_versions,
+};
+use crate::windows::import_libs::{self, DefRegistry};
+use crate::windows::pe_security::{self, RequestedCharacteristics};
+use crate::windows::windres;
+use crate::windows::xwin::XwinSdk;
 
 /// Zig linker wrapper
 #[derive(Clone, Debug, clap::Subcommand)]
@@ -64,14 +76,28 @@ pub enum Zig {
         #[arg(num_args = 1.., trailing_var_arg = true)]
         args: Vec<String>,
     },
+    /// GNU `windres` compatible wrapper of `zig rc`
+    #[command(name = "windres")]
+    Windres {
+        /// `windres` arguments
+        #[arg(num_args = 1.., trailing_var_arg = true)]
+        args: Vec<String>,
+    },
+    /// `zig rc` wrapper
+    #[command(name = "rc")]
+    Rc {
+        /// `zig rc` arguments
+        #[arg(num_args = 1.., trailing_var_arg = true)]
+        args: Vec<String>,
+    },
 }
 
-struct TargetInfo {
+pub(crate) struct TargetInfo {
     target: Option<String>,
 }
 
 impl TargetInfo {
-    fn new(target: Option<&String>) -> Self {
+    pub(crate) fn new(target: Option<&String>) -> Self {
         Self {
             target: target.cloned(),
         }
@@ -200,8 +226,86 @@ impl TargetInfo {
             .unwrap_or_default()
     }
 
+    fn is_maccatalyst(&self) -> bool {
+        self.target
+            .as_ref()
+            .map(|x| x.contains("maccatalyst") || x.contains("macabi"))
+            .unwrap_or_default()
+    }
+
+    /// iOS, tvOS, watchOS and visionOS simulators, like `aarch64-apple-ios-sim`
+    /// or `aarch64-ios-simulator`, x86 iOS targets other than Mac Catalyst are always simulators
+    fn is_apple_simulator(&self) -> bool {
+        if self.is_maccatalyst() {
+            return false;
+        }
+        self.target
+            .as_ref()
+            .map(|x| {
+                x.contains("-sim")
+                    || ((x.starts_with("x86") || x.starts_with("i386"))
+                        && (x.contains("ios") || x.contains("tvos") || x.contains("watchos")))
+            })
+            .unwrap_or_default()
+    }
+
+    /// The `xcrun --sdk` name of the SDK for an Apple target, like `iphonesimulator`
+    pub(crate) fn xcrun_sdk(&self) -> Option<&'static str> {
+        let simulator = self.is_apple_simulator();
+        let sdk = if self.is_macos() || self.is_darwin() || self.is_maccatalyst() {
+            "macosx"
+        } else if self.is_visionos() {
+            if simulator { "xrsimulator" } else { "xros" }
+        } else if self.is_watchos() {
+            if simulator {
+                "watchsimulator"
+            } else {
+                "watchos"
+            }
+        } else if self.is_tvos() {
+            if simulator {
+                "appletvsimulator"
+            } else {
+                "appletvos"
+            }
+        } else if self.is_ios() {
+            if simulator {
+                "iphonesimulator"
+            } else {
+                "iphoneos"
+            }
+        } else {
+            return None;
+        };
+        Some(sdk)
+    }
+
+    /// `TARGET_OS_*` definitions matching `TargetConditionals.h` for Apple targets,
+    /// for code that checks them without including it
+    pub(crate) fn target_os_defines(&self) -> Vec<String> {
+        if !self.is_apple_platform() {
+            return Vec::new();
+        }
+        let catalyst = self.is_maccatalyst();
+        let osx = (self.is_macos() || self.is_darwin()) && !catalyst;
+        let ios = self.is_ios() || catalyst;
+        [
+            ("OSX", osx),
+            ("IPHONE", !osx),
+            ("IOS", ios),
+            ("MACCATALYST", catalyst),
+            ("TV", self.is_tvos()),
+            ("WATCH", self.is_watchos()),
+            ("VISION", self.is_visionos()),
+            ("SIMULATOR", self.is_apple_simulator()),
+        ]
+        .into_iter()
+        .map(|(name, value)| format!("-DTARGET_OS_{name}={}", u8::from(value)))
+        .collect()
+    }
+
     /// Returns the appropriate Apple CPU for the platform
-    fn apple_cpu(&self) -> &'static str {
+    pub(crate) fn apple_cpu(&self) -> &'static str {
         if self.is_macos() || self.is_darwin() {
             "apple_m1" // M-series for macOS
         } else if self.is_visionos() {
@@ -242,6 +346,35 @@ impl TargetInfo {
             .map(|x| x.contains("ohos"))
             .unwrap_or_default()
     }
+
+    /// Evaluate a named target predicate used by linker argument rules,
+    /// returns `None` for unknown predicates
+    pub(crate) fn predicate(&self, name: &str) -> Option<bool> {
+        let value = match name {
+            "arm" => self.is_arm(),
+            "aarch64" => self.is_aarch64(),
+            "aarch64_be" => self.is_aarch64_be(),
+            "i386" => self.is_i386(),
+            "i686" => self.is_i686(),
+            "riscv64" => self.is_riscv64(),
+            "riscv32" => self.is_riscv32(),
+            "mips32" => self.is_mips32(),
+            "musl" => self.is_musl(),
+            "macos" => self.is_macos(),
+            "darwin" => self.is_darwin(),
+            "apple_platform" => self.is_apple_platform(),
+            "ios" => self.is_ios(),
+            "tvos" => self.is_tvos(),
+            "watchos" => self.is_watchos(),
+            "visionos" => self.is_visionos(),
+            "freebsd" => self.is_freebsd(),
+            "windows_gnu" => self.is_windows_gnu(),
+            "windows_msvc" => self.is_windows_msvc(),
+            "ohos" => self.is_ohos(),
+            _ => return None,
+        };
+        Some(value)
+    }
 }
 
 impl Zig {
@@ -254,9 +387,23 @@ impl Zig {
             Zig::Ranlib { args } => self.execute_compiler("ranlib", args),
             Zig::Lib { args } => self.execute_compiler("lib", args),
             Zig::Dlltool { args } => self.execute_dlltool(args),
+            Zig::Windres { args } => self.execute_windres(args),
+            Zig::Rc { args } => self.execute_tool("rc", args),
         }
     }
 
+    /// Execute zig rc with translated `windres` arguments
+    pub fn execute_windres(&self, cmd_args: &[String]) -> Result<()> {
+        let rc_args = windres::rc_args(cmd_args)?;
+        let zig_version = Zig::zig_version()?;
+        if windres::outputs_coff(&rc_args) && (zig_version.major, zig_version.minor) < (0, 14) {
+            bail!(
+                "zig {zig_version} can't compile resources to COFF objects, zig 0.14 or newer is required"
+            );
+        }
+        self.execute_tool("rc", &rc_args)
+    }
+
     /// Execute zig dlltool command
     /// Filter out unsupported options for older zig versions (< 0.12)
     pub fn execute_dlltool(&self, cmd_args: &[String]) -> Result<()> {
@@ -307,6 +454,30 @@ impl Zig {
             Err(_) => rustc_version::version()?,
         };
         let zig_version = Zig::zig_version()?;
+        let config = ZigbuildConfig::load()?;
+        let rules = LinkerArgRules::new(&config)?;
+        // Read before the response file is rewritten without it
+        let export_list =
+            if (zig_version.major, zig_version.minor) < (0, 16) && is_link_command(cmd_args) {
+                export_list(cmd_args)
+            } else {
+                None
+            };
+        // zig rejects the PE characteristics options rustc passes, they are applied after linking
+        let pe_characteristics = if target_info.is_windows_gnu() && is_link_command(cmd_args) {
+            let args: Vec<_> = expand_linker_response_file(cmd_args).collect();
+            RequestedCharacteristics::from_args(args.iter().map(String::as_str))
+        } else {
+            RequestedCharacteristics::default()
+        };
+        // Libraries like `-lwindows` are dropped unless the user provides them
+        let registry = DefRegistry::from_config(&config);
+        let provided_libs = if target_info.is_windows_gnu() && is_link_command(cmd_args) {
+            let args: Vec<_> = expand_linker_response_file(cmd_args).collect();
+            import_libs::provided_dropped_libs(&args, &registry)?
+        } else {
+            Vec::new()
+        };
 
         let mut new_cmd_args = Vec::with_capacity(cmd_args.len());
         let mut skip_next_arg = false;
@@ -329,12 +500,13 @@ impl Zig {
             let args = if arg.starts_with('@') && arg.ends_with("linker-arguments") {
                 vec![self.process_linker_response_file(
                     arg,
+                    &rules,
                     &rustc_ver,
                     &zig_version,
                     &target_info,
                 )?]
             } else {
-                match self.filter_linker_arg(arg, &rustc_ver, &zig_version, &target_info) {
+                match rules.apply(arg, &rustc_ver, &zig_version, &target_info) {
                     FilteredArg::Keep(filtered) => filtered,
                     FilteredArg::Skip => continue,
                     FilteredArg::SkipWithNext => {
@@ -346,6 +518,41 @@ impl Zig {
             new_cmd_args.extend(args);
         }
 
+        // zig < 0.16 drops export lists, see `linker-arg-rules.toml`
+        let mut exported_symbols = None;
+        match export_list {
+            Some(ExportList::DynamicList(list)) => {
+                let content = fs::read_to_string(&list)?;
+                let version_script = dynamic_list::version_script(&content)
+                    .with_context(|| format!("Failed to parse `{}`", list.display()))?;
+                let version_script_path = list.with_extension("version-script");
+                fs::write(&version_script_path, version_script)?;
+                new_cmd_args.push("-Wl,--export-dynamic".to_string());
+                new_cmd_args.push(format!(
+                    "-Wl,--version-script={}",
+                    version_script_path.display()
+                ));
+            }
+            Some(ExportList::ExportedSymbolsList(list)) => {
+                // Applied to the export trie after linking
+                let content = fs::read_to_string(&list)?;
+                exported_symbols = Some(exports::parse_exported_symbols_list(&content));
+            }
+            None => {}
+        }
+
+        new_cmd_args.extend(provided_libs);
+        // Import libraries zig's mingw doesn't ship, like `-lsynchronization` for older zig
+        if target_info.is_windows_gnu() && is_link_command(cmd_args) {
+            let arch = target.and_then(|target| target.split('-').next());
+            let link_args: Vec<_> = expand_linker_response_file(&new_cmd_args).collect();
+            for dir in
+                import_libs::synthesize_missing(&link_args, arch.unwrap_or_default(), &registry)?
+            {
+                new_cmd_args.push(format!("-L{}", dir.display()));
+            }
+        }
+
         if target_info.is_mips32() {
             // See https://github.com/ziglang/zig/issues/4925#issuecomment-1499823425
             new_cmd_args.push("-Wl,-z,notext".to_string());
@@ -358,22 +565,48 @@ impl Zig {
         if self.has_undefined_dynamic_lookup(cmd_args) {
             new_cmd_args.push("-Wl,-undefined=dynamic_lookup".to_string());
         }
-        if target_info.is_macos() {
-            if self.should_add_libcharset(cmd_args, &zig_version) {
-                new_cmd_args.push("-lcharset".to_string());
-            }
-            self.add_macos_specific_args(&mut new_cmd_args, &zig_version)?;
+        if target_info.is_apple_platform()
+            && config.headerpad_max_install_names == Some(true)
+            && is_link_command(cmd_args)
+        {
+            // Reserve room for rewriting install names and rpaths with install_name_tool later
+            new_cmd_args.push("-Wl,-headerpad_max_install_names".to_string());
+        }
+        if target_info.is_macos() && self.should_add_libcharset(cmd_args, &zig_version) {
+            new_cmd_args.push("-lcharset".to_string());
+        }
+        if target_info.is_apple_platform() {
+            self.add_apple_specific_args(&mut new_cmd_args, &zig_version, &config, &target_info)?;
         }
 
-        // For Zig >= 0.15 with macOS, set SDKROOT environment variable
+        // For Zig >= 0.15 with Apple SDKs, set SDKROOT environment variable
         // if it exists, instead of passing --sysroot
         let mut command = Self::command()?;
         if (zig_version.major, zig_version.minor) >= (0, 15)
-            && let Some(sdkroot) = Self::macos_sdk_root()
+            && let Some(sdkroot) = Self::target_sdk_root(&target_info, &config)
         {
             command.env("SDKROOT", sdkroot);
         }
+        // zig can't find the MSVC libraries by itself outside of Windows
+        if target_info.is_windows_msvc()
+            && env::var_os("ZIG_LIBC").is_none()
+            && let Some(xwin_sdk) = XwinSdk::from_config(&config)?
+        {
+            let arch = target.and_then(|target| target.split('-').next());
+            let libc_file = xwin_sdk.libc_file(arch.unwrap_or_default())?;
+            let hash = crc::Crc::<u16>::new(&crc::CRC_16_IBM_SDLC).checksum(libc_file.as_bytes());
+            let libc_file_path = cache_dir().join(format!("xwin-libc-{hash:x}.txt"));
+            write_file(&libc_file_path, &libc_file)?;
+            command.env("ZIG_LIBC", libc_file_path);
+        }
 
+        let link_output = if (target_info.is_apple_platform() || !pe_characteristics.is_empty())
+            && is_link_command(cmd_args)
+        {
+            link_output(&new_cmd_args)
+        } else {
+            None
+        };
         let mut child = command
             .arg(cmd)
             .args(new_cmd_args)
@@ -383,12 +616,29 @@ impl Zig {
         if !status.success() {
             process::exit(status.code().unwrap_or(1));
         }
+        if let Some(output) = link_output {
+            if !pe_characteristics.is_empty() {
+                pe_security::apply_requested_characteristics(&output, pe_characteristics)?;
+            }
+            if target_info.is_apple_platform() {
+                if let Some(symbols) = &exported_symbols {
+                    exports::restrict_exported_symbols(&output, symbols)?;
+                }
+                // arm64 Apple binaries must be signed, at least ad-hoc, or the kernel kills them
+                codesign::sign_linked_output(&output)?;
+                // Catch linker arguments overriding the OS version of our `-target`
+                if let Some(version) = target.and_then(|target| zig_target_os_version(target)) {
+                    build_version::check_min_os_version(&output, version)?;
+                }
+            }
+        }
         Ok(())
     }
 
     fn process_linker_response_file(
         &self,
         arg: &str,
+        rules: &LinkerArgRules,
         rustc_ver: &rustc_version::Version,
         zig_version: &semver::Version,
         target_info: &TargetInfo,
@@ -422,12 +672,13 @@ impl Zig {
                 )
             })?
         };
-        let mut link_args: Vec<_> = filter_linker_args(
-            content.split('\n').map(|s| s.to_string()),
-            rustc_ver,
-            zig_version,
-            target_info,
-        );
+        let args: Vec<_> = if target_info.is_windows_msvc() {
+            content.lines().map(unquote_msvc_arg).collect()
+        } else {
+            content.split('\n').map(|s| s.to_string()).collect()
+        };
+        let mut link_args: Vec<_> =
+            filter_linker_args(args, rules, rustc_ver, zig_version, target_info);
         if self.has_undefined_dynamic_lookup(&link_args) {
             link_args.push("-Wl,-undefined=dynamic_lookup".to_string());
         }
@@ -435,40 +686,108 @@ impl Zig {
             link_args.push("-lcharset".to_string());
         }
         if target_info.is_windows_msvc() {
-            let new_content = link_args.join("\n");
-            let mut out = Vec::with_capacity((1 + new_content.len()) * 2);
-            // start the stream with a UTF-16 BOM
-            for c in std::iter::once(0xFEFF).chain(new_content.encode_utf16()) {
-                // encode in little endian
-                out.push(c as u8);
-                out.push((c >> 8) as u8);
-            }
-            fs::write(arg.trim_start_matches('@'), out)?;
+            // zig reads response files as UTF-8, keep rustc's quoting for paths with spaces
+            let new_content = link_args
+                .iter()
+                .map(|arg| format!("\"{}\"", arg.replace('"', "\\\"")))
+                .collect::<Vec<_>>()
+                .join("\n");
+            fs::write(arg.trim_start_matches('@'), new_content.as_bytes())?;
         } else {
             fs::write(arg.trim_start_matches('@'), link_args.join("\n").as_bytes())?;
         }
         Ok(arg.to_string())
     }
+}
 
-    fn filter_linker_arg(
-        &self,
-        arg: &str,
-        rustc_ver: &rustc_version::Version,
-        zig_version: &semver::Version,
-        target_info: &TargetInfo,
-    ) -> FilteredArg {
-        filter_linker_arg(arg, rustc_ver, zig_version, target_info)
+/// Unquote an argument of a response file rustc wrote for the MSVC linker, quoted like
+/// `"arg"` with `\"` escapes
+fn unquote_msvc_arg(arg: &str) -> String {
+    let arg = arg.trim_end_matches('\r');
+    arg.strip_prefix('"')
+        .and_then(|arg| arg.strip_suffix('"'))
+        .map(|arg| arg.replace("\\\"", "\""))
+        .unwrap_or_else(|| arg.to_string())
+}
+
+/// The OS version of a zig target, like `11.0` in `aarch64-macos.11.0-none`
+fn zig_target_os_version(target: &str) -> Option<TargetVersion> {
+    let (_, version) = target.split('-').nth(1)?.split_once('.')?;
+    version.parse().ok()
+}
+
+/// Whether a `zig cc` invocation links, rather than only preprocessing or compiling
+fn is_link_command(args: &[String]) -> bool {
+    !args
+        .iter()
+        .any(|arg| matches!(arg.as_str(), "-c" | "-E" | "-S" | "-M" | "-MM"))
+}
+
+/// The arguments of a link command with rustc's linker response file expanded
+fn expand_linker_response_file(args: &[String]) -> impl Iterator<Item = String> {
+    args.iter().cloned().flat_map(|arg| {
+        if arg.starts_with('@') && arg.ends_with("linker-arguments") {
+            fs::read_to_string(arg.trim_start_matches('@'))
+                .map(|content| content.split('\n').map(str::to_string).collect())
+                .unwrap_or_default()
+        } else {
+            vec![arg]
+        }
+    })
+}
+
+/// The `-o` output of a link command, looking into rustc's linker response file
+fn link_output(args: &[String]) -> Option<PathBuf> {
+    let mut args = expand_linker_response_file(args);
+    let mut output = None;
+    while let Some(arg) = args.next() {
+        if arg == "-o" {
+            output = args.next().map(PathBuf::from);
+        }
     }
+    output
+}
+
+/// A file listing the symbols to export, passed to the linker by rustc
+#[derive(Debug, PartialEq, Eq)]
+enum ExportList {
+    /// ld64 `-exported_symbols_list`
+    ExportedSymbolsList(PathBuf),
+    /// GNU ld `--dynamic-list`
+    DynamicList(PathBuf),
 }
 
-enum FilteredArg {
-    Keep(Vec<String>),
-    Skip,
-    SkipWithNext,
+/// The export list of a link command, looking into rustc's linker response file
+///
+/// Both the `-Wl,<flag>,<path>` and the `-Wl,<flag> -Wl,<path>` forms are recognized.
+fn export_list(args: &[String]) -> Option<ExportList> {
+    let mut args = expand_linker_response_file(args);
+    let mut list = None;
+    while let Some(arg) = args.next() {
+        let (flag, path) = match arg.split_once(',') {
+            Some(("-Wl", rest)) => match rest.split_once(',') {
+                Some((flag, path)) => (flag.to_string(), Some(path.to_string())),
+                None => (rest.to_string(), None),
+            },
+            _ => continue,
+        };
+        let kind = match flag.as_str() {
+            "-exported_symbols_list" => ExportList::ExportedSymbolsList,
+            "--dynamic-list" => ExportList::DynamicList,
+            _ => continue,
+        };
+        let path = path.or_else(|| {
+            args.next()
+                .map(|next| next.strip_prefix("-Wl,").unwrap_or(&next).to_string())
+        });
+        list = path.map(|path| kind(PathBuf::from(path)));
+    }
+    list
 }
 
 fn filter_linker_args(
     args: impl IntoIterator<Item = String>,
+    rules: &LinkerArgRules,
     rustc_ver: &rustc_version::Version,
     zig_version: &semver::Version,
     target_info: &TargetInfo,
@@ -480,7 +799,7 @@ fn filter_linker_args(
             skip_next = false;
             continue;
         }
-        match filter_linker_arg(&arg, rustc_ver, zig_version, target_info) {
+        match rules.apply(&arg, rustc_ver, zig_version, target_info) {
             FilteredArg::Keep(filtered) => result.extend(filtered),
             FilteredArg::Skip => {}
             FilteredArg::SkipWithNext => {
@@ -491,145 +810,6 @@ fn filter_linker_args(
     result
 }
 
-fn filter_linker_arg(
-    arg: &str,
-    rustc_ver: &rustc_version::Version,
-    zig_version: &semver::Version,
-    target_info: &TargetInfo,
-) -> FilteredArg {
-    if arg == "-lgcc_s" {
-        return FilteredArg::Keep(vec!["-lunwind".to_string()]);
-    } else if arg.starts_with("--target=") {
-        return FilteredArg::Skip;
-    } else if arg.starts_with("-e") && arg.len() > 2 && !arg.starts_with("-export") {
-        let entry = &arg[2..];
-        return FilteredArg::Keep(vec![format!("-Wl,--entry={}", entry)]);
-    }
-    if (target_info.is_arm() || target_info.is_windows_gnu())
-        && arg.ends_with(".rlib")
-        && arg.contains("libcompiler_builtins-")
-    {
-        return FilteredArg::Skip;
-    }
-    if target_info.is_windows_gnu() {
-        #[allow(clippy::if_same_then_else)]
-        if arg == "-lgcc_eh"
-            && ((zig_version.major, zig_version.minor) < (0, 14) || target_info.is_i686())
-        {
-            return FilteredArg::Keep(vec!["-lc++".to_string()]);
-        } else if arg.ends_with("rsbegin.o") || arg.ends_with("rsend.o") {
-            if target_info.is_i686() {
-                return FilteredArg::Skip;
-            }
-        } else if arg == "-Wl,-Bdynamic" && (zig_version.major, zig_version.minor) >= (0, 11) {
-            return FilteredArg::Keep(vec!["-Wl,-search_paths_first".to_owned()]);
-        } else if arg == "-lwindows" || arg == "-l:libpthread.a" || arg == "-lgcc" {
-            return FilteredArg::Skip;
-        } else if arg == "-Wl,--disable-auto-image-base"
-            || arg == "-Wl,--dynamicbase"
-            || arg == "-Wl,--large-address-aware"
-            || (arg.starts_with("-Wl,")
-                && (arg.ends_with("/list.def") || arg.ends_with("\\list.def")))
-        {
-            return FilteredArg::Skip;
-        } else if arg == "-lmsvcrt" {
-            return FilteredArg::Skip;
-        }
-    } else if arg == "-Wl,--no-undefined-version"
-        || arg == "-Wl,-znostart-stop-gc"
-        // See https://github.com/rust-lang/rust/pull/155453
-        || arg == "-Wl,--fix-cortex-a53-843419"
-        || arg.starts_with("-Wl,-plugin-opt")
-    {
-        return FilteredArg::Skip;
-    }
-    if target_info.is_musl() || target_info.is_ohos() {
-        if (arg.ends_with(".o") && arg.contains("self-contained") && arg.contains("crt"))
-            || arg == "-Wl,-melf_i386"
-        {
-            return FilteredArg::Skip;
-        }
-        if rustc_ver.major == 1
-            && rustc_ver.minor < 59
-            && arg.ends_with(".rlib")
-            && arg.contains("liblibc-")
-        {
-            return FilteredArg::Skip;
-        }
-        if arg == "-lc" {
-            return FilteredArg::Skip;
-        }
-    }
-    // zig cc only supports -Wp,-MD, -Wp,-MMD, and -Wp,-MT;
-    // strip all other -Wp, args (e.g. -Wp,-U_FORTIFY_SOURCE from CMake)
-    // https://github.com/ziglang/zig/blob/0.15.2/src/main.zig#L2798
-    if arg.starts_with("-Wp,")
-        && !arg.starts_with("-Wp,-MD")
-        && !arg.starts_with("-Wp,-MMD")
-        && !arg.starts_with("-Wp,-MT")
-    {
-        return FilteredArg::Skip;
-    }
-    if arg.starts_with("-march=") {
-        if target_info.is_arm() || target_info.is_i386() {
-            return FilteredArg::Skip;
-        } else if target_info.is_riscv64() {
-            return FilteredArg::Keep(vec!["-march=generic_rv64".to_string()]);
-        } else if target_info.is_riscv32() {
-            return FilteredArg::Keep(vec!["-march=generic_rv32".to_string()]);
-        } else if arg.starts_with("-march=armv")
-            && (target_info.is_aarch64() || target_info.is_aarch64_be())
-        {
-            let march_value = arg.strip_prefix("-march=").unwrap();
-            let features = if let Some(pos) = march_value.find('+') {
-                &march_value[pos..]
-            } else {
-                ""
-            };
-            let base_cpu = if target_info.is_apple_platform() {
-                target_info.apple_cpu()
-            } else {
-                "generic"
-            };
-            let mut result = vec![format!("-mcpu={}{}", base_cpu, features)];
-            if features.contains("+crypto") {
-                result.append(&mut vec!["-Xassembler".to_owned(), arg.to_string()]);
-            }
-            return FilteredArg::Keep(result);
-        }
-    }
-    if target_info.is_apple_platform() {
-        if (zig_version.major, zig_version.minor) < (0, 16) {
-            if arg.starts_with("-Wl,-exported_symbols_list,") {
-                return FilteredArg::Skip;
-            }
-            if arg == "-Wl,-exported_symbols_list" {
-                return FilteredArg::SkipWithNext;
-            }
-        }
-        if arg == "-Wl,-dylib" {
-            return FilteredArg::Skip;
-        }
-    }
-    // Handle two-arg form on all platforms (cross-compilation from non-Apple hosts)
-    if (zig_version.major, zig_version.minor) < (0, 16) {
-        if arg == "-Wl,-exported_symbols_list" || arg == "-Wl,--dynamic-list" {
-            return FilteredArg::SkipWithNext;
-        }
-        if arg.starts_with("-Wl,-exported_symbols_list,") || arg.starts_with("-Wl,--dynamic-list,")
-        {
-            return FilteredArg::Skip;
-        }
-    }
-    if target_info.is_freebsd() {
-        let ignored_libs = ["-lkvm", "-lmemstat", "-lprocstat", "-ldevstat"];
-        if ignored_libs.contains(&arg) {
-            return FilteredArg::Skip;
-        }
-    }
-    FilteredArg::Keep(vec![arg.to_string()])
-}
-
 impl Zig {
     fn has_undefined_dynamic_lookup(&self, args: &[String]) -> bool {
         let undefined = args
@@ -648,12 +828,14 @@ impl Zig {
         }
     }
 
-    fn add_macos_specific_args(
+    fn add_apple_specific_args(
         &self,
         new_cmd_args: &mut Vec<String>,
         zig_version: &semver::Version,
+        config: &ZigbuildConfig,
+        target_info: &TargetInfo,
     ) -> Result<()> {
-        let sdkroot = Self::macos_sdk_root();
+        let sdkroot = Self::target_sdk_root(target_info, config);
         if (zig_version.major, zig_version.minor) >= (0, 12) {
             // Zig 0.12.0+ requires passing `--sysroot`
             // However, for Zig 0.15+, we should use SDKROOT environment variable instead
@@ -680,7 +862,6 @@ impl Zig {
                             .join("Frameworks")
                             .display()
                     ),
-                    "-DTARGET_OS_IPHONE=0".to_string(),
                 ]);
             } else {
                 // For zig >= 0.15 with SDKROOT, we still need to add framework paths
@@ -707,9 +888,24 @@ impl Zig {
                             .join("Frameworks")
                             .display()
                     ),
-                    "-DTARGET_OS_IPHONE=0".to_string(),
                 ]);
             }
+            new_cmd_args.extend(target_info.target_os_defines());
+        }
+        if !target_info.is_macos() {
+            return Ok(());
+        }
+
+        // User supplied stubs come after the SDK and before our own `.tbd` files
+        if let Some(stubs) = &config.apple_sdk_stubs {
+            let stub_sdk = StubSdk::new(stubs)?;
+            let include_dir = stub_sdk.include_dir();
+            if include_dir.is_dir() {
+                new_cmd_args.push("-isystem".to_string());
+                new_cmd_args.push(format!("{}", include_dir.display()));
+            }
+            new_cmd_args.push(format!("-L{}", stub_sdk.lib_dir().display()));
+            new_cmd_args.push(format!("-F{}", stub_sdk.frameworks_dir().display()));
         }
 
         // Add the deps directory that contains `.tbd` files to the library search path
@@ -744,7 +940,7 @@ impl Zig {
         Ok(cmd)
     }
 
-    fn zig_version() -> Result<semver::Version> {
+    pub(crate) fn zig_version() -> Result<semver::Version> {
         static ZIG_VERSION: OnceLock<semver::Version> = OnceLock::new();
 
         if let Some(version) = ZIG_VERSION.get() {
@@ -885,8 +1081,69 @@ impl Zig {
         cmd: &mut Command,
         enable_zig_ar: bool,
     ) -> Result<()> {
+        let zigbuild_config = ZigbuildConfig::load()?;
+        Self::apply_command_env_with_config(
+            manifest_path,
+            release,
+            cargo,
+            cmd,
+            enable_zig_ar,
+            &zigbuild_config,
+        )
+    }
+
+    /// Like `apply_command_env`, with the `[zigbuild]` configuration already loaded
+    pub(crate) fn apply_command_env_with_config(
+        manifest_path: Option<&Path>,
+        release: bool,
+        cargo: &cargo_options::CommonOptions,
+        cmd: &mut Command,
+        enable_zig_ar: bool,
+        zigbuild_config: &ZigbuildConfig,
+    ) -> Result<()> {
+        let command_env = Self::command_env_with_config(
+            manifest_path,
+            release,
+            cargo,
+            enable_zig_ar,
+            zigbuild_config,
+        )?;
+        command_env.apply(cmd);
+        Ok(())
+    }
+
+    /// Compute the environment variables needed to run cargo with zig as the linker
+    /// for the targets in `cargo`, without touching the process environment.
+    pub fn command_env(
+        manifest_path: Option<&Path>,
+        release: bool,
+        cargo: &cargo_options::CommonOptions,
+        enable_zig_ar: bool,
+    ) -> Result<CommandEnv> {
+        let zigbuild_config = ZigbuildConfig::load()?;
+        Self::command_env_with_config(
+            manifest_path,
+            release,
+            cargo,
+            enable_zig_ar,
+            &zigbuild_config,
+        )
+    }
+
+    /// Like `command_env`, with the `[zigbuild]` configuration already loaded
+    fn command_env_with_config(
+        manifest_path: Option<&Path>,
+        release: bool,
+        cargo: &cargo_options::CommonOptions,
+        enable_zig_ar: bool,
+        zigbuild_config: &ZigbuildConfig,
+    ) -> Result<CommandEnv> {
+        let mut cmd = CommandEnv::default();
         // setup zig as linker
         let cargo_config = cargo_config2::Config::load()?;
+        // Validate linker argument rules early instead of failing at link time
+        LinkerArgRules::new(zigbuild_config)?;
+        cmd.set_if_missing(RESOLVED_CONFIG_ENV, serde_json::to_string(zigbuild_config)?);
         // Use targets from CLI args, or fall back to cargo config's build.target
         let config_targets;
         let raw_targets: &[String] = if cargo.target.is_empty() {
@@ -907,12 +1164,54 @@ impl Zig {
             .map(|target| target.split_once('.').map(|(t, _)| t).unwrap_or(target))
             .collect::<Vec<&str>>();
         let rustc_meta = rustc_version::version_meta()?;
-        Self::add_env_if_missing(
-            cmd,
+        cmd.set_if_missing(
             "CARGO_ZIGBUILD_RUSTC_VERSION",
             rustc_meta.semver.to_string(),
         );
         let host_target = &rustc_meta.host;
+        let macos_sdk = if rust_targets
+            .iter()
+            .any(|target| target.contains("apple-darwin"))
+        {
+            Self::macos_sdk(zigbuild_config).context("Failed to find a macOS SDK")?
+        } else {
+            None
+        };
+        if let Some(sdk) = &macos_sdk
+            && Self::macos_sdk_root().as_ref() != Some(&sdk.path)
+        {
+            // Pass the discovered SDK down to build scripts and the `zig cc` wrappers
+            cmd.set_if_missing("SDKROOT", &sdk.path);
+        }
+        // Have rustc target the OS version of the suffix too, so that objects agree.
+        // The variable applies to every target of the OS, it's left alone when their
+        // suffixes disagree and only the `zig cc` target carries the version then.
+        let mut deployment_targets: BTreeMap<&str, Option<String>> = BTreeMap::new();
+        for raw_target in raw_targets {
+            if let (rust_target, Some(version)) = split_target_version(raw_target)?
+                && let Some(var) = deployment_target_env(rust_target)
+            {
+                let version = version.with_minor().to_string();
+                deployment_targets
+                    .entry(var)
+                    .and_modify(|existing| {
+                        if existing.as_ref() != Some(&version) {
+                            *existing = None;
+                        }
+                    })
+                    .or_insert(Some(version));
+            }
+        }
+        for (var, version) in deployment_targets {
+            if let Some(version) = version {
+                cmd.set_if_missing(var, version);
+            }
+        }
+        // `WINDRES` isn't target specific, only set it when it can't pick the wrong target
+        let windows_gnu_targets = rust_targets
+            .iter()
+            .filter(|target| target.contains("windows-gnu"))
+            .count();
         for (parsed_target, raw_target) in rust_targets.iter().zip(raw_targets) {
             let env_target = parsed_target.replace('-', "_");
             let zig_wrapper = prepare_zig_linker(raw_target, &cargo_config)?;
@@ -920,49 +1219,74 @@ impl Zig {
             if is_mingw_shell() {
                 let zig_cc = zig_wrapper.cc.to_slash_lossy();
                 let zig_cxx = zig_wrapper.cxx.to_slash_lossy();
-                Self::add_env_if_missing(cmd, format!("CC_{env_target}"), &*zig_cc);
-                Self::add_env_if_missing(cmd, format!("CXX_{env_target}"), &*zig_cxx);
+                cmd.set_if_missing(format!("CC_{env_target}"), &*zig_cc);
+                cmd.set_if_missing(format!("CXX_{env_target}"), &*zig_cxx);
                 if !parsed_target.contains("wasm") {
-                    Self::add_env_if_missing(
-                        cmd,
+                    cmd.set_if_missing(
                         format!("CARGO_TARGET_{}_LINKER", env_target.to_uppercase()),
                         &*zig_cc,
                     );
                 }
             } else {
-                Self::add_env_if_missing(cmd, format!("CC_{env_target}"), &zig_wrapper.cc);
-                Self::add_env_if_missing(cmd, format!("CXX_{env_target}"), &zig_wrapper.cxx);
+                cmd.set_if_missing(format!("CC_{env_target}"), &zig_wrapper.cc);
+                cmd.set_if_missing(format!("CXX_{env_target}"), &zig_wrapper.cxx);
                 if !parsed_target.contains("wasm") {
-                    Self::add_env_if_missing(
-                        cmd,
+                    cmd.set_if_missing(
                         format!("CARGO_TARGET_{}_LINKER", env_target.to_uppercase()),
                         &zig_wrapper.cc,
                     );
                 }
             }
 
-            Self::add_env_if_missing(cmd, format!("RANLIB_{env_target}"), &zig_wrapper.ranlib);
+            cmd.set_if_missing(format!("RANLIB_{env_target}"), &zig_wrapper.ranlib);
             // Only setup AR when explicitly asked to
             // because it need special executable name handling, see src/bin/cargo-zigbuild.rs
             if enable_zig_ar {
                 if parsed_target.contains("msvc") {
-                    Self::add_env_if_missing(cmd, format!("AR_{env_target}"), &zig_wrapper.lib);
+                    cmd.set_if_missing(format!("AR_{env_target}"), &zig_wrapper.lib);
                 } else {
-                    Self::add_env_if_missing(cmd, format!("AR_{env_target}"), &zig_wrapper.ar);
+                    cmd.set_if_missing(format!("AR_{env_target}"), &zig_wrapper.ar);
+                }
+            }
+
+            if let Some(rc) = &zig_wrapper.rc {
+                cmd.set_if_missing(format!("RC_{env_target}"), rc);
+                if parsed_target.contains("windows-gnu") && windows_gnu_targets == 1 {
+                    cmd.set_if_missing("WINDRES", rc);
                 }
             }
 
             Self::setup_os_deps(manifest_path, release, cargo)?;
 
+            let xwin_sdk = if parsed_target.contains("windows-msvc") {
+                let xwin_sdk = XwinSdk::from_config(zigbuild_config)?;
+                if let Some(xwin_sdk) = &xwin_sdk {
+                    let arch = parsed_target.split('-').next().unwrap_or_default();
+                    check_sdk_dir(
+                        xwin_sdk.lib_dirs(arch).map(drop),
+                        "xwin directory",
+                        xwin_sdk.root(),
+                        parsed_target,
+                    )?;
+                }
+                xwin_sdk
+            } else {
+                None
+            };
+
             let cmake_toolchain_file_env = format!("CMAKE_TOOLCHAIN_FILE_{env_target}");
             if env::var_os(&cmake_toolchain_file_env).is_none()
                 && env::var_os(format!("CMAKE_TOOLCHAIN_FILE_{parsed_target}")).is_none()
                 && env::var_os("TARGET_CMAKE_TOOLCHAIN_FILE").is_none()
                 && env::var_os("CMAKE_TOOLCHAIN_FILE").is_none()
-                && let Ok(cmake_toolchain_file) =
-                    Self::setup_cmake_toolchain(parsed_target, &zig_wrapper, enable_zig_ar)
+                && let Ok(cmake_toolchain_file) = Self::setup_cmake_toolchain(
+                    parsed_target,
+                    &zig_wrapper,
+                    enable_zig_ar,
+                    xwin_sdk.as_ref(),
+                )
             {
-                cmd.env(cmake_toolchain_file_env, cmake_toolchain_file);
+                cmd.set(cmake_toolchain_file_env, cmake_toolchain_file);
             }
 
             // On Windows, cmake defaults to the Visual Studio generator which ignores
@@ -973,11 +1297,11 @@ impl Zig {
                 && env::var_os("CMAKE_GENERATOR").is_none()
                 && which::which("ninja").is_ok()
             {
-                cmd.env("CMAKE_GENERATOR", "Ninja");
+                cmd.set("CMAKE_GENERATOR", "Ninja");
             }
 
             if raw_target.contains("windows-gnu") {
-                cmd.env("WINAPI_NO_BUNDLED_LIBRARIES", "1");
+                cmd.set("WINAPI_NO_BUNDLED_LIBRARIES", "1");
                 // Add the cache directory to PATH so rustc can find architecture-specific dlltool
                 // (e.g., x86_64-w64-mingw32-dlltool), but only if no system dlltool exists
                 // If system mingw-w64 dlltool exists, prefer it over zig's dlltool
@@ -985,21 +1309,40 @@ impl Zig {
                 if !has_system_dlltool(&triple.architecture) {
                     // zig_wrapper.ar lives in the per-exe wrapper dir
                     let wrapper_dir = zig_wrapper.ar.parent().unwrap();
-                    let existing_path = env::var_os("PATH").unwrap_or_default();
+                    let existing_path = cmd
+                        .get("PATH")
+                        .map(OsStr::to_os_string)
+                        .or_else(|| env::var_os("PATH"))
+                        .unwrap_or_default();
                     let paths = std::iter::once(wrapper_dir.to_path_buf())
                         .chain(env::split_paths(&existing_path));
                     if let Ok(new_path) = env::join_paths(paths) {
-                        cmd.env("PATH", new_path);
+                        cmd.set("PATH", new_path);
                     }
                 }
             }
 
-            if raw_target.contains("apple-darwin")
-                && let Some(sdkroot) = Self::macos_sdk_root()
+            if let Some(apple_tools) = &zig_wrapper.apple_tools {
+                let existing_path = cmd
+                    .get("PATH")
+                    .map(OsStr::to_os_string)
+                    .or_else(|| env::var_os("PATH"))
+                    .unwrap_or_default();
+                let paths =
+                    std::iter::once(apple_tools.clone()).chain(env::split_paths(&existing_path));
+                if let Ok(new_path) = env::join_paths(paths) {
+                    cmd.set("PATH", new_path);
+                }
+            }
+
+            let target_sdk = macos_sdk
+                .as_ref()
+                .filter(|_| raw_target.contains("apple-darwin"));
+            if let Some(sdk) = target_sdk
                 && env::var_os("PKG_CONFIG_SYSROOT_DIR").is_none()
             {
                 // Set PKG_CONFIG_SYSROOT_DIR for pkg-config crate
-                cmd.env("PKG_CONFIG_SYSROOT_DIR", sdkroot);
+                cmd.set("PKG_CONFIG_SYSROOT_DIR", &sdk.path);
             }
 
             // Enable unstable `target-applies-to-host` option automatically
@@ -1008,19 +1351,42 @@ impl Zig {
                 if !matches!(rustc_meta.channel, rustc_version::Channel::Nightly) {
                     // Hack to use the unstable feature on stable Rust
                     // https://github.com/rust-lang/cargo/pull/9753#issuecomment-1022919343
-                    cmd.env("__CARGO_TEST_CHANNEL_OVERRIDE_DO_NOT_USE_THIS", "nightly");
+                    cmd.set("__CARGO_TEST_CHANNEL_OVERRIDE_DO_NOT_USE_THIS", "nightly");
                 }
-                cmd.env("CARGO_UNSTABLE_TARGET_APPLIES_TO_HOST", "true");
-                cmd.env("CARGO_TARGET_APPLIES_TO_HOST", "false");
+                cmd.set("CARGO_UNSTABLE_TARGET_APPLIES_TO_HOST", "true");
+                cmd.set("CARGO_TARGET_APPLIES_TO_HOST", "false");
             }
 
+            let stub_sdk = match &zigbuild_config.apple_sdk_stubs {
+                Some(stubs)
+                    if parsed_target.contains("apple-darwin")
+                        || parsed_target.ends_with("macabi") =>
+                {
+                    let stub_sdk = StubSdk::new(stubs)?;
+                    if let Some(arch) = sdk::apple_arch(parsed_target) {
+                        check_sdk_dir(
+                            stub_sdk.validate(arch),
+                            "stub SDK directory",
+                            stubs,
+                            parsed_target,
+                        )?;
+                    }
+                    Some(stub_sdk)
+                }
+                _ => None,
+            };
+
             // Pass options used by zig cc down to bindgen, if possible
-            let mut options = Self::collect_zig_cc_options(&zig_wrapper, raw_target)
-                .context("Failed to collect `zig cc` options")?;
-            if raw_target.contains("apple-darwin") {
-                // everyone seems to miss `#import <TargetConditionals.h>`...
-                options.push("-DTARGET_OS_IPHONE=0".to_string());
-            }
+            let mut options = Self::collect_zig_cc_options(
+                &zig_wrapper,
+                raw_target,
+                stub_sdk.as_ref(),
+                xwin_sdk.as_ref(),
+                target_sdk.map(|sdk| sdk.path.as_path()),
+            )
+            .context("Failed to collect `zig cc` options")?;
+            // everyone seems to miss `#import <TargetConditionals.h>`...
+            options.extend(TargetInfo::new(Some(raw_target)).target_os_defines());
             let escaped_options = shell_words::join(options.iter().map(|s| &s[..]));
             let bindgen_env = "BINDGEN_EXTRA_CLANG_ARGS";
             let fallback_value = env::var(bindgen_env);
@@ -1035,19 +1401,25 @@ impl Zig {
                         value.push(' ');
                     }
                     value.push_str(&escaped_options);
-                    unsafe { env::set_var(name, value) };
+                    cmd.set(name, value);
                 } else {
-                    unsafe { env::set_var(name, escaped_options.clone()) };
+                    cmd.set(name, escaped_options.clone());
                 }
             }
         }
-        Ok(())
+        Ok(cmd)
     }
 
     /// Collects compiler options used by `zig cc` for given target.
     /// Used for the case where `zig cc` cannot be used but underlying options should be retained,
     /// for example, as in bindgen (which requires libclang.so and thus is independent from zig).
-    fn collect_zig_cc_options(zig_wrapper: &ZigWrapper, raw_target: &str) -> Result<Vec<String>> {
+    fn collect_zig_cc_options(
+        zig_wrapper: &ZigWrapper,
+        raw_target: &str,
+        stub_sdk: Option<&StubSdk>,
+        xwin_sdk: Option<&XwinSdk>,
+        sdkroot: Option<&Path>,
+    ) -> Result<Vec<String>> {
         #[derive(Debug, PartialEq, Eq)]
         enum Kind {
             Normal,
@@ -1064,6 +1436,7 @@ impl Zig {
             program: &Path,
             ext: &str,
             raw_target: &str,
+            sdkroot: Option<&Path>,
         ) -> Result<PerLanguageOptions> {
             // We can't use `-x c` or `-x c++` because pre-0.11 Zig doesn't handle them
             let empty_file_path = cache_dir().join(format!(".intentionally-empty-file.{ext}"));
@@ -1072,6 +1445,8 @@ impl Zig {
             }
 
             let output = Command::new(program)
+                // The SDK may have been discovered rather than set in the environment
+                .envs(sdkroot.map(|sdkroot| ("SDKROOT", sdkroot)))
                 .arg("-E")
                 .arg(&empty_file_path)
                 .arg("-v")
@@ -1134,8 +1509,8 @@ impl Zig {
             })
         }
 
-        let c_opts = collect_per_language_options(&zig_wrapper.cc, "c", raw_target)?;
-        let cpp_opts = collect_per_language_options(&zig_wrapper.cxx, "cpp", raw_target)?;
+        let c_opts = collect_per_language_options(&zig_wrapper.cc, "c", raw_target, sdkroot)?;
+        let cpp_opts = collect_per_language_options(&zig_wrapper.cxx, "cpp", raw_target, sdkroot)?;
 
         // Ensure that `c_opts` and `cpp_opts` are almost identical in the way we expect.
         if c_opts.glibc_minor_ver != cpp_opts.glibc_minor_ver {
@@ -1145,7 +1520,7 @@ impl Zig {
                 cpp_opts.glibc_minor_ver,
             );
         }
-        let c_paths = c_opts.include_paths;
+        let mut c_paths = c_opts.include_paths;
         let mut cpp_paths = cpp_opts.include_paths;
         let cpp_pre_len = cpp_paths
             .iter()
@@ -1272,6 +1647,29 @@ impl Zig {
             args.push(path);
         }
 
+        // Headers shipped with the stub SDK, unless `zig cc` already reported them
+        if let Some(stub_sdk) = stub_sdk {
+            for (kind, dir) in [
+                (Kind::Normal, stub_sdk.include_dir()),
+                (Kind::Framework, stub_sdk.frameworks_dir()),
+            ] {
+                let dir = dir.display().to_string();
+                if Path::new(&dir).is_dir() && !c_paths.iter().any(|(_, path)| *path == dir) {
+                    c_paths.push((kind, dir));
+                }
+            }
+        }
+
+        // Headers of the Windows SDK, `zig cc` only reports them when it runs with `ZIG_LIBC`
+        if let Some(xwin_sdk) = xwin_sdk {
+            for dir in xwin_sdk.include_dirs() {
+                let dir = dir.display().to_string();
+                if Path::new(&dir).is_dir() && !c_paths.iter().any(|(_, path)| *path == dir) {
+                    c_paths.push((Kind::Normal, dir));
+                }
+            }
+        }
+
         for (kind, path) in c_paths {
             match kind {
                 Kind::Normal => {
@@ -1358,17 +1756,6 @@ impl Zig {
                         fs::write(arm_features_h, ARM_FEATURES_H)?;
                     }
                 }
-            } else if target.contains("windows-gnu")
-                && let Ok(lib_dir) = Zig::lib_dir()
-            {
-                let lib_common = lib_dir.join("libc").join("mingw").join("lib-common");
-                let synchronization_def = lib_common.join("synchronization.def");
-                if !synchronization_def.is_file() {
-                    let api_ms_win_core_synch_l1_2_0_def =
-                        lib_common.join("api-ms-win-core-synch-l1-2-0.def");
-                    // Ignore error
-                    fs::copy(api_ms_win_core_synch_l1_2_0_def, synchronization_def).ok();
-                }
             }
         }
         Ok(())
@@ -1378,6 +1765,7 @@ impl Zig {
         target: &str,
         zig_wrapper: &ZigWrapper,
         enable_zig_ar: bool,
+        xwin_sdk: Option<&XwinSdk>,
     ) -> Result<PathBuf> {
         // Place cmake toolchain files alongside the other wrappers in the
         // per-exe directory to avoid races between parallel builds.
@@ -1392,6 +1780,15 @@ impl Zig {
         let (system_name, system_processor) = match (os.as_str(), arch.as_str()) {
             ("darwin", "x86_64") => ("Darwin", "x86_64"),
             ("darwin", "aarch64") => ("Darwin", "arm64"),
+            ("ios" | "tvos" | "watchos" | "visionos" | "xros", arch) => {
+                let system_name = match os.as_str() {
+                    "ios" => "iOS",
+                    "tvos" => "tvOS",
+                    "watchos" => "watchOS",
+                    _ => "visionOS",
+                };
+                (system_name, sdk::apple_arch(target).unwrap_or(arch))
+            }
             ("linux", arch) => {
                 let cmake_arch = match arch {
                     "powerpc" => "ppc",
@@ -1427,10 +1824,12 @@ set(CMAKE_CXX_LINKER_DEPFILE_SUPPORTED FALSE)"#,
                 zig_wrapper.ar.to_slash_lossy()
             ));
         }
+        if let Some(rc) = &zig_wrapper.rc {
+            content.push_str(&format!("\nset(CMAKE_RC_COMPILER {})", rc.to_slash_lossy()));
+        }
         // When cross-compiling to Darwin from a non-macOS host, CMake requires
         // install_name_tool and otool which don't exist on Linux/Windows.
-        // Provide our own install_name_tool implementation via symlink wrapper,
-        // and a no-op script for otool (not needed for builds) if no system otool exists.
+        // Provide our own install_name_tool and otool implementations via symlink wrappers.
         if system_name == "Darwin" && !cfg!(target_os = "macos") {
             let exe_ext = if cfg!(windows) { ".exe" } else { "" };
             let install_name_tool = wrapper_dir.join(format!("install_name_tool{exe_ext}"));
@@ -1440,13 +1839,31 @@ set(CMAKE_CXX_LINKER_DEPFILE_SUPPORTED FALSE)"#,
                 install_name_tool.to_slash_lossy()
             ));
 
-            if which::which("otool").is_err() {
-                let script_ext = if cfg!(windows) { "bat" } else { "sh" };
-                let otool = cmake.join(format!("otool.{script_ext}"));
-                write_noop_script(&otool)?;
-                content.push_str(&format!("\nset(CMAKE_OTOOL {})", otool.to_slash_lossy()));
+            let otool = wrapper_dir.join(format!("otool{exe_ext}"));
+            symlink_wrapper(&otool)?;
+            content.push_str(&format!("\nset(CMAKE_OTOOL {})", otool.to_slash_lossy()));
+
+            if cfg!(feature = "universal2") {
+                let lipo = wrapper_dir.join(format!("lipo{exe_ext}"));
+                symlink_wrapper(&lipo)?;
+                content.push_str(&format!("\nset(CMAKE_LIPO {})", lipo.to_slash_lossy()));
             }
         }
+        // Let `find_path` and `find_library` look into the Windows SDK
+        if let Some(xwin_sdk) = xwin_sdk {
+            let quote = |dirs: Vec<PathBuf>| {
+                dirs.iter()
+                    .map(|dir| format!("\"{}\"", dir.to_slash_lossy()))
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            };
+            content.push_str(&format!(
+                "\nset(CMAKE_FIND_ROOT_PATH {})\nset(CMAKE_SYSTEM_INCLUDE_PATH {})\nset(CMAKE_SYSTEM_LIBRARY_PATH {})",
+                quote(vec![xwin_sdk.root().to_path_buf()]),
+                quote(xwin_sdk.include_dirs()),
+                quote(xwin_sdk.lib_dirs(&arch)?),
+            ));
+        }
         // Prevent cmake from searching the host system's include and library paths,
         // which can conflict with zig's bundled headers (e.g. __COLD in sys/cdefs.h).
         // See https://github.com/rust-cross/cargo-zigbuild/issues/268
@@ -1461,37 +1878,91 @@ set(CMAKE_FIND_ROOT_PATH_MODE_PACKAGE ONLY)"#,
         Ok(toolchain_file)
     }
 
+    /// `SDKROOT` if set, otherwise the macOS SDK reported by `xcrun`
+    pub(crate) fn macos_sdk_root() -> Option<PathBuf> {
+        match env::var_os("SDKROOT") {
+            Some(sdkroot) if !sdkroot.is_empty() => Some(sdkroot.into()),
+            _ => Self::xcrun_sdk_path("macosx"),
+        }
+    }
+
+    /// The SDK for a `zig cc` target, the macOS SDK for targets that aren't Apple's
+    fn target_sdk_root(target_info: &TargetInfo, config: &ZigbuildConfig) -> Option<PathBuf> {
+        match target_info.xcrun_sdk() {
+            Some("macosx") | None => Self::macos_sdk_root(),
+            Some(sdk_name) => Self::apple_sdk_root(sdk_name, config),
+        }
+    }
+
+    /// The path of an SDK like `iphoneos`: `SDKROOT_<sdk>`, the `apple-sdk-roots` setting,
+    /// `SDKROOT` if it is an SDK of that platform, otherwise the SDK reported by `xcrun`
+    ///
+    /// `SDKROOT` is usually the macOS SDK when macOS targets are built too.
+    fn apple_sdk_root(sdk_name: &str, config: &ZigbuildConfig) -> Option<PathBuf> {
+        match env::var_os(format!("SDKROOT_{sdk_name}")) {
+            Some(sdkroot) if !sdkroot.is_empty() => return Some(sdkroot.into()),
+            _ => {}
+        }
+        if let Some(sdkroot) = config.apple_sdk_roots.get(sdk_name) {
+            return Some(sdkroot.clone());
+        }
+        if let Some(sdkroot) = env::var_os("SDKROOT").filter(|sdkroot| !sdkroot.is_empty())
+            && sdk::is_sdk_of(Path::new(&sdkroot), sdk_name)
+        {
+            return Some(sdkroot.into());
+        }
+        Self::xcrun_sdk_path(sdk_name)
+    }
+
+    /// The path of an SDK like `iphoneos` reported by `xcrun`, cached per SDK
     #[cfg(target_os = "macos")]
-    fn macos_sdk_root() -> Option<PathBuf> {
-        static SDK_ROOT: OnceLock<Option<PathBuf>> = OnceLock::new();
-
-        SDK_ROOT
-            .get_or_init(|| match env::var_os("SDKROOT") {
-                Some(sdkroot) if !sdkroot.is_empty() => Some(sdkroot.into()),
-                _ => {
-                    let output = Command::new("xcrun")
-                        .args(["--sdk", "macosx", "--show-sdk-path"])
-                        .output()
-                        .ok()?;
-                    if output.status.success() {
-                        let stdout = String::from_utf8(output.stdout).ok()?;
-                        let stdout = stdout.trim();
-                        if !stdout.is_empty() {
-                            return Some(stdout.into());
-                        }
+    fn xcrun_sdk_path(sdk_name: &str) -> Option<PathBuf> {
+        use std::collections::HashMap;
+        use std::sync::Mutex;
+
+        static SDK_PATHS: OnceLock<Mutex<HashMap<String, Option<PathBuf>>>> = OnceLock::new();
+
+        let mut sdk_paths = SDK_PATHS.get_or_init(Default::default).lock().unwrap();
+        sdk_paths
+            .entry(sdk_name.to_string())
+            .or_insert_with(|| {
+                let output = Command::new("xcrun")
+                    .args(["--sdk", sdk_name, "--show-sdk-path"])
+                    .output()
+                    .ok()?;
+                if output.status.success() {
+                    let stdout = String::from_utf8(output.stdout).ok()?;
+                    let stdout = stdout.trim();
+                    if !stdout.is_empty() {
+                        return Some(stdout.into());
                     }
-                    None
                 }
+                None
             })
             .clone()
     }
 
+    /// There's no `xcrun` on other hosts
     #[cfg(not(target_os = "macos"))]
-    fn macos_sdk_root() -> Option<PathBuf> {
-        match env::var_os("SDKROOT") {
-            Some(sdkroot) if !sdkroot.is_empty() => Some(sdkroot.into()),
-            _ => None,
-        }
+    fn xcrun_sdk_path(_sdk_name: &str) -> Option<PathBuf> {
+        None
+    }
+
+    /// Resolve the macOS SDK: `SDKROOT` if set, the SDK `xcrun` reports on macOS,
+    /// otherwise the newest SDK matching the configured version in the search paths
+    pub(crate) fn macos_sdk(config: &ZigbuildConfig) -> Result<Option<MacosSdk>> {
+        if env::var_os("SDKROOT").is_some_and(|sdkroot| !sdkroot.is_empty())
+            || (cfg!(target_os = "macos") && config.macos_sdk_version.is_none())
+        {
+            return Ok(Self::macos_sdk_root().map(MacosSdk::from_sdkroot));
+        }
+        let mut search_paths = config.macos_sdk_search_paths.clone();
+        search_paths.extend(sdk::default_search_paths());
+        sdk::find_macos_sdk(
+            &search_paths,
+            config.macos_sdk_version.as_deref(),
+            !config.macos_sdk_search_paths.is_empty(),
+        )
     }
 }
 
@@ -1503,35 +1974,6 @@ fn write_file(path: &Path, content: &str) -> Result<(), anyhow::Error> {
     Ok(())
 }
 
-/// Write a no-op shell/batch script for use as a placeholder tool.
-/// Used for macOS-specific tools (install_name_tool, otool) when cross-compiling
-/// to Darwin from non-macOS hosts.
-#[cfg(target_family = "unix")]
-fn write_noop_script(path: &Path) -> Result<()> {
-    let content = "#!/bin/sh\nexit 0\n";
-    let existing = fs::read_to_string(path).unwrap_or_default();
-    if existing != content {
-        OpenOptions::new()
-            .create(true)
-            .write(true)
-            .truncate(true)
-            .mode(0o700)
-            .open(path)?
-            .write_all(content.as_bytes())?;
-    }
-    Ok(())
-}
-
-#[cfg(not(target_family = "unix"))]
-fn write_noop_script(path: &Path) -> Result<()> {
-    let content = "@echo off\r\nexit /b 0\r\n";
-    let existing = fs::read_to_string(path).unwrap_or_default();
-    if existing != content {
-        fs::write(path, content)?;
-    }
-    Ok(())
-}
-
 fn write_tbd_files(deps_dir: &Path) -> Result<(), anyhow::Error> {
     write_file(&deps_dir.join("libiconv.tbd"), LIBICONV_TBD)?;
     write_file(&deps_dir.join("libcharset.1.tbd"), LIBCHARSET_TBD)?;
@@ -1539,7 +1981,7 @@ fn write_tbd_files(deps_dir: &Path) -> Result<(), anyhow::Error> {
     Ok(())
 }
 
-fn cache_dir() -> PathBuf {
+pub(crate) fn cache_dir() -> PathBuf {
     env::var("CARGO_ZIGBUILD_CACHE_DIR")
         .ok()
         .map(|s| s.into())
@@ -1563,6 +2005,82 @@ pub struct ZigWrapper {
     pub ar: PathBuf,
     pub ranlib: PathBuf,
     pub lib: PathBuf,
+    /// Resource compiler for Windows targets, `windres` for windows-gnu and `rc` for windows-msvc
+    pub rc: Option<PathBuf>,
+    /// Directory with our Apple binary tools for non-macOS hosts, to be added to `PATH`,
+    /// `None` if every tool is installed
+    pub apple_tools: Option<PathBuf>,
+}
+
+/// Environment variables computed by [`Zig::command_env`]
+#[derive(Debug, Clone, Default)]
+pub struct CommandEnv {
+    vars: Vec<CommandEnvVar>,
+}
+
+#[derive(Debug, Clone)]
+struct CommandEnvVar {
+    name: String,
+    value: OsString,
+    /// Whether to override a variable already set on the command
+    overwrite: bool,
+}
+
+impl CommandEnv {
+    /// Set a variable, replacing any previous value
+    pub(crate) fn set(&mut self, name: impl Into<String>, value: impl AsRef<OsStr>) {
+        let name = name.into();
+        let value = value.as_ref().to_os_string();
+        match self.vars.iter_mut().find(|var| var.name == name) {
+            Some(var) => {
+                var.value = value;
+                var.overwrite = true;
+            }
+            None => self.vars.push(CommandEnvVar {
+                name,
+                value,
+                overwrite: true,
+            }),
+        }
+    }
+
+    /// Set a variable unless it's already set here or in the process environment
+    fn set_if_missing(&mut self, name: impl Into<String>, value: impl AsRef<OsStr>) {
+        let name = name.into();
+        if self.get(&name).is_none() && env::var_os(&name).is_none() {
+            self.vars.push(CommandEnvVar {
+                name,
+                value: value.as_ref().to_os_string(),
+                overwrite: false,
+            });
+        }
+    }
+
+    /// Get the value of a variable
+    pub fn get(&self, name: &str) -> Option<&OsStr> {
+        self.vars
+            .iter()
+            .find(|var| var.name == name)
+            .map(|var| var.value.as_os_str())
+    }
+
+    /// Iterate over the variables in the order they were set
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &OsStr)> {
+        self.vars
+            .iter()
+            .map(|var| (var.name.as_str(), var.value.as_os_str()))
+    }
+
+    /// Apply the variables to `cmd`
+    pub fn apply(&self, cmd: &mut Command) {
+        for var in &self.vars {
+            if var.overwrite {
+                cmd.env(&var.name, &var.value);
+            } else {
+                Zig::add_env_if_missing(cmd, &var.name, &var.value);
+            }
+        }
+    }
 }
 
 #[derive(Debug, Clone, Default, PartialEq)]
@@ -1602,6 +2120,31 @@ impl TargetFlags {
     }
 }
 
+/// Whether a target environment uses glibc
+pub(crate) fn is_glibc_env(environment: Environment) -> bool {
+    matches!(
+        environment,
+        Environment::Gnu
+            | Environment::Gnuspe
+            | Environment::Gnux32
+            | Environment::Gnueabi
+            | Environment::Gnuabi64
+            | Environment::GnuIlp32
+            | Environment::Gnueabihf
+    )
+}
+
+/// Whether a target environment uses musl
+fn is_musl_env(environment: Environment) -> bool {
+    matches!(
+        environment,
+        Environment::Musl
+            | Environment::Muslabi64
+            | Environment::Musleabi
+            | Environment::Musleabihf
+    )
+}
+
 /// Prepare wrapper scripts for `zig cc` and `zig c++` and returns their paths
 ///
 /// We want to use `zig cc` as linker and c compiler. We want to call `python -m ziglang cc`, but
@@ -1615,24 +2158,7 @@ pub fn prepare_zig_linker(
     target: &str,
     cargo_config: &cargo_config2::Config,
 ) -> Result<ZigWrapper> {
-    let (rust_target, abi_suffix) = target.split_once('.').unwrap_or((target, ""));
-    let abi_suffix = if abi_suffix.is_empty() {
-        String::new()
-    } else {
-        if abi_suffix
-            .split_once('.')
-            .filter(|(x, y)| {
-                !x.is_empty()
-                    && x.chars().all(|c| c.is_ascii_digit())
-                    && !y.is_empty()
-                    && y.chars().all(|c| c.is_ascii_digit())
-            })
-            .is_none()
-        {
-            bail!("Malformed zig target abi suffix.")
-        }
-        format!(".{abi_suffix}")
-    };
+    let (rust_target, target_version) = split_target_version(target)?;
     let triple: Triple = rust_target
         .parse()
         .with_context(|| format!("Unsupported Rust target '{rust_target}'"))?;
@@ -1643,6 +2169,21 @@ pub fn prepare_zig_linker(
         (_, Environment::GnuLlvm) => Environment::Gnu,
         (_, environment) => environment,
     };
+    let supports_version = match triple.operating_system {
+        OperatingSystem::Linux => is_glibc_env(target_env) || is_musl_env(target_env),
+        OperatingSystem::MacOSX { .. } | OperatingSystem::Darwin(_) | OperatingSystem::Freebsd => {
+            true
+        }
+        _ => false,
+    };
+    if let Some(version) = target_version
+        && !supports_version
+    {
+        bail!(
+            "Version suffix `.{version}` in `{target}` is not supported, \
+             only Linux glibc and musl, macOS and FreeBSD targets accept a version suffix"
+        );
+    }
     let file_ext = if cfg!(windows) { "bat" } else { "sh" };
     let file_target = target.trim_end_matches('.');
 
@@ -1734,19 +2275,43 @@ pub fn prepare_zig_linker(
                 zig_target_env = "ohoseabi".to_string();
             }
 
+            let abi_suffix = match target_version {
+                // zig always links its bundled musl, the suffix is accepted but not passed on
+                Some(_) if is_musl_env(target_env) => String::new(),
+                Some(version) => {
+                    if let Some(shipped) = Zig::lib_dir()
+                        .ok()
+                        .and_then(|lib_dir| zig_glibc_versions(&lib_dir))
+                    {
+                        check_glibc_version(target, version, &shipped)?;
+                    }
+                    format!(".{version}")
+                }
+                None => String::new(),
+            };
             cc_args.push("-target".to_string());
             cc_args.push(format!("{zig_arch}-linux-{zig_target_env}{abi_suffix}"));
         }
         OperatingSystem::MacOSX { .. } | OperatingSystem::Darwin(_) => {
             let zig_version = Zig::zig_version()?;
+            // The version suffix is the minimum macOS version, which zig takes as the OS version,
+            // otherwise use `MACOSX_DEPLOYMENT_TARGET` like rustc so that C and Rust objects agree
+            let os_version = match target_version {
+                Some(version) => {
+                    check_macos_version(target, &arch, version)?;
+                    Some(version.with_minor())
+                }
+                None => deployment_target(rust_target)?,
+            };
+            let os_suffix = os_version.map(|v| format!(".{v}")).unwrap_or_default();
             // Zig 0.10.0 switched macOS ABI to none
             // see https://github.com/ziglang/zig/pull/11684
             if zig_version > semver::Version::new(0, 9, 1) {
                 cc_args.push("-target".to_string());
-                cc_args.push(format!("{arch}-macos-none{abi_suffix}"));
+                cc_args.push(format!("{arch}-macos{os_suffix}-none"));
             } else {
                 cc_args.push("-target".to_string());
-                cc_args.push(format!("{arch}-macos-gnu{abi_suffix}"));
+                cc_args.push(format!("{arch}-macos{os_suffix}-gnu"));
             }
         }
         OperatingSystem::Windows => {
@@ -1762,25 +2327,56 @@ pub fn prepare_zig_linker(
                 arch => arch,
             };
             cc_args.push("-target".to_string());
-            cc_args.push(format!("{zig_arch}-windows-{target_env}{abi_suffix}"));
+            cc_args.push(format!("{zig_arch}-windows-{target_env}"));
         }
         OperatingSystem::Emscripten => {
             cc_args.push("-target".to_string());
-            cc_args.push(format!("{arch}-emscripten{abi_suffix}"));
+            cc_args.push(format!("{arch}-emscripten"));
         }
         OperatingSystem::Wasi => {
             cc_args.push("-target".to_string());
-            cc_args.push(format!("{arch}-wasi{abi_suffix}"));
+            cc_args.push(format!("{arch}-wasi"));
         }
         OperatingSystem::WasiP1 => {
             cc_args.push("-target".to_string());
-            cc_args.push(format!("{arch}-wasi.0.1.0{abi_suffix}"));
+            cc_args.push(format!("{arch}-wasi.0.1.0"));
         }
         OperatingSystem::IOS(_) if triple.environment == Environment::Macabi => {
             // Mac Catalyst (aarch64-apple-ios-macabi / x86_64-apple-ios-macabi)
-            // maps to zig's maccatalyst target
+            // maps to zig's maccatalyst target, whose OS version is the iOS version
+            let os_suffix = deployment_target(rust_target)?
+                .map(|v| format!(".{v}"))
+                .unwrap_or_default();
             cc_args.push("-target".to_string());
-            cc_args.push(format!("{arch}-maccatalyst-none{abi_suffix}"));
+            cc_args.push(format!("{arch}-maccatalyst{os_suffix}-none"));
+        }
+        OperatingSystem::IOS(_)
+        | OperatingSystem::TvOS(_)
+        | OperatingSystem::WatchOS(_)
+        | OperatingSystem::VisionOS(_)
+        | OperatingSystem::XROS(_) => {
+            let zig_arch = match arch.as_str() {
+                "aarch64" | "x86_64" => arch.as_str(),
+                "i386" => "x86",
+                _ => bail!("unsupported target '{rust_target}'"),
+            };
+            let zig_os = match triple.operating_system {
+                OperatingSystem::IOS(_) => "ios",
+                OperatingSystem::TvOS(_) => "tvos",
+                OperatingSystem::WatchOS(_) => "watchos",
+                _ => "visionos",
+            };
+            // x86 targets like `x86_64-apple-ios` only exist as simulators
+            let zig_abi = if triple.environment == Environment::Sim || zig_arch != "aarch64" {
+                "simulator"
+            } else {
+                "none"
+            };
+            let os_suffix = deployment_target(rust_target)?
+                .map(|v| format!(".{v}"))
+                .unwrap_or_default();
+            cc_args.push("-target".to_string());
+            cc_args.push(format!("{zig_arch}-{zig_os}{os_suffix}-{zig_abi}"));
         }
         OperatingSystem::Freebsd => {
             let zig_arch = match arch.as_str() {
@@ -1794,8 +2390,12 @@ pub fn prepare_zig_linker(
                 }
                 arch => arch,
             };
+            // The version suffix pins the FreeBSD release, which zig takes as the OS version
+            let os_suffix = target_version
+                .map(|version| format!(".{}", version.with_minor()))
+                .unwrap_or_default();
             cc_args.push("-target".to_string());
-            cc_args.push(format!("{zig_arch}-freebsd"));
+            cc_args.push(format!("{zig_arch}-freebsd{os_suffix}"));
         }
         OperatingSystem::Openbsd => {
             cc_args.push("-target".to_string());
@@ -1806,7 +2406,7 @@ pub fn prepare_zig_linker(
                 || triple.architecture == Architecture::Wasm64
             {
                 cc_args.push("-target".to_string());
-                cc_args.push(format!("{arch}-freestanding{abi_suffix}"));
+                cc_args.push(format!("{arch}-freestanding"));
             } else {
                 bail!("unsupported target '{rust_target}'")
             }
@@ -1818,26 +2418,10 @@ pub fn prepare_zig_linker(
     fs::create_dir_all(&zig_linker_dir)?;
 
     if triple.operating_system == OperatingSystem::Linux {
-        if matches!(
-            triple.environment,
-            Environment::Gnu
-                | Environment::Gnuspe
-                | Environment::Gnux32
-                | Environment::Gnueabi
-                | Environment::Gnuabi64
-                | Environment::GnuIlp32
-                | Environment::Gnueabihf
-        ) {
-            let glibc_version = if abi_suffix.is_empty() {
-                (2, 17)
-            } else {
-                let mut parts = abi_suffix[1..].split('.');
-                let major: usize = parts.next().unwrap().parse()?;
-                let minor: usize = parts.next().unwrap().parse()?;
-                (major, minor)
-            };
+        if is_glibc_env(triple.environment) {
+            let glibc_version = target_version.unwrap_or(DEFAULT_GLIBC_VERSION);
             // See https://github.com/ziglang/zig/issues/9485
-            if glibc_version < (2, 28) {
+            if glibc_version.triple() < (2, 28, 0) {
                 use crate::linux::{FCNTL_H, FCNTL_MAP};
 
                 let zig_version = Zig::zig_version()?;
@@ -1858,13 +2442,7 @@ pub fn prepare_zig_linker(
                     cc_args.push(fcntl_h.display().to_string());
                 }
             }
-        } else if matches!(
-            triple.environment,
-            Environment::Musl
-                | Environment::Muslabi64
-                | Environment::Musleabi
-                | Environment::Musleabihf
-        ) {
+        } else if is_musl_env(triple.environment) {
             use crate::linux::MUSL_WEAK_SYMBOLS_MAPPING_SCRIPT;
 
             let zig_version = Zig::zig_version()?;
@@ -1933,15 +2511,76 @@ pub fn prepare_zig_linker(
         }
     }
 
+    // Resource compilers for build scripts and CMake, backed by `zig rc`
+    let rc = match (triple.operating_system, triple.environment) {
+        (OperatingSystem::Windows, Environment::Gnu | Environment::GnuLlvm) => {
+            Some(wrapper_dir.join(format!(
+                "{}{exe_ext}",
+                get_windres_name(&triple.architecture)
+            )))
+        }
+        (OperatingSystem::Windows, Environment::Msvc) => {
+            Some(wrapper_dir.join(format!("rc{exe_ext}")))
+        }
+        _ => None,
+    };
+    if let Some(rc) = &rc {
+        symlink_wrapper(rc)?;
+    }
+
+    // Apple's binary tools don't exist on other hosts, provide our own implementations
+    // of the missing ones for build scripts
+    let apple_tools = if matches!(triple.vendor, Vendor::Apple) && !cfg!(target_os = "macos") {
+        let apple_tools = wrapper_dir.join("apple-tools");
+        fs::create_dir_all(&apple_tools)?;
+        let mut provided = false;
+        for tool in APPLE_TOOLS {
+            let link = apple_tools.join(format!("{tool}{exe_ext}"));
+            // `PATH` may already contain our links when cargo-zigbuild runs nested
+            let installed = which::which_all(tool).is_ok_and(|mut paths| {
+                paths.any(|path| path.parent() != Some(apple_tools.as_path()))
+            });
+            if installed {
+                // Don't let a link from an earlier run shadow a tool installed since
+                if fs::symlink_metadata(&link).is_ok() {
+                    fs::remove_file(&link)?;
+                }
+            } else {
+                symlink_wrapper(&link)?;
+                provided = true;
+            }
+        }
+        provided.then_some(apple_tools)
+    } else {
+        None
+    };
+
     Ok(ZigWrapper {
         cc: zig_cc,
         cxx: zig_cxx,
         ar: zig_ar,
         ranlib: zig_ranlib,
         lib: zig_lib,
+        rc,
+        apple_tools,
     })
 }
 
+/// Fail early instead of at link time when an SDK directory from the config
+/// doesn't support `target`
+fn check_sdk_dir(result: Result<()>, kind: &str, root: &Path, target: &str) -> Result<()> {
+    result.with_context(|| format!("{kind} '{}' can't be used for {target}", root.display()))
+}
+
+/// Apple binary tools implemented by cargo-zigbuild, dispatched by program name
+const APPLE_TOOLS: &[&str] = &[
+    "install_name_tool",
+    "otool",
+    "codesign",
+    #[cfg(feature = "universal2")]
+    "lipo",
+];
+
 /// Resolve the current executable path, preferring the test override env var.
 fn resolve_current_exe() -> Result<PathBuf> {
     if let Ok(exe) = env::var("CARGO_BIN_EXE_cargo-zigbuild") {
@@ -2141,7 +2780,7 @@ fn zig_path() -> Result<PathBuf> {
 /// Get the dlltool executable name for the given architecture
 /// On Windows, rustc looks for "dlltool.exe"
 /// On non-Windows hosts, rustc looks for architecture-specific names
-fn get_dlltool_name(arch: &Architecture) -> &'static str {
+pub(crate) fn get_dlltool_name(arch: &Architecture) -> &'static str {
     if cfg!(windows) {
         "dlltool"
     } else {
@@ -2154,9 +2793,20 @@ fn get_dlltool_name(arch: &Architecture) -> &'static str {
     }
 }
 
+/// Name of the windres wrapper, prefixed with the mingw triple of the architecture
+/// like `embed-resource` and CMake expect when cross compiling
+pub(crate) fn get_windres_name(arch: &Architecture) -> &'static str {
+    match arch {
+        Architecture::X86_64 => "x86_64-w64-mingw32-windres",
+        Architecture::X86_32(_) => "i686-w64-mingw32-windres",
+        Architecture::Aarch64(_) => "aarch64-w64-mingw32-windres",
+        _ => "windres",
+    }
+}
+
 /// Check if a dlltool for the given architecture exists in PATH
 /// Returns true if found, false otherwise
-fn has_system_dlltool(arch: &Architecture) -> bool {
+pub(crate) fn has_system_dlltool(arch: &Architecture) -> bool {
     which::which(get_dlltool_name(arch)).is_ok()
 }
 
@@ -2272,6 +2922,7 @@ mod tests {
         let target_info = TargetInfo::new(target.map(|s| s.to_string()).as_ref());
         filter_linker_args(
             args.iter().map(|s| s.to_string()),
+            LinkerArgRules::builtin(),
             &rustc_ver,
             &zig_version,
             &target_info,
@@ -2293,6 +2944,7 @@ mod tests {
         let target_info = TargetInfo::new(target.map(|s| s.to_string()).as_ref());
         filter_linker_args(
             std::iter::once(arg.to_string()),
+            LinkerArgRules::builtin(),
             &rustc_ver,
             &zig_version,
             &target_info,
@@ -2482,6 +3134,117 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_is_link_command() {
+        let args = |args: &[&str]| args.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        assert!(is_link_command(&args(&["-o", "foo", "foo.o", "-lSystem"])));
+        assert!(!is_link_command(&args(&["-c", "foo.c", "-o", "foo.o"])));
+        assert!(!is_link_command(&args(&["-E", "foo.c"])));
+    }
+
+    #[test]
+    fn test_link_output() {
+        let tmp = tempfile::tempdir().unwrap();
+        let response_file = tmp.path().join("linker-arguments");
+        fs::write(&response_file, "foo.o\n-o\n/out/libfoo.dylib\n-dynamiclib").unwrap();
+        let args = vec![
+            "-target".to_string(),
+            "aarch64-macos".to_string(),
+            format!("@{}", response_file.display()),
+        ];
+        assert_eq!(link_output(&args), Some(PathBuf::from("/out/libfoo.dylib")));
+        let args = vec!["-o".to_string(), "foo".to_string()];
+        assert_eq!(link_output(&args), Some(PathBuf::from("foo")));
+        assert_eq!(link_output(&args[..1]), None);
+    }
+
+    #[test]
+    fn test_apple_target_info() {
+        let info = |target: &str| TargetInfo::new(Some(&target.to_string()));
+        assert_eq!(info("aarch64-apple-darwin").xcrun_sdk(), Some("macosx"));
+        assert_eq!(info("aarch64-macos.11.0-none").xcrun_sdk(), Some("macosx"));
+        assert_eq!(info("aarch64-maccatalyst-none").xcrun_sdk(), Some("macosx"));
+        assert_eq!(info("aarch64-ios-none").xcrun_sdk(), Some("iphoneos"));
+        assert_eq!(
+            info("aarch64-apple-ios-sim").xcrun_sdk(),
+            Some("iphonesimulator")
+        );
+        assert_eq!(
+            info("x86_64-apple-ios").xcrun_sdk(),
+            Some("iphonesimulator")
+        );
+        assert_eq!(
+            info("aarch64-tvos.17.0-simulator").xcrun_sdk(),
+            Some("appletvsimulator")
+        );
+        assert_eq!(info("aarch64-apple-watchos").xcrun_sdk(), Some("watchos"));
+        assert_eq!(info("aarch64-visionos-none").xcrun_sdk(), Some("xros"));
+        assert_eq!(info("x86_64-linux-gnu").xcrun_sdk(), None);
+
+        let defines = |target: &str| -> Vec<String> {
+            info(target)
+                .target_os_defines()
+                .into_iter()
+                .filter(|define| define.ends_with("=1"))
+                .collect()
+        };
+        assert_eq!(defines("x86_64-apple-darwin"), ["-DTARGET_OS_OSX=1"]);
+        assert_eq!(
+            defines("aarch64-ios.15.0-simulator"),
+            [
+                "-DTARGET_OS_IPHONE=1",
+                "-DTARGET_OS_IOS=1",
+                "-DTARGET_OS_SIMULATOR=1"
+            ]
+        );
+        assert_eq!(
+            defines("aarch64-apple-ios-macabi"),
+            [
+                "-DTARGET_OS_IPHONE=1",
+                "-DTARGET_OS_IOS=1",
+                "-DTARGET_OS_MACCATALYST=1"
+            ]
+        );
+        assert_eq!(
+            defines("x86_64-apple-ios-macabi"),
+            [
+                "-DTARGET_OS_IPHONE=1",
+                "-DTARGET_OS_IOS=1",
+                "-DTARGET_OS_MACCATALYST=1"
+            ]
+        );
+        assert_eq!(
+            defines("aarch64-apple-tvos"),
+            ["-DTARGET_OS_IPHONE=1", "-DTARGET_OS_TV=1"]
+        );
+        assert_eq!(
+            defines("aarch64-apple-visionos-sim"),
+            [
+                "-DTARGET_OS_IPHONE=1",
+                "-DTARGET_OS_VISION=1",
+                "-DTARGET_OS_SIMULATOR=1"
+            ]
+        );
+        assert_eq!(info("aarch64-apple-darwin").target_os_defines().len(), 8);
+        assert!(info("x86_64-linux-gnu").target_os_defines().is_empty());
+    }
+
+    #[test]
+    fn test_zig_target_os_version() {
+        assert_eq!(
+            zig_target_os_version("aarch64-macos.11.0-none"),
+            Some("11.0".parse().unwrap())
+        );
+        assert_eq!(
+            zig_target_os_version("x86_64-maccatalyst.13.1-none")
+                .unwrap()
+                .to_string(),
+            "13.1"
+        );
+        assert_eq!(zig_target_os_version("aarch64-macos-none"), None);
+        assert_eq!(zig_target_os_version("x86_64-linux-gnu.2.17"), None);
+    }
+
     #[test]
     fn test_filter_apple_args() {
         let darwin = Some("aarch64-apple-darwin");
@@ -2593,6 +3356,115 @@ mod tests {
         assert_eq!(result, vec!["-o", "output.so"]);
     }
 
+    #[test]
+    fn test_export_list() {
+        let args = |args: &[&str]| args.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        assert_eq!(
+            export_list(&args(&[
+                "-Wl,-exported_symbols_list",
+                "-Wl,/tmp/rustcXXX/list",
+                "-o",
+                "output.dylib",
+            ])),
+            Some(ExportList::ExportedSymbolsList(PathBuf::from(
+                "/tmp/rustcXXX/list"
+            )))
+        );
+        assert_eq!(
+            export_list(&args(&[
+                "-Wl,--dynamic-list,/tmp/rustcXXX/list",
+                "-o",
+                "output"
+            ])),
+            Some(ExportList::DynamicList(PathBuf::from("/tmp/rustcXXX/list")))
+        );
+        assert_eq!(
+            export_list(&args(&[
+                "-Wl,--gc-sections",
+                "-Wl,-z,relro",
+                "-o",
+                "output"
+            ])),
+            None
+        );
+
+        let dir = tempfile::tempdir().unwrap();
+        let response_file = dir.path().join("linker-arguments");
+        fs::write(
+            &response_file,
+            "-Wl,--dynamic-list\n-Wl,/tmp/rustcXXX/list\n-o\noutput",
+        )
+        .unwrap();
+        assert_eq!(
+            export_list(&[format!("@{}", response_file.display())]),
+            Some(ExportList::DynamicList(PathBuf::from("/tmp/rustcXXX/list")))
+        );
+    }
+
+    #[test]
+    fn test_filter_windows_msvc_link_args() {
+        let result = run_filter(
+            &[
+                "/NOLOGO",
+                "/tmp/rustcXXX/symbols.o",
+                "/tmp/target/deps/foo.foo.abc-cgu.0.rcgu.o",
+                "kernel32.lib",
+                "ws2_32.lib",
+                "/defaultlib:msvcrt",
+                "/NXCOMPAT",
+                "/LIBPATH:/tmp/sysroot/lib",
+                "/DLL",
+                "/DEF:/tmp/rustcXXX/lib.def",
+                "/IMPLIB:/tmp/target/foo.dll.lib",
+                "/SUBSYSTEM:windows,6.01",
+                "/OUT:/tmp/target/foo.dll",
+                "/OPT:REF,NOICF",
+                "/DEBUG",
+                "/PDBALTPATH:%_PDB%",
+                "/NATVIS:/tmp/sysroot/etc/intrinsic.natvis",
+                "/MANIFEST:NO",
+                "/EXPORT:foo_init",
+                "/INCLUDE:__foo_ctor",
+                "/NODEFAULTLIB:libcmt",
+                "/x:y",
+            ],
+            Some("x86_64-windows-msvc"),
+            (13, 0),
+        );
+        assert_eq!(
+            result,
+            vec![
+                "/tmp/rustcXXX/symbols.o",
+                "/tmp/target/deps/foo.foo.abc-cgu.0.rcgu.o",
+                "-lkernel32",
+                "-lws2_32",
+                "-lmsvcrt",
+                "-L/tmp/sysroot/lib",
+                "-shared",
+                "/tmp/rustcXXX/lib.def",
+                "-Wl,--out-implib,/tmp/target/foo.dll.lib",
+                "-Wl,--subsystem,windows",
+                "-o",
+                "/tmp/target/foo.dll",
+                "-Wl,--export=foo_init",
+                "-Wl,-u,__foo_ctor",
+                "/NODEFAULTLIB:libcmt",
+                "/x:y",
+            ]
+        );
+        // Not rewritten for other targets
+        let result = run_filter_one("/OUT:foo.exe", Some("x86_64-windows-gnu"), (13, 0));
+        assert_eq!(result, vec!["/OUT:foo.exe"]);
+    }
+
+    #[test]
+    fn test_unquote_msvc_arg() {
+        assert_eq!(unquote_msvc_arg("\"/NOLOGO\""), "/NOLOGO");
+        assert_eq!(unquote_msvc_arg("\"/OUT:a b.exe\"\r"), "/OUT:a b.exe");
+        assert_eq!(unquote_msvc_arg("\"say \\\"hi\\\"\""), "say \"hi\"");
+        assert_eq!(unquote_msvc_arg("kernel32.lib"), "kernel32.lib");
+    }
+
     #[test]
     fn test_filter_preserves_normal_args() {
         let result = run_filter(